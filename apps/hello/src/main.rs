@@ -6,6 +6,6 @@
 librs::main!(main);
 
 fn main() {
-    librs::syscall::register_server(None);
+    librs::syscall::register_server(None).unwrap();
     println!("Hello, world!");
 }
@@ -1,48 +1,28 @@
 #![feature(custom_test_frameworks)]
-#![test_runner(test_runner)]
+#![test_runner(librs::test::test_runner)]
 #![no_std]
 #![no_main]
 
-#[cfg(test)]
-fn test_runner(tests: &[&dyn Fn()]) {
-    tests.iter().for_each(|test| test());
-}
+librs::main!(main);
 
-#[panic_handler]
-fn panic(_info: &core::panic::PanicInfo) -> ! {
-    print_str("error: panicked\n");
-    loop {}
-}
+use core::time::Duration;
+use librs::{
+    signal::{self, Handler, Signal},
+    syscall,
+};
 
-// TODO: use the `uart` crate
-fn print_char(c: char) {
-    // TODO: capability based identity mapping
-    unsafe { (0x10000000 as *mut u8).write_volatile(c as _) }
-}
+fn main() {
+    syscall::register_server(None).unwrap();
+    println!("hello world");
 
-fn print_str(s: &str) {
-    for c in s.bytes() {
-        print_char(c as _)
-    }
-}
-
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
-    print_str("hello world\n");
+    signal::use_alternate_stack();
+    signal::set_handler(Signal::User1, Handler::Function(signal_handler));
 
-    unsafe {
-        // TODO: proper syscall API
-        core::arch::asm!("ecall", in("a0") 1, in("a1") signal_handler as usize);
+    loop {
+        syscall::sleep(Duration::from_secs(60));
     }
-
-    #[allow(clippy::empty_loop)]
-    loop {}
 }
 
-extern "C" fn signal_handler(cause: u64) {
-    print_str("signal received. ");
-    let cause_str = core::char::from_digit(cause as _, 10).unwrap();
-    print_str("cause: ");
-    print_char(cause_str);
-    print_str("\n");
+fn signal_handler(signal: Signal) {
+    println!("signal received: {signal:?}");
 }
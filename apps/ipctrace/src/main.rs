@@ -0,0 +1,162 @@
+#![feature(custom_test_frameworks)]
+#![test_runner(librs::test::test_runner)]
+#![no_std]
+#![no_main]
+
+librs::main!(main);
+
+use alloc::{format, string::String, vec::Vec};
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use librs::{
+    service,
+    signal::{self, Handler, Signal},
+    syscall::{self, TraceKind, TraceRecord},
+};
+use log_server::LogClient;
+use ustar::FileSystemClient;
+use virtio::DiskClient;
+
+/// How often new records are read.
+const INTERVAL: Duration = Duration::from_millis(100);
+
+/// How many records are read at once.
+const BATCH: usize = 64;
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+/// A server whose messages are traced, with the names of the requests of its protocol.
+struct Traced {
+    name: &'static str,
+    requests: &'static [&'static str],
+    handle: u64,
+    server_id: u64,
+}
+
+fn main() {
+    signal::set_handler(Signal::Interrupt, Handler::Function(stop));
+    signal::set_handler(Signal::Terminate, Handler::Function(stop));
+
+    let protocols = [
+        (log_server::NAME, LogClient::REQUESTS),
+        (ustar::NAME, FileSystemClient::REQUESTS),
+        (virtio::NAME, DiskClient::REQUESTS),
+    ];
+
+    let mut servers = Vec::new();
+    for (name, requests) in protocols {
+        let Ok(handle) = syscall::connect(name) else {
+            println!("{name} is not running");
+            continue;
+        };
+
+        let server_id = match syscall::trace_server(handle, true) {
+            Ok(server_id) => server_id,
+            Err(error) => {
+                println!("cannot trace {name}: {error}");
+                let _ = syscall::close_handle(handle);
+                continue;
+            }
+        };
+
+        println!("tracing {name} as server {server_id}");
+        servers.push(Traced {
+            name,
+            requests,
+            handle,
+            server_id,
+        });
+    }
+
+    if servers.is_empty() {
+        syscall::exit(1);
+    }
+
+    println!("press Control-C to stop");
+    println!("        TIME  KIND     SENDER  RECEIVER  TOKEN  REQUEST");
+
+    // The kernel records none of our own messages, or the replies to them, so printing through the
+    // log server while tracing it does not keep producing records to print
+    while !STOP.load(Ordering::Relaxed) {
+        let Ok(records) = syscall::read_trace(BATCH) else {
+            break;
+        };
+
+        for record in &records {
+            print_record(record, &servers);
+        }
+
+        if records.len() < BATCH {
+            syscall::sleep(INTERVAL);
+        }
+    }
+
+    for server in servers {
+        let _ = syscall::trace_server(server.handle, false);
+        let _ = syscall::close_handle(server.handle);
+    }
+}
+
+fn stop(_: Signal) {
+    STOP.store(true, Ordering::Relaxed);
+}
+
+/// Print the record on one line, with the sender as `pid/sid`.
+fn print_record(record: &TraceRecord, servers: &[Traced]) {
+    let (kind, request) = match record.kind() {
+        Some(TraceKind::Send) => ("send", request_name(record, servers)),
+        Some(TraceKind::Call) => ("call", request_name(record, servers)),
+        Some(TraceKind::Receive) => ("receive", request_name(record, servers)),
+        Some(TraceKind::Reply) => ("reply", reply_name(record.identifier)),
+        None => ("?", format!("#{}", record.identifier)),
+    };
+
+    let time = format!("{:.3?}", record.time());
+    let sender = format!("{}/{}", record.sender_pid, server_id(record.sender_sid));
+    let token = if record.token == 0 {
+        String::from("-")
+    } else {
+        format!("{}", record.token)
+    };
+
+    println!(
+        "{time:>12}  {kind:<7}  {sender:>6}  {:>8}  {token:>5}  {request} {:x?}",
+        server_id(record.receiver_sid),
+        record.data
+    );
+}
+
+/// The request by the name it has in the protocol of the receiver, if it is known.
+fn request_name(record: &TraceRecord, servers: &[Traced]) -> String {
+    let name = servers
+        .iter()
+        .find(|server| server.server_id == record.receiver_sid)
+        .and_then(|server| {
+            let index = usize::try_from(record.identifier).ok()?.checked_sub(1)?;
+            Some((server.name, *server.requests.get(index)?))
+        });
+
+    match name {
+        Some((server, request)) => format!("{server}::{request}"),
+        None => format!("#{}", record.identifier),
+    }
+}
+
+fn reply_name(identifier: u64) -> String {
+    match identifier {
+        service::REPLY_OK => String::from("ok"),
+        service::REPLY_ERR => String::from("err"),
+        service::REPLY_REJECTED => String::from("rejected"),
+        _ => format!("#{identifier}"),
+    }
+}
+
+fn server_id(sid: u64) -> String {
+    if sid == 0 {
+        String::from("-")
+    } else {
+        format!("{sid}")
+    }
+}
@@ -3,12 +3,17 @@
 #![no_std]
 #![no_main]
 
-use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use alloc::{collections::VecDeque, vec::Vec};
+use core::{
+    sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering},
+    time::Duration,
+};
 use librs::{
-    ipc::{self, MessageData},
-    syscall,
+    ipc,
+    service::Reply,
+    syscall::{self, Events},
 };
-use log_server::{Reply, Request};
+use log_server::Log;
 
 librs::main!(main);
 
@@ -57,54 +62,99 @@ impl<const LEN: usize> AtomicQueue<LEN> {
     }
 }
 
-// NOTE: this is never initialized as that will be done by the kernel for debug purposes
-static UART: uart::NS16550a = uart::NS16550a::DEFAULT;
+static UART_ADDRESS: AtomicU64 = AtomicU64::new(0);
 static INPUT_QUEUE: AtomicQueue<32> = AtomicQueue::new();
 
+// NOTE: this is never initialized as that will be done by the kernel for debug purposes
+fn uart() -> uart::NS16550a {
+    uart::NS16550a::with_base_address(UART_ADDRESS.load(Ordering::Relaxed))
+}
+
+struct LogServer {
+    /// Readers waiting for input in the order they asked, along with when to give up on them.
+    readers: VecDeque<(Reply<Option<u8>>, Option<Duration>)>,
+}
+
+impl Log for LogServer {
+    fn write(&mut self, bytes: Vec<u8>) {
+        bytes.iter().for_each(|&b| uart().write(b));
+    }
+
+    fn read(&mut self, timeout: Option<Duration>, reply: Reply<Option<u8>>) {
+        let deadline = timeout.map(|timeout| syscall::duration_since_boot() + timeout);
+        self.readers.push_back((reply, deadline));
+    }
+}
+
+impl LogServer {
+    /// Hand out the input that arrived to the readers waiting for it, and give up on those that waited for too long.
+    fn answer_readers(&mut self) {
+        while !self.readers.is_empty() {
+            let Some(b) = INPUT_QUEUE.pop() else {
+                break;
+            };
+
+            // TODO: reply with more data if available
+            let (reply, _) = self.readers.pop_front().unwrap();
+            reply.send(Some(b));
+        }
+
+        let now = syscall::duration_since_boot();
+        let (expired, waiting) = self
+            .readers
+            .drain(..)
+            .partition(|(_, deadline)| deadline.is_some_and(|deadline| deadline <= now));
+        self.readers = waiting;
+
+        for (reply, _) in expired {
+            reply.send(None);
+        }
+    }
+
+    /// How long until the next reader gives up, if any of them does.
+    fn next_timeout(&self) -> Option<Duration> {
+        let now = syscall::duration_since_boot();
+        self.readers
+            .iter()
+            .filter_map(|(_, deadline)| *deadline)
+            .min()
+            .map(|deadline| deadline.saturating_sub(now))
+    }
+}
+
 fn main() {
-    syscall::register_server(Some(u64::from_le_bytes(*b"log\0\0\0\0\0"))).unwrap();
-    syscall::identity_map(uart::BASE_ADDR..=uart::BASE_ADDR + 0x1000);
-    syscall::register_interrupt_handler(uart::INTERRUPT_ID, interrupt_handler);
+    syscall::register_server(Some(log_server::NAME)).unwrap();
+
+    let device = syscall::find_devices("ns16550a").next().unwrap();
+    UART_ADDRESS.store(device.address, Ordering::Relaxed);
+    device.identity_map().unwrap();
+    syscall::register_interrupt_handler(device.interrupt.unwrap(), interrupt_handler);
+    syscall::set_priority(None, syscall::Priority::REAL_TIME).unwrap();
+
+    let mut server = LogServer {
+        readers: VecDeque::new(),
+    };
 
     loop {
-        let msg = ipc::Message::receive_blocking();
-        let reply = ipc::MessageBuilder::new(msg.server_id);
-
-        match Request::from(msg) {
-            Request::Read => {
-                if let Some(b) = INPUT_QUEUE.pop() {
-                    // TODO: reply with more data if available
-                    let data = MessageData::from(b as u64);
-
-                    reply
-                        .with_identifier(Reply::DataReady { data }.to_identifier())
-                        .with_data(data)
-                        .send();
-                } else {
-                    reply
-                        .with_identifier(Reply::DataNotReady.to_identifier())
-                        .send();
-                }
-            }
-
-            Request::Write { data } => {
-                data.iter()
-                    .for_each(|num| num.to_be_bytes().iter().for_each(|b| UART.write(*b)));
-            }
-
-            Request::Unknown { id } => {
-                reply
-                    .with_identifier(Reply::RequestUnknown.to_identifier())
-                    .with_data(id.into())
-                    .send();
-            }
+        server.answer_readers();
+
+        // Wake up for new requests, for input from the interrupt handler, or when the next reader gives up.
+        // Whatever ended the wait, everything is checked again.
+        let _ = syscall::wait_events(
+            Events::MESSAGE.union(Events::INTERRUPT),
+            server.next_timeout(),
+        );
+
+        while let Some(msg) = ipc::Message::receive() {
+            server.dispatch(msg);
         }
     }
 }
 
 // Will be called by the kernel when data is submitted to the UART
 extern "C" fn interrupt_handler() {
-    while let Some(b) = UART.poll() {
+    let uart = uart();
+    while let Some(b) = uart.poll() {
         INPUT_QUEUE.push(b);
     }
 
@@ -5,9 +5,18 @@
 
 librs::main!(main);
 
-use alloc::{string::String, vec::Vec};
-use core::time::Duration;
-use librs::syscall;
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use core::{
+    cmp::Reverse,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use librs::{
+    signal::{self, Signal},
+    sync::Mutex,
+    syscall::{self, ExitStatus, ProcessInfo, RunState},
+    thread,
+};
 
 // Filesystems are bloatware
 mod elfs {
@@ -15,6 +24,8 @@ mod elfs {
 
     pub const HELLO: &[u8] =
         include_bytes!("../../../target/riscv64gc-unknown-none-elf/debug/hello");
+    pub const IPCTRACE: &[u8] =
+        include_bytes!("../../../target/riscv64gc-unknown-none-elf/debug/ipctrace");
     pub const LOG: &[u8] =
         include_bytes!("../../../target/riscv64gc-unknown-none-elf/debug/log-server");
     pub const USTAR: &[u8] =
@@ -23,7 +34,150 @@ mod elfs {
         include_bytes!("../../../target/riscv64gc-unknown-none-elf/debug/virtio");
 }
 
-const SLEEP_DURATION: Duration = Duration::from_millis(20);
+/// How often a foreground program is checked for having exited while waiting for input.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often `top` refreshes.
+const TOP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How the last program run in the foreground ended, available as `$?`.
+static LAST_STATUS: Mutex<Option<ExitStatus>> = Mutex::new(None);
+
+/// Run a program and wait for it to exit, reporting how it ended unless it succeeded.
+/// Meanwhile pressing Control-C interrupts the program.
+fn run_foreground(elf: &[u8], name: &str) {
+    let pid = match syscall::spawn_named(elf, name) {
+        Ok(pid) => pid,
+        Err(error) => {
+            println!("cannot run {name}: {error}");
+            return;
+        }
+    };
+
+    let done = Arc::new(AtomicBool::new(false));
+    let watcher = {
+        let done = done.clone();
+        thread::spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                if log_server::read_timeout(POLL_INTERVAL) == Some(b'\x03') {
+                    println!("^C");
+                    // The program may have exited in the meantime
+                    let _ = signal::kill(pid, Signal::Interrupt);
+                }
+            }
+        })
+    };
+
+    let status = syscall::wait(pid);
+    done.store(true, Ordering::Relaxed);
+    watcher.join();
+
+    let Ok(status) = status else {
+        return;
+    };
+
+    if !status.success() {
+        println!("process {pid} {status}");
+    }
+
+    *LAST_STATUS.lock() = Some(status);
+}
+
+/// Expand `$?` to the code of the last foreground program, or how it was killed.
+fn expand(arg: &str) -> String {
+    if arg != "$?" {
+        return arg.into();
+    }
+
+    match *LAST_STATUS.lock() {
+        Some(ExitStatus::Exited(code)) => format!("{code}"),
+        Some(status) => format!("{status}"),
+        None => "0".into(),
+    }
+}
+
+fn state_name(info: &ProcessInfo) -> &'static str {
+    match info.state() {
+        Some(RunState::Running) => "running",
+        Some(RunState::Ready) => "ready",
+        Some(RunState::Sleeping) => "sleeping",
+        Some(RunState::Blocked) => "blocked",
+        None => "?",
+    }
+}
+
+fn ps() {
+    println!("  PID  PPID  STATE     PRI  THREADS        TIME  SWITCHES  NAME");
+    for info in syscall::list_processes() {
+        let parent = info
+            .parent()
+            .map_or("-".into(), |parent| format!("{parent}"));
+        let time = format!("{:.3?}", info.cpu_time());
+        println!(
+            "{:>5}  {parent:>4}  {:<8}  {:>3}  {:>7}  {time:>10}  {:>8}  {}",
+            info.pid,
+            state_name(&info),
+            info.priority,
+            info.threads,
+            info.switches,
+            info.name()
+        );
+    }
+}
+
+/// Show which processes used the most CPU time since the last refresh, until a key is pressed.
+fn top() {
+    let mut previous: Vec<ProcessInfo> = Vec::new();
+    let mut last_refresh = syscall::duration_since_boot();
+
+    loop {
+        let now = syscall::duration_since_boot();
+        let interval = now
+            .saturating_sub(last_refresh)
+            .max(Duration::from_millis(1));
+        last_refresh = now;
+
+        let infos = syscall::list_processes();
+        let mut usage: Vec<_> = infos
+            .iter()
+            .map(|info| {
+                let before = previous
+                    .iter()
+                    .find(|p| p.pid == info.pid)
+                    .map_or(Duration::ZERO, ProcessInfo::cpu_time);
+                (info, info.cpu_time().saturating_sub(before))
+            })
+            .collect();
+        usage.sort_by_key(|(info, used)| (Reverse(*used), info.pid));
+
+        // Clear the screen
+        print!("\x1b[2J\x1b[H");
+        println!(
+            "up {:.0?}, {} processes, press any key to quit",
+            now,
+            infos.len()
+        );
+        println!("  PID  STATE     PRI   CPU%        TIME  NAME");
+        for (info, used) in usage {
+            let percent = used.as_secs_f64() / interval.as_secs_f64() * 100.0;
+            let time = format!("{:.3?}", info.cpu_time());
+            println!(
+                "{:>5}  {:<8}  {:>3}  {percent:>5.1}  {time:>10}  {}",
+                info.pid,
+                state_name(info),
+                info.priority,
+                info.name()
+            );
+        }
+
+        previous = infos;
+
+        let remaining = (now + TOP_INTERVAL).saturating_sub(syscall::duration_since_boot());
+        if log_server::read_timeout(remaining).is_some() {
+            return;
+        }
+    }
+}
 
 fn print_prefix() {
     print!("$ ");
@@ -35,21 +189,28 @@ fn handle_command(line: &str) {
     println!(); // Newline
 
     match command {
-        "exit" => syscall::exit(),
+        "exit" => {
+            let code = iter.next().and_then(|code| code.parse().ok()).unwrap_or(0);
+            syscall::exit(code)
+        }
 
         "uptime" => {
             let uptime = syscall::duration_since_boot();
             println!("uptime: {uptime:?}");
         }
 
-        "hello" => {
-            syscall::spawn(elfs::HELLO, true);
-        }
+        "hello" => run_foreground(elfs::HELLO, "hello"),
 
         "async_hello" => {
-            syscall::spawn(elfs::HELLO, false);
+            syscall::spawn_named(elfs::HELLO, "hello").unwrap();
         }
 
+        "ps" => ps(),
+
+        "top" => top(),
+
+        "ipctrace" => run_foreground(elfs::IPCTRACE, "ipctrace"),
+
         "sleep" => {
             let secs: u64 = iter.next().unwrap().parse().unwrap();
             let duration = Duration::from_secs(secs);
@@ -58,8 +219,26 @@ fn handle_command(line: &str) {
             println!("done sleeping");
         }
 
+        "kill" => {
+            let pid = iter.next().and_then(|pid| pid.parse().ok());
+            let signal = iter.next().map_or(Some(Signal::Terminate), |signal| {
+                signal
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|signal| Signal::try_from(signal).ok())
+            });
+
+            if let (Some(pid), Some(signal)) = (pid, signal) {
+                if signal::kill(pid, signal).is_err() {
+                    println!("no process with PID {pid}");
+                }
+            } else {
+                println!("usage: kill <pid> [signal number]");
+            }
+        }
+
         "echo" => {
-            let args = iter.collect::<Vec<_>>().join(" ");
+            let args = iter.map(expand).collect::<Vec<_>>().join(" ");
             println!("{args}");
         }
 
@@ -78,7 +257,7 @@ fn handle_command(line: &str) {
             };
 
             println!("spawning {path:?}");
-            syscall::spawn(&file, true);
+            run_foreground(&file, path);
         }
 
         "cat" => {
@@ -133,15 +312,15 @@ fn handle_command(line: &str) {
 }
 
 fn main() {
-    syscall::register_server(None);
+    syscall::register_server(None).unwrap();
 
     // Until an `init` process exists
-    syscall::spawn(elfs::LOG, false);
-    syscall::sleep(SLEEP_DURATION); // Dont print before the log server is set up
+    syscall::spawn_named(elfs::LOG, "log-server").unwrap();
+    log_server::wait_until_running(); // Dont print before the log server is set up
 
-    syscall::spawn(elfs::VIRTIO, false);
-    syscall::sleep(SLEEP_DURATION);
-    syscall::spawn(elfs::USTAR, false);
+    // Servers wait for the ones they depend on by name
+    syscall::spawn_named(elfs::VIRTIO, "virtio").unwrap();
+    syscall::spawn_named(elfs::USTAR, "ustar").unwrap();
 
     println!("welcome to knockoff bash");
     print_prefix();
@@ -178,8 +357,6 @@ fn main() {
 
                 _ => {}
             }
-        } else {
-            syscall::sleep(SLEEP_DURATION);
         }
     }
 }
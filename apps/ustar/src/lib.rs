@@ -5,158 +5,85 @@
 
 extern crate alloc;
 
-use alloc::{
-    string::{String, ToString},
-    vec::Vec,
-};
+use alloc::{string::String, vec::Vec};
 use bitbybit::bitenum;
-use librs::ipc;
+use librs::service::{self, Buffer, Decoder, Encoder, Payload};
 
-pub const SID: u64 = u64::from_be_bytes(*b"ustar\0\0\0");
+pub const NAME: &str = "fs.ustar";
+
+static SERVER: FileSystemClient = FileSystemClient::new();
 
 pub type FileIndex = usize;
 
 #[bitenum(u64, exhaustive: false)]
-#[derive(Debug)]
-#[repr(u64)]
-pub enum Request {
-    ListFiles = 1,
-    FileName = 4,
-    FileIndex = 5,
-    FileContents = 6,
-    UnknownRequest = 0xffff,
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    FileNotFound = 1,
+    IsDirectory = 2,
+    NotDirectory = 3,
+    /// The server could not be reached, or did not understand the request.
+    Unavailable = 0xffff,
 }
 
-impl From<&ipc::Message> for Request {
-    fn from(value: &ipc::Message) -> Self {
-        Self::new_with_raw_value(value.identifier).unwrap_or(Request::UnknownRequest)
-    }
-}
+impl Payload for Error {
+    const WORDS: usize = 1;
 
-impl From<Request> for u64 {
-    fn from(val: Request) -> Self {
-        val.raw_value()
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.push(self.raw_value());
     }
-}
 
-#[bitenum(u64, exhaustive: false)]
-#[derive(Debug)]
-#[repr(u64)]
-pub enum Reply {
-    ReplyCount = 2,
-    FileName = 4,
-    FileIndex = 5,
-    FileContents = 6,
-    IsDirectory = 7,
-    NoChildren = 0xfffe,
-    FileNotFound = 0xfffd,
-    UnknownRequest = 0xffff,
+    fn decode(decoder: &mut Decoder) -> Option<Self> {
+        Self::new_with_raw_value(decoder.pop()?).ok()
+    }
 }
 
-impl From<&ipc::Message> for Reply {
-    fn from(value: &ipc::Message) -> Self {
-        Self::new_with_raw_value(value.identifier).unwrap_or(Reply::UnknownRequest)
+impl From<service::Error> for Error {
+    fn from(_: service::Error) -> Self {
+        Self::Unavailable
     }
 }
 
-impl From<Reply> for u64 {
-    fn from(val: Reply) -> Self {
-        val.raw_value()
-    }
+#[librs::service(NAME)]
+pub trait FileSystem {
+    /// The children of a directory, the root directory has index 0.
+    fn children(&mut self, parent: FileIndex) -> Result<Vec<FileIndex>, Error>;
+
+    fn file_name(&mut self, file: FileIndex) -> Result<String, Error>;
+
+    fn file_index(&mut self, path: String) -> Result<FileIndex, Error>;
+
+    /// The contents of a file, which the server shares with the caller.
+    fn file_contents(&mut self, file: FileIndex) -> Result<Buffer, Error>;
 }
 
-pub fn file_index_of(path: &str) -> Result<FileIndex, Reply> {
-    let reply = ipc::MessageBuilder::new(SID)
-        .with_identifier(Request::FileIndex.into())
-        .with_data(path.into())
-        .build()
-        .send_receive()
-        .unwrap();
-
-    if reply.identifier == Reply::FileIndex.into() {
-        Ok(reply.data[0] as _)
-    } else {
-        Err(Reply::from(&reply))
-    }
+pub fn file_index_of(path: &str) -> Result<FileIndex, Error> {
+    SERVER.file_index(path.into())?
 }
 
-pub fn file_name(file_id: FileIndex) -> Result<String, Reply> {
-    let reply = ipc::MessageBuilder::new(SID)
-        .with_identifier(Request::FileName.into())
-        .with_data((file_id as u64).into())
-        .build()
-        .send_receive()
-        .unwrap();
-
-    if reply.identifier == Reply::FileName.into() {
-        let bytes = reply.data.as_be_bytes();
-        Ok(core::str::from_utf8(&bytes).unwrap().to_string())
-    } else {
-        Err(Reply::from(&reply))
-    }
+pub fn file_name(file_id: FileIndex) -> Result<String, Error> {
+    SERVER.file_name(file_id)?
 }
 
-pub fn file_name_of_path(path: &str) -> Result<String, Reply> {
+pub fn file_name_of_path(path: &str) -> Result<String, Error> {
     let file_id = file_index_of(path)?;
     file_name(file_id)
 }
 
-pub fn read_file(file_id: FileIndex) -> Result<Vec<u8>, Reply> {
-    let reply = ipc::MessageBuilder::new(SID)
-        .with_identifier(Request::FileContents.into())
-        .with_data((file_id as u64).into())
-        .build()
-        .send_receive()
-        .unwrap();
-
-    if reply.identifier != Reply::FileContents.into() {
-        return Err(Reply::from(&reply));
-    }
-
-    let ptr = reply.data[0] as *mut u8;
-    let len = reply.data[1] as usize;
-    Ok(unsafe { Vec::from_raw_parts(ptr, len, len) })
+pub fn read_file(file_id: FileIndex) -> Result<Vec<u8>, Error> {
+    let buffer = SERVER.file_contents(file_id)??;
+    buffer.to_vec().ok_or(Error::Unavailable)
 }
 
-pub fn read_file_from_path(path: &str) -> Result<Vec<u8>, Reply> {
+pub fn read_file_from_path(path: &str) -> Result<Vec<u8>, Error> {
     let file_id = file_index_of(path)?;
     read_file(file_id)
 }
 
-pub fn children(parent: FileIndex) -> Result<Vec<FileIndex>, Reply> {
-    let reply = ipc::MessageBuilder::new(SID)
-        .with_identifier(Request::ListFiles.into())
-        .with_data((parent as u64).into())
-        .build()
-        .send_receive()
-        .unwrap();
-
-    if reply.identifier != Reply::ReplyCount.into() {
-        return Err(Reply::from(&reply));
-    }
-
-    let length = reply.data[0];
-    let mut files = Vec::with_capacity(length as usize);
-
-    for _ in 0..length {
-        let reply = ipc::Message::receive_blocking();
-        if reply.identifier != Reply::FileIndex.into() {
-            return Err(Reply::from(&reply));
-        }
-
-        for id in reply
-            .data
-            .iter()
-            .take_while(|&&id| id != Reply::NoChildren.into())
-        {
-            files.push(*id as _);
-        }
-    }
-
-    Ok(files)
+pub fn children(parent: FileIndex) -> Result<Vec<FileIndex>, Error> {
+    SERVER.children(parent)?
 }
 
-pub fn children_of_path(path: &str) -> Result<Vec<FileIndex>, Reply> {
+pub fn children_of_path(path: &str) -> Result<Vec<FileIndex>, Error> {
     let file_id = file_index_of(path)?;
     children(file_id)
 }
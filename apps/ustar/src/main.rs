@@ -3,11 +3,11 @@
 #![no_std]
 #![no_main]
 
-use alloc::{fmt, vec::Vec};
+use alloc::{fmt, string::String, vec::Vec};
 use binrw::{binrw, BinRead, BinReaderExt, NullString};
 use core::{ops::Index, str};
-use librs::ipc;
-use ustar::{FileIndex, Reply, Request};
+use librs::service::Buffer;
+use ustar::{Error, FileIndex, FileSystem};
 
 librs::main!(main);
 
@@ -182,114 +182,47 @@ impl<'a> Index<FileIndex> for TarBall<'a> {
     }
 }
 
-fn main() {
-    librs::syscall::register_server(Some(u64::from_be_bytes(*b"ustar\0\0\0")));
+impl FileSystem for TarBall<'_> {
+    fn children(&mut self, parent: FileIndex) -> Result<Vec<FileIndex>, Error> {
+        let children = TarBall::children(self, parent).ok_or(Error::NotDirectory)?;
+        Ok(children.map(|child| child.index).collect())
+    }
 
-    let size_msg: librs::ipc::Message = virtio::Request::DiskSize.into();
-    let size_reply = size_msg.send_receive().unwrap();
-    assert_eq!(
-        virtio::Reply::from_message(&size_reply),
-        Some(virtio::Reply::DiskSize)
-    );
+    fn file_name(&mut self, file: FileIndex) -> Result<String, Error> {
+        let file = self.get_index(file).ok_or(Error::FileNotFound)?;
+        Ok(String::from_utf8_lossy(&file.header.file_name).into())
+    }
 
-    println!("[ustar] reading disk with size {:#x}", size_reply.data[0]);
+    fn file_index(&mut self, path: String) -> Result<FileIndex, Error> {
+        self.get_name(path.as_bytes())
+            .map(|file| file.index)
+            .ok_or(Error::FileNotFound)
+    }
 
-    let contents_msg: librs::ipc::Message = virtio::Request::ReadDisk.into();
-    let contents_reply = contents_msg.send_receive().unwrap();
-    let contents = unsafe { virtio::reply_as_slice(&contents_reply).unwrap() };
+    fn file_contents(&mut self, file: FileIndex) -> Result<Buffer, Error> {
+        let file = self.get_index(file).ok_or(Error::FileNotFound)?;
+        if file.header.type_flag == TypeFlag::Directory {
+            return Err(Error::IsDirectory);
+        }
 
-    let tarball = TarBall::new(contents);
+        // Share a read-only copy of the file contents with the caller
+        Buffer::new(file.content).map_err(|_| Error::Unavailable)
+    }
+}
 
-    println!("[ustar] server ready");
+fn main() {
+    librs::syscall::register_server(Some(ustar::NAME)).unwrap();
 
-    loop {
-        let msg = ipc::Message::receive_blocking();
-        let reply = ipc::MessageBuilder::new(msg.server_id);
-
-        match Request::from(&msg) {
-            Request::ListFiles => {
-                let parent = msg.data[0] as usize;
-
-                if let Some(children) = tarball.children(parent).map(|c| c.collect::<Vec<_>>()) {
-                    let length = children.chunks(ipc::MessageData::LEN).count() as u64;
-                    reply
-                        .with_identifier(Reply::ReplyCount.into())
-                        .with_data(length.into())
-                        .send();
-
-                    for chunk in children.chunks(ipc::MessageData::LEN) {
-                        let mut data = [Reply::NoChildren.into(); ipc::MessageData::LEN];
-                        for (i, child) in chunk.iter().enumerate() {
-                            data[i] = child.index as u64;
-                        }
-
-                        reply
-                            .with_identifier(Reply::FileIndex.into())
-                            .with_data(data.into())
-                            .send();
-                    }
-                } else {
-                    reply.with_identifier(Reply::NoChildren.into()).send();
-                }
-            }
-
-            Request::FileName => {
-                let index = msg.data[0] as _;
-                if let Some(file) = &tarball.get_index(index) {
-                    let name = file.header.file_name.as_slice();
-                    reply
-                        .with_identifier(Reply::FileName.into())
-                        .with_data(name.into())
-                        .send();
-                } else {
-                    reply.with_identifier(Reply::FileNotFound.into()).send();
-                }
-            }
-
-            Request::FileIndex => {
-                let bytes = msg.data.as_be_bytes();
-                let bytes = bytes.split(|b| *b == 0).next().unwrap_or(&[]);
-
-                if let Some(fid) = tarball.get_name(bytes).map(|f| f.index as u64) {
-                    reply
-                        .with_identifier(Reply::FileIndex.into())
-                        .with_data(fid.into())
-                        .send();
-                } else {
-                    reply.with_identifier(Reply::FileNotFound.into()).send();
-                }
-            }
-
-            Request::FileContents => {
-                let index = msg.data[0] as _;
-                if let Some(file) = &tarball.get_index(index) {
-                    if file.header.type_flag == TypeFlag::Directory {
-                        reply.with_identifier(Reply::IsDirectory.into()).send();
-                    } else {
-                        // Allocate a buffer and copy the file contents into it
-                        let aligned_size = librs::align_page_up(file.content.len());
-                        let mut buffer = alloc::vec![0; aligned_size];
-                        buffer[..file.content.len()].copy_from_slice(file.content);
-                        let buffer_ptr = buffer.as_mut_ptr() as u64;
-
-                        // Transfer the buffer to the client
-                        librs::syscall::transfer_memory(msg.server_id, buffer);
-                        let reply_data: &[u64] = &[buffer_ptr, file.content.len() as u64];
-
-                        reply
-                            .with_identifier(Reply::FileContents.into())
-                            .with_data(reply_data.into())
-                            .send();
-                    }
-                } else {
-                    reply.with_identifier(Reply::FileNotFound.into()).send();
-                }
-            }
-
-            _ => {
-                println!("[ustar] unknown request: {:#x}", msg.identifier);
-                reply.with_identifier(Reply::UnknownRequest.into()).send();
-            }
-        }
-    }
+    let disk = virtio::DiskClient::new();
+    let size = disk.size().unwrap();
+    println!("[ustar] reading disk with size {size:#x}");
+
+    let buffer = disk.read_all().unwrap().unwrap();
+    let mapping = buffer.map().unwrap();
+    let contents = &mapping[..buffer.len()];
+
+    let mut tarball = TarBall::new(contents);
+
+    println!("[ustar] server ready");
+    tarball.serve();
 }
@@ -12,15 +12,12 @@ mod queue;
 
 use crate::block_device::{BlockDevice, BLOCK_SIZE};
 use bitbybit::{bitenum, bitfield};
-use core::{cell::UnsafeCell, fmt, mem::MaybeUninit, ops::RangeInclusive};
-use librs::{ipc, syscall};
+use core::{cell::UnsafeCell, fmt, mem::MaybeUninit};
+use librs::{service::Buffer, syscall};
+use virtio::Disk;
 
 librs::main!(main);
 
-// TODO: dont hardcode this
-const VIRTIO_RANGE: RangeInclusive<u64> = 0x10001000..=0x10008000;
-const VIRTIO_DEVICE_LEN: usize = 0x1000;
-const INTERRUPT_ID: u64 = 8;
 const MAGIC: u32 = u32::from_le_bytes(*b"virt");
 
 // TODO: lock this up
@@ -143,23 +140,49 @@ impl DeviceRegister {
     }
 }
 
+struct DiskServer;
+
+impl Disk for DiskServer {
+    fn size(&mut self) -> u64 {
+        unsafe { DISK.get_mut().assume_init_mut() }.capacity() * BLOCK_SIZE as u64
+    }
+
+    fn read_all(&mut self) -> Option<Buffer> {
+        let disk = unsafe { DISK.get_mut().assume_init_mut() };
+        let capacity = disk.capacity();
+
+        // The device writes to physical addresses, which the identity mapped heap provides
+        let size = capacity * BLOCK_SIZE as u64;
+        let mut buffer = alloc::vec![0u8; size as usize].into_boxed_slice();
+        let buf_ptr = buffer.as_mut_ptr();
+
+        // Read out every sector of the block device
+        for sector in 0..capacity {
+            println!("[virtio] reading sector {sector:#x}");
+            let buf = unsafe { buf_ptr.add(sector as usize * BLOCK_SIZE) };
+            unsafe { disk.read_sector(sector, buf) };
+        }
+
+        // Share a read-only copy with the caller, it is freed once the caller is done with it
+        println!("[virtio] sharing buffer of size {size:#x}");
+        Buffer::new(&buffer).ok()
+    }
+}
+
 extern "C" fn interrupt_handler() {
     unsafe { DISK.get_mut().assume_init_mut() }.interrupt();
     syscall::complete_interrupt()
 }
 
 fn main() {
-    syscall::register_server(Some(123));
-    syscall::identity_map(VIRTIO_RANGE);
-    syscall::register_interrupt_handler(INTERRUPT_ID, interrupt_handler);
+    syscall::register_server(Some(virtio::NAME)).unwrap();
 
     println!("virtio driver startup");
 
-    for (index, dev_ptr) in VIRTIO_RANGE
-        .step_by(VIRTIO_DEVICE_LEN)
-        .map(|d| d as *mut u32)
-        .enumerate()
-    {
+    for (index, device) in syscall::find_devices("virtio,mmio").enumerate() {
+        device.identity_map().unwrap();
+        let dev_ptr = device.address as *mut u32;
+
         if DeviceRegister::Magic.read(dev_ptr) != MAGIC {
             println!("device {index}: no magic");
             continue;
@@ -191,6 +214,8 @@ fn main() {
         println!("found {device_id:?} {index} at {dev_ptr:?}: vendor_id = {vendor_id}, virtio = {version}");
         match device_id {
             DeviceIdentifier::BlockDevice => {
+                syscall::register_interrupt_handler(device.interrupt.unwrap(), interrupt_handler);
+                syscall::set_priority(None, syscall::Priority::REAL_TIME).unwrap();
                 let block_device = BlockDevice::init(dev_ptr);
                 unsafe { DISK.get().write(MaybeUninit::new(block_device)) };
             }
@@ -200,56 +225,5 @@ fn main() {
 
     println!("virtio driver ready");
 
-    loop {
-        let msg = ipc::Message::receive_blocking();
-        let reply = ipc::MessageBuilder::new(msg.server_id);
-
-        match virtio::Request::from(&msg) {
-            virtio::Request::DiskSize => {
-                let capacity =
-                    unsafe { DISK.get_mut().assume_init_mut() }.capacity() * BLOCK_SIZE as u64;
-                reply
-                    .with_identifier(virtio::Reply::DiskSize as _)
-                    .with_data(capacity.into())
-                    .send();
-            }
-
-            virtio::Request::ReadDisk => {
-                let disk = unsafe { DISK.get_mut().assume_init_mut() };
-                let capacity = disk.capacity();
-
-                // Align the buffer to a page so that it can be mapped into the receivers address space
-                let size = capacity * BLOCK_SIZE as u64;
-                let aligned_size = librs::align_page_up(size as _);
-                let mut buffer = alloc::vec![0u8; aligned_size].into_boxed_slice();
-                let buf_ptr = buffer.as_mut_ptr();
-
-                // Read out every sector of the block device
-                for sector in 0..capacity {
-                    println!("[virtio] reading sector {sector:#x}");
-                    let buf = unsafe { buf_ptr.add(sector as usize * BLOCK_SIZE) };
-                    unsafe { disk.read_sector(sector, buf) };
-                }
-
-                let reply_data: &[u64] = &[buf_ptr as _, size];
-                let buffer = buffer.into_vec();
-
-                // Transfer the buffer to the receiver, we cannot access it afterwards
-                println!("[virtio] transferring buffer of size {size:#x}");
-                librs::syscall::transfer_memory(msg.server_id, buffer);
-
-                // Let the receiver know that the data is ready, and it can be found
-                reply
-                    .with_identifier(virtio::Reply::DataReady as u64)
-                    .with_data(reply_data.into())
-                    .send();
-            }
-
-            virtio::Request::UnknownRequest => {
-                reply
-                    .with_identifier(virtio::Request::UnknownRequest as u64)
-                    .send();
-            }
-        }
-    }
+    DiskServer.serve();
 }
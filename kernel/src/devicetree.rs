@@ -0,0 +1,146 @@
+//! Discovery of the memory and devices of the machine, through the flattened device tree passed to us at boot.
+
+use crate::{memory::page, uart};
+use arbor::{node::Node, property::Region, DeviceTree};
+use core::{
+    iter, ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+/// Devices that get mapped into the kernel, either because we use them or so that they can be identity mapped into userspace drivers.
+const MAPPED_DEVICES: &[&str] = &["ns16550a", "riscv,plic0", "riscv,clint0", "virtio,mmio"];
+
+static DEVICE_TREE: AtomicPtr<u8> = AtomicPtr::new(ptr::null_mut());
+
+/// Called from `entry.asm` in machine mode with the pointer the firmware left us, before any other device is touched.
+#[no_mangle]
+unsafe extern "C" fn device_tree_init(ptr: *const u8) {
+    if let Err(err) = DeviceTree::from_ptr(ptr) {
+        panic!("invalid device tree at {ptr:?}: {err:?}");
+    }
+
+    DEVICE_TREE.store(ptr as _, Ordering::Relaxed);
+    uart::init();
+}
+
+pub fn get() -> DeviceTree<'static> {
+    let ptr = DEVICE_TREE.load(Ordering::Relaxed);
+    assert!(!ptr.is_null(), "device tree used before it was initialized");
+    unsafe { DeviceTree::from_ptr(ptr) }.unwrap()
+}
+
+/// A memory mapped device.
+#[derive(Debug, Clone, Copy)]
+pub struct Device {
+    pub region: Region,
+    pub interrupt: Option<u32>,
+}
+
+impl Device {
+    fn from_node(node: Node) -> Option<Self> {
+        Some(Self {
+            region: node.reg().next()?,
+            interrupt: node.interrupts().next(),
+        })
+    }
+}
+
+/// All devices compatible with the given string, in the order they appear in the device tree.
+pub fn find_devices(compatible: &str) -> impl Iterator<Item = Device> + '_ {
+    get()
+        .find_compatible(compatible)
+        .filter_map(Device::from_node)
+}
+
+/// The first device compatible with the given string, for devices we cannot function without.
+pub fn expect_device(compatible: &str) -> Device {
+    find_devices(compatible)
+        .next()
+        .unwrap_or_else(|| panic!("no device compatible with {compatible:?} found"))
+}
+
+/// The value of a `name=value` option in the boot arguments, passed through the `bootargs` property of `/chosen`.
+pub fn boot_argument(name: &str) -> Option<&'static str> {
+    let chosen = get()
+        .nodes()
+        .find(|node| node.depth == 1 && node.name == "chosen")?;
+    let bootargs = chosen.property("bootargs")?.as_str()?;
+
+    bootargs
+        .split_whitespace()
+        .filter_map(|arg| arg.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// The end of the memory usable by a heap starting at the given address.
+/// This stops at the first reserved region after it, which includes the device tree itself.
+pub fn heap_end(start: usize) -> usize {
+    let tree = get();
+    let memory = tree
+        .memory()
+        .find(|region| region.contains(start as _))
+        .expect("heap does not start in memory");
+
+    let device_tree = Region {
+        start: DEVICE_TREE.load(Ordering::Relaxed) as _,
+        size: tree.size() as _,
+    };
+
+    tree.reserved_memory()
+        .chain(iter::once(device_tree))
+        .filter(|region| region.start >= start as _)
+        .map(|region| region.start)
+        .fold(memory.end(), u64::min) as _
+}
+
+/// Map the device tree and the devices we know about into the given page table.
+pub fn map(page_table: &mut page::Table) {
+    let tree_start = DEVICE_TREE.load(Ordering::Relaxed) as usize;
+    page_table.identity_map(
+        tree_start,
+        tree_start + get().size() - 1,
+        page::EntryAttributes::Readable,
+    );
+
+    for device in MAPPED_DEVICES.iter().flat_map(|c| find_devices(c)) {
+        let start = device.region.start as usize;
+        page_table.identity_map(
+            start,
+            start + device.region.size as usize - 1,
+            page::EntryAttributes::ReadWrite,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::sections;
+
+    #[test_case]
+    fn kernel_is_in_memory() {
+        let kernel = sections::text_start() as u64;
+        assert!(get().memory().any(|region| region.contains(kernel)));
+    }
+
+    #[test_case]
+    fn finds_required_devices() {
+        for compatible in ["ns16550a", "riscv,plic0", "riscv,clint0"] {
+            let device = expect_device(compatible);
+            assert_ne!(device.region.size, 0);
+        }
+
+        assert!(expect_device("ns16550a").interrupt.is_some());
+    }
+
+    #[test_case]
+    fn heap_excludes_device_tree() {
+        let start = sections::heap_start();
+        let end = heap_end(start);
+        let tree_start = DEVICE_TREE.load(Ordering::Relaxed) as usize;
+
+        assert!(end > start);
+        assert!(tree_start < start || end <= tree_start);
+    }
+}
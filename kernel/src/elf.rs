@@ -1,13 +1,14 @@
-use crate::memory::{
-    self,
-    page::{self, Page},
-    PAGE_SIZE,
+use crate::{
+    memory::{self, page},
+    thread::region::{Backing, Region, RegionList},
 };
-use alloc::boxed::Box;
+use alloc::{string::String, sync::Arc};
 use binrw::BinRead;
 use fairy::{
     header,
     program::{self, ProgramFlags},
+    section::SectionTable,
+    symbol::{SymbolTable, SymbolType},
 };
 
 const fn convert_flags(from: ProgramFlags) -> Option<page::EntryAttributes> {
@@ -19,37 +20,71 @@ const fn convert_flags(from: ProgramFlags) -> Option<page::EntryAttributes> {
     }
 }
 
-pub fn load_elf(elf: &[u8], page_table: &mut memory::page::Table) -> u64 {
-    let mut cursor = binrw::io::Cursor::new(elf);
-    let header = header::Header::try_from(&mut cursor).unwrap();
-    assert_eq!(header.identifier.class, header::Class::Bits64);
+/// Register the loadable segments of the given ELF file as regions, their pages are populated once they are accessed.
+/// Returns the entry point of the program, or `None` if the file is not a valid 64-bit ELF or its segments are malformed.
+pub fn load_elf(elf: &Arc<[u8]>, regions: &mut RegionList) -> Option<u64> {
+    let mut cursor = binrw::io::Cursor::new(&elf[..]);
+    let header = header::Header::try_from(&mut cursor).ok()?;
+    if header.identifier.class != header::Class::Bits64 {
+        return None;
+    }
 
-    cursor.set_position(header.primary.program_header_start_64.unwrap() as _);
+    cursor.set_position(header.primary.program_header_start_64? as _);
     for _ in 0..header.primary.program_header_entry_count {
         let program =
-            program::ProgramHeader::read_options(&mut cursor, header.endianness(), ()).unwrap();
+            program::ProgramHeader::read_options(&mut cursor, header.endianness(), ()).ok()?;
 
         if program.program_type == program::ProgramType::Loadable {
-            for page_offset in memory::page_offsets(program.memory_size as usize) {
-                let page_data = {
-                    let len = PAGE_SIZE.min(program.memory_size as usize - page_offset);
-                    let elf_start = program.offset as usize + page_offset;
-                    let elf_end = elf_start + len;
-                    Box::new(Page::from_slice(&elf[elf_start..elf_end]))
-                };
-
-                // Calculate the virtual address of the page
-                let vaddr = memory::align_page_down(program.virtual_address as usize + page_offset);
-
-                // Map the page to the virtual address.
-                page_table.map_page(
-                    vaddr,
-                    Box::into_raw(page_data) as usize,
-                    convert_flags(program.flags).unwrap(),
-                );
+            let vaddr = program.virtual_address as usize;
+            let end = vaddr.checked_add(program.memory_size as usize)?;
+            let file_end = (program.offset as usize).checked_add(program.file_size as usize)?;
+
+            // The data has to be in the file and fit the segment, which must not run into other segments
+            if program.file_size > program.memory_size
+                || file_end > elf.len()
+                || end > memory::align_page_down(usize::MAX)
+            {
+                return None;
             }
+
+            let range = memory::align_page_down(vaddr)..memory::align_page_up(end);
+            if !regions.is_free(&range) {
+                return None;
+            }
+
+            let backing = if program.file_size == 0 {
+                Backing::Anonymous
+            } else {
+                Backing::Elf {
+                    data: elf.clone(),
+                    vaddr,
+                    offset: program.offset as _,
+                    size: program.file_size as _,
+                }
+            };
+
+            regions.insert(Region::new(range, convert_flags(program.flags)?, backing));
         }
     }
 
-    header.primary.entry_point_64.unwrap()
+    header.primary.entry_point_64
+}
+
+/// The name of the program in the given ELF file, taken from the first source file in its symbol table.
+/// Returns `None` if the file has no symbols.
+pub fn program_name(elf: &[u8]) -> Option<String> {
+    let mut cursor = binrw::io::Cursor::new(elf);
+    let header = header::Header::try_from(&mut cursor).ok()?;
+
+    cursor.set_position(header.primary.section_header_start_64? as _);
+    let sections = SectionTable::new(&mut cursor, &header)?;
+    let symbols = SymbolTable::new(&sections, header.endianness())?;
+
+    // Source files of Rust crates are named after the crate, followed by a hash and the codegen unit
+    let file = symbols
+        .iter()
+        .filter(|symbol| symbol.entry.info.symbol_type == SymbolType::File)
+        .find_map(|symbol| symbol.name)?;
+    let name = file.split('.').next().filter(|name| !name.is_empty())?;
+    Some(name.into())
 }
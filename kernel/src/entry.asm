@@ -10,9 +10,22 @@ _start:
 	la gp, _global_pointer
     .option pop
 
-	# Any hardware threads (harts) other than hart 0 should park until multithreading is implemented
-	csrr t0, mhartid
-	bnez t0, park_hart
+	# Every hardware thread (hart) keeps its ID in the thread pointer while running in the kernel
+	csrr tp, mhartid
+	li t0, {MAX_HARTS}
+	bgeu tp, t0, park_hart
+
+    # Initialize the stack of this hart, see `link.ld`
+	la sp, _stack_end
+	li t0, {STACK_SIZE}
+	mul t0, t0, tp
+	sub sp, sp, t0
+
+	# Only hart 0 sets up the shared state, the other harts wait until it is done
+	bnez tp, wait_for_boot_hart
+
+	# Save the device tree pointer passed by the firmware, as clearing the BSS clobbers it
+	mv s1, a1
 
 	# Clear the BSS section to avoid UB
 	la a0, _bss_start
@@ -22,12 +35,12 @@ clear_bss_loop:
 	addi a0, a0, 8
 	bltu a0, a1, clear_bss_loop
 
-    # Initialize the stack, assuming one hart
-	la sp, _stack_end
-
-    # Delegate all traps to Supervisor
-    li t0, 0xffffffffffffff
+setup_machine_mode:
+    # Delegate all traps to Supervisor, except for its own environment calls which are used to program the timer
+    li t0, 0xfffffffffffdff
     csrw medeleg, t0
+    # Delegate the supervisor software, timer and external interrupts
+    li t0, 1 << 1 | 1 << 5 | 1 << 9
     csrw mideleg, t0
 
     # Set the Physical Memory Protection to allow Supervisor to access all memory
@@ -41,14 +54,31 @@ clear_bss_loop:
     la t0, machine_trap_vector
     csrw mtvec, t0
 
+    # The other harts do not need to parse the device tree again
+    bnez tp, init_timer
+
+    # Parse the device tree, this must happen before any device is accessed
+    mv a0, s1
+    call device_tree_init
+
+    # Let the other harts continue
+    li t0, 1
+    la t1, boot_hart_ready
+    sw t0, (t1)
+    fence
+
+init_timer:
     # Initialize timer interrupts
     call machine_timer_init
 
 	# Temporarily disable paging, will be enabled by the kernel once its ready
 	csrw satp, zero
 
-	# Set up the jump to the kernels entry point
+	# Set up the jump to the kernels entry point, which is different for the boot hart
 	la t0, kernel_main
+	beqz tp, set_entry_point
+	la t0, hart_main
+set_entry_point:
 	csrw mepc, t0
 
 	# Place to continue execution after the kernel has finished (should never be reached)
@@ -61,7 +91,22 @@ clear_bss_loop:
 	# Enter supervisor mode and jump to the kernel
 	mret
 
+.section .text.init
+wait_for_boot_hart:
+	la t0, boot_hart_ready
+wait_for_boot_hart_loop:
+	lw t1, (t0)
+	beqz t1, wait_for_boot_hart_loop
+	fence
+	j setup_machine_mode
+
 .section .text.init
 park_hart:
     wfi
     j park_hart
+
+# Set by hart 0 once the device tree has been parsed.
+# Lives in `.data` rather than `.bss`, as the other harts poll it while the BSS is being cleared.
+.section .data
+boot_hart_ready:
+	.word 0
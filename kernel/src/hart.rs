@@ -0,0 +1,104 @@
+//! Bookkeeping for the hardware threads (harts) of the machine.
+//! While a hart runs in the kernel, its thread pointer register holds its ID, as set up by `entry.asm`.
+
+use crate::{devicetree, memory::sections, trap::clint};
+use core::{
+    arch::asm,
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+/// Harts with a higher ID are parked by `entry.asm`.
+pub const MAX_HARTS: usize = 8;
+
+/// The size of the kernel stack of every hart, must match `entry.asm`.
+pub const STACK_SIZE: usize = 16 * 0x1000; // 64 KiB
+
+/// Set by the boot hart once memory and paging have been set up, the other harts wait for this.
+static KERNEL_READY: AtomicBool = AtomicBool::new(false);
+
+/// Counts the switches of every hart between the kernel and user threads, it is odd while the hart runs user code.
+/// The TLB is flushed on every such switch, see `switch.asm`.
+static USER_SWITCHES: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+
+/// The ID of the hart we are running on.
+#[inline(always)]
+pub fn id() -> usize {
+    let id: usize;
+    unsafe { asm!("mv {}, tp", out(reg) id) };
+    id
+}
+
+/// The top of the kernel stack of the hart we are running on.
+pub fn stack_top() -> usize {
+    sections::stack_end() - (id() * STACK_SIZE)
+}
+
+/// The amount of harts the kernel runs on, as described by the device tree.
+pub fn count() -> usize {
+    devicetree::get()
+        .nodes()
+        .filter(|node| {
+            node.property("device_type")
+                .and_then(|prop| prop.as_str())
+                .is_some_and(|device_type| device_type == "cpu")
+        })
+        .count()
+        .min(MAX_HARTS)
+}
+
+/// Record that the current hart is about to run a user thread.
+pub fn enter_user() {
+    USER_SWITCHES[id()].fetch_add(1, Ordering::SeqCst);
+}
+
+/// Record that the current hart trapped from a user thread into the kernel.
+pub fn leave_user() {
+    USER_SWITCHES[id()].fetch_add(1, Ordering::SeqCst);
+}
+
+/// Make sure that none of the given harts still translates addresses through page table entries that were removed
+/// before this call. Harts running user code are interrupted and waited for until they trapped into the kernel,
+/// the others flush their TLB before they run user code again.
+pub fn flush_tlbs(harts: impl Iterator<Item = usize>) {
+    for hart in harts.filter(|&hart| hart != id()) {
+        let switches = USER_SWITCHES[hart].load(Ordering::SeqCst);
+        if switches % 2 == 0 {
+            continue;
+        }
+
+        clint::send_ipi(hart);
+        while USER_SWITCHES[hart].load(Ordering::SeqCst) == switches {
+            spin_loop();
+        }
+    }
+}
+
+/// Let the other harts continue into the kernel, must only be called by the boot hart.
+pub fn start_others() {
+    KERNEL_READY.store(true, Ordering::Release);
+}
+
+/// Wait until the boot hart has set up the kernel.
+pub fn wait_for_boot_hart() {
+    while !KERNEL_READY.load(Ordering::Acquire) {
+        spin_loop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn tests_run_on_boot_hart() {
+        assert_eq!(id(), 0);
+        assert_eq!(stack_top(), sections::stack_end());
+    }
+
+    #[test_case]
+    fn stacks_fit_in_stack_section() {
+        assert!((1..=MAX_HARTS).contains(&count()));
+        assert!(sections::stack_end() - (MAX_HARTS * STACK_SIZE) >= sections::stack_start());
+    }
+}
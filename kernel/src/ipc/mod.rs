@@ -0,0 +1,307 @@
+use crate::{process::capability::Capability, spinlock::SpinLock};
+use alloc::{collections::VecDeque, string::String, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+use syscall::Rights;
+
+pub mod trace;
+
+static SERVER_LIST: SpinLock<ServerList> = SpinLock::new(ServerList::new());
+static NEXT_SERVER_ID: AtomicU64 = AtomicU64::new(1);
+static NEXT_REPLY_TOKEN: AtomicU64 = AtomicU64::new(1);
+
+/// How many messages can be queued for a server that did not choose a limit itself.
+pub const DEFAULT_QUEUE_LIMIT: usize = 32;
+
+// TODO: merge with librs
+#[derive(Debug, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct MessageData {
+    pub data: [u64; 5],
+}
+
+impl MessageData {
+    const DEFAULT: MessageData = MessageData { data: [0; 5] };
+
+    pub fn from_slice(data: &[u64]) -> Self {
+        let mut result = Self::DEFAULT;
+        result.data[..data.len()].copy_from_slice(data);
+        result
+    }
+
+    pub fn as_slice(&self) -> &[u64] {
+        &self.data
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Message {
+    pub identifier: u64,
+    pub sender_pid: usize,
+    pub sender_sid: u64,
+    pub data: MessageData,
+    /// Identifies the call this message was sent with, which the receiving server has to reply to.
+    pub reply_token: Option<u64>,
+    /// A capability granted to the receiver along with the message.
+    pub capability: Option<Capability>,
+}
+
+impl Message {
+    pub const fn new(
+        sender_pid: usize,
+        sender_sid: u64,
+        identifier: u64,
+        data: MessageData,
+    ) -> Self {
+        Self {
+            sender_sid,
+            sender_pid,
+            identifier,
+            data,
+            reply_token: None,
+            capability: None,
+        }
+    }
+
+    /// A message whose sender waits for a reply, with a new token to reply with.
+    pub fn call(sender_pid: usize, sender_sid: u64, identifier: u64, data: MessageData) -> Self {
+        Self {
+            reply_token: Some(NEXT_REPLY_TOKEN.fetch_add(1, Ordering::Relaxed)),
+            ..Self::new(sender_pid, sender_sid, identifier, data)
+        }
+    }
+}
+
+/// The error of sending to a server whose queue is full.
+#[derive(Debug, PartialEq, Eq)]
+pub struct QueueFull;
+
+#[derive(Debug)]
+pub struct Server {
+    pub process_id: usize,
+    pub server_id: u64,
+    /// The name anyone may connect to the server with, like `fs.ustar`.
+    pub name: Option<String>,
+    /// The capability the server holds for itself, from which the capabilities of its clients are derived.
+    pub capability: Capability,
+    /// How many messages may be queued at once, senders have to wait or fail once the queue is full.
+    pub queue_limit: usize,
+    messages: VecDeque<Message>,
+    /// Tokens of the calls this server received but did not reply to yet, each of them can be used once.
+    reply_rights: Vec<u64>,
+}
+
+impl Server {
+    fn new(process_id: usize, name: Option<String>) -> Self {
+        let server_id = NEXT_SERVER_ID.fetch_add(1, Ordering::SeqCst);
+
+        Self {
+            process_id,
+            server_id,
+            name,
+            capability: Capability::server(server_id),
+            queue_limit: DEFAULT_QUEUE_LIMIT,
+            messages: VecDeque::new(),
+            reply_rights: Vec::new(),
+        }
+    }
+
+    /// A capability for clients that connect to the server by its name, which may send and grant it.
+    pub fn client_capability(&self) -> Capability {
+        self.capability
+            .derive(Rights::SEND.union(Rights::GRANT))
+            .expect("servers can always grant their own capability")
+    }
+
+    pub fn has_messages(&self) -> bool {
+        !self.messages.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.messages.len() >= self.queue_limit
+    }
+
+    /// Queue a message, dropping it if the queue is full.
+    pub fn send_message(&mut self, message: Message) -> Result<(), QueueFull> {
+        if self.is_full() {
+            return Err(QueueFull);
+        }
+
+        self.messages.push_back(message);
+        Ok(())
+    }
+
+    /// Take the next message, receiving a call grants the right to reply to it.
+    pub fn receive_message(&mut self) -> Option<Message> {
+        let message = self.messages.pop_front()?;
+        self.reply_rights.extend(message.reply_token);
+        Some(message)
+    }
+
+    /// Use up the right to reply to the call with the given token, returning whether the server had it.
+    pub fn take_reply_right(&mut self, token: u64) -> bool {
+        let Some(index) = self.reply_rights.iter().position(|&t| t == token) else {
+            return false;
+        };
+
+        self.reply_rights.swap_remove(index);
+        true
+    }
+}
+
+#[derive(Debug)]
+pub struct ServerList {
+    servers: Vec<Server>,
+}
+
+impl ServerList {
+    pub const fn new() -> Self {
+        Self {
+            servers: Vec::new(),
+        }
+    }
+
+    /// Register a server for the given process, names have to be unique.
+    pub fn register(&mut self, process_id: usize, name: Option<String>) -> Option<u64> {
+        if self.servers.iter().any(|s| s.process_id == process_id) {
+            return None;
+        }
+
+        if let Some(name) = &name {
+            if self.get_by_name(name).is_some() {
+                return None;
+            }
+        }
+
+        let server = Server::new(process_id, name);
+        let server_id = server.server_id;
+        self.servers.push(server);
+        Some(server_id)
+    }
+
+    pub fn get_by_pid(&mut self, process_id: usize) -> Option<&mut Server> {
+        self.servers.iter_mut().find(|s| s.process_id == process_id)
+    }
+
+    pub fn get_by_sid(&mut self, server_id: u64) -> Option<&mut Server> {
+        self.servers.iter_mut().find(|s| s.server_id == server_id)
+    }
+
+    pub fn get_by_name(&mut self, name: &str) -> Option<&mut Server> {
+        self.servers
+            .iter_mut()
+            .find(|s| s.name.as_deref() == Some(name))
+    }
+
+    /// Whether a server can still reply to the call with the given token, because it is queued or was received.
+    pub fn is_call_pending(&self, token: u64) -> bool {
+        self.servers.iter().any(|server| {
+            server.reply_rights.contains(&token)
+                || server
+                    .messages
+                    .iter()
+                    .any(|message| message.reply_token == Some(token))
+        })
+    }
+
+    /// Remove the capabilities derived from the one with the given ID from messages that were not received yet.
+    pub fn revoke(&mut self, id: u64) {
+        let messages = self.servers.iter_mut().flat_map(|s| s.messages.iter_mut());
+        for message in messages {
+            if message
+                .capability
+                .as_ref()
+                .is_some_and(|c| c.is_derived_from(id))
+            {
+                message.capability = None;
+            }
+        }
+    }
+
+    pub fn remove_by_pid(&mut self, process_id: usize) -> Option<Server> {
+        for server in self.servers.iter_mut() {
+            server.messages.retain(|m| m.sender_pid != process_id);
+        }
+
+        let index = self
+            .servers
+            .iter()
+            .position(|s| s.process_id == process_id)?;
+        Some(self.servers.remove(index))
+    }
+}
+
+pub fn server_list() -> &'static SpinLock<ServerList> {
+    &SERVER_LIST
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn reply_rights_are_used_once() {
+        let mut servers = ServerList::new();
+        let sid = servers.register(1, None).unwrap();
+
+        let call = Message::call(2, 0, 7, MessageData::DEFAULT);
+        let token = call.reply_token.unwrap();
+        servers.get_by_sid(sid).unwrap().send_message(call).unwrap();
+        assert!(servers.is_call_pending(token));
+
+        // The right is only granted once the call is received
+        let server = servers.get_by_sid(sid).unwrap();
+        assert!(!server.take_reply_right(token));
+        assert_eq!(server.receive_message().unwrap().identifier, 7);
+        assert!(server.take_reply_right(token));
+        assert!(!server.take_reply_right(token));
+        assert!(!servers.is_call_pending(token));
+    }
+
+    #[test_case]
+    fn calls_fail_when_the_server_exits() {
+        let mut servers = ServerList::new();
+        let sid = servers.register(1, None).unwrap();
+
+        let call = Message::call(2, 0, 7, MessageData::DEFAULT);
+        let token = call.reply_token.unwrap();
+        servers.get_by_sid(sid).unwrap().send_message(call).unwrap();
+        servers.get_by_sid(sid).unwrap().receive_message();
+
+        servers.remove_by_pid(1);
+        assert!(!servers.is_call_pending(token));
+    }
+
+    #[test_case]
+    fn full_queues_reject_messages() {
+        let mut servers = ServerList::new();
+        let sid = servers.register(1, None).unwrap();
+        let server = servers.get_by_sid(sid).unwrap();
+        server.queue_limit = 2;
+
+        for identifier in 0..2 {
+            assert!(server
+                .send_message(Message::new(2, 0, identifier, MessageData::DEFAULT))
+                .is_ok());
+        }
+
+        let rejected = server.send_message(Message::new(2, 0, 2, MessageData::DEFAULT));
+        assert_eq!(rejected, Err(QueueFull));
+
+        // Receiving makes room again
+        assert_eq!(server.receive_message().unwrap().identifier, 0);
+        assert!(!server.is_full());
+    }
+
+    #[test_case]
+    fn names_are_unique() {
+        let mut servers = ServerList::new();
+        let sid = servers.register(1, Some("fs.ustar".into())).unwrap();
+        assert_eq!(servers.register(2, Some("fs.ustar".into())), None);
+        assert_eq!(servers.get_by_name("fs.ustar").unwrap().server_id, sid);
+
+        // The name can be taken again once the server exits
+        servers.remove_by_pid(1);
+        assert!(servers.get_by_name("fs.ustar").is_none());
+        assert!(servers.register(2, Some("fs.ustar".into())).is_some());
+    }
+}
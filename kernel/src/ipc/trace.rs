@@ -0,0 +1,156 @@
+use super::Message;
+use crate::{spinlock::SpinLock, trap::clint};
+use alloc::{collections::VecDeque, vec::Vec};
+use syscall::{TraceKind, TraceRecord};
+
+static TRACE: SpinLock<Trace> = SpinLock::new(Trace::new());
+
+/// How many records are kept until they are read, the oldest ones are dropped once there are more.
+pub const CAPACITY: usize = 256;
+
+/// Records of the messages sent to and from the servers that are traced.
+#[derive(Debug)]
+pub struct Trace {
+    servers: Vec<u64>,
+    /// The process that last changed which servers are traced, whose own messages are not recorded
+    /// so that it can print the records without tracing itself.
+    tracer: Option<usize>,
+    records: VecDeque<TraceRecord>,
+}
+
+impl Trace {
+    pub const fn new() -> Self {
+        Self {
+            servers: Vec::new(),
+            tracer: None,
+            records: VecDeque::new(),
+        }
+    }
+
+    /// Start or stop recording the messages of the given server on behalf of the tracer.
+    pub fn set_traced(&mut self, tracer: usize, server_id: u64, traced: bool) {
+        self.tracer = Some(tracer);
+        self.servers.retain(|&id| id != server_id);
+        if traced {
+            self.servers.push(server_id);
+        }
+    }
+
+    /// Record what happened to the message, if it is sent to or from a traced server.
+    /// `token` ties calls and their replies together.
+    pub fn record(
+        &mut self,
+        kind: TraceKind,
+        message: &Message,
+        receiver_sid: u64,
+        token: Option<u64>,
+    ) {
+        if !self.servers.contains(&receiver_sid) && !self.servers.contains(&message.sender_sid)
+            || self.tracer == Some(message.sender_pid)
+        {
+            return;
+        }
+
+        if self.records.len() == CAPACITY {
+            self.records.pop_front();
+        }
+
+        self.records.push_back(TraceRecord {
+            time: clint::time_since_bootup().as_nanos() as _,
+            kind: kind as _,
+            sender_pid: message.sender_pid as _,
+            sender_sid: message.sender_sid,
+            receiver_sid,
+            token: token.unwrap_or(0),
+            identifier: message.identifier,
+            data: message.data.data,
+        });
+    }
+
+    /// Record the reply to a call of the given process, unless that is the tracer which did not record its call either.
+    pub fn record_reply(&mut self, reply: &Message, caller: usize, caller_sid: u64, token: u64) {
+        if self.tracer != Some(caller) {
+            self.record(TraceKind::Reply, reply, caller_sid, Some(token));
+        }
+    }
+
+    /// Copy up to the given number of the oldest records, which are kept until they are discarded.
+    pub fn peek(&self, count: usize) -> Vec<TraceRecord> {
+        self.records.iter().take(count).copied().collect()
+    }
+
+    /// Remove up to the given number of the oldest records.
+    pub fn discard(&mut self, count: usize) {
+        let count = count.min(self.records.len());
+        self.records.drain(..count);
+    }
+}
+
+pub fn trace() -> &'static SpinLock<Trace> {
+    &TRACE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::MessageData;
+
+    fn take(trace: &mut Trace, count: usize) -> Vec<TraceRecord> {
+        let records = trace.peek(count);
+        trace.discard(records.len());
+        records
+    }
+
+    #[test_case]
+    fn only_traced_servers_are_recorded() {
+        let mut trace = Trace::new();
+        trace.set_traced(1, 3, true);
+
+        let message = Message::new(2, 0, 7, MessageData::DEFAULT);
+        trace.record(TraceKind::Send, &message, 4, None);
+        trace.record(TraceKind::Send, &message, 3, None);
+
+        let records = take(&mut trace, usize::MAX);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].receiver_sid, 3);
+        assert_eq!(records[0].kind(), Some(TraceKind::Send));
+
+        trace.set_traced(1, 3, false);
+        trace.record(TraceKind::Send, &message, 3, None);
+        assert!(take(&mut trace, usize::MAX).is_empty());
+    }
+
+    #[test_case]
+    fn old_records_are_dropped() {
+        let mut trace = Trace::new();
+        trace.set_traced(1, 3, true);
+
+        for identifier in 0..CAPACITY as u64 + 1 {
+            let message = Message::new(2, 0, identifier, MessageData::DEFAULT);
+            trace.record(TraceKind::Send, &message, 3, None);
+        }
+
+        // Records stay until they are discarded
+        assert_eq!(trace.peek(1)[0].identifier, 1);
+        let records = take(&mut trace, 2);
+        assert_eq!(records[0].identifier, 1);
+        assert_eq!(take(&mut trace, usize::MAX).len(), CAPACITY - 2);
+    }
+
+    #[test_case]
+    fn the_tracer_is_not_recorded() {
+        let mut trace = Trace::new();
+        trace.set_traced(2, 3, true);
+
+        let message = Message::new(2, 0, 7, MessageData::DEFAULT);
+        trace.record(TraceKind::Send, &message, 3, None);
+        assert!(take(&mut trace, usize::MAX).is_empty());
+
+        // Neither are the replies to its calls
+        let reply = Message::new(4, 3, 0, MessageData::DEFAULT);
+        trace.record_reply(&reply, 2, 0, 1);
+        assert!(take(&mut trace, usize::MAX).is_empty());
+        trace.record_reply(&reply, 5, 0, 1);
+        assert_eq!(take(&mut trace, usize::MAX).len(), 1);
+    }
+}
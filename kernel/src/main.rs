@@ -3,16 +3,16 @@
 #![test_runner(test::test_runner)]
 #![no_std]
 #![no_main]
-// TODO: remove together with legacy process API once the userland scheduler is functional
-#![allow(dead_code, unused_variables)]
 
 #[macro_use]
 mod uart;
+mod devicetree;
 mod elf;
-// mod ipc;
+mod hart;
+mod ipc;
 mod memory;
 mod power;
-// mod process;
+mod process;
 mod spinlock;
 mod test;
 mod thread;
@@ -22,19 +22,23 @@ extern crate alloc;
 
 use core::arch::global_asm;
 
-global_asm!(include_str!("./entry.asm"));
+global_asm!(
+    include_str!("./entry.asm"),
+    MAX_HARTS = const hart::MAX_HARTS,
+    STACK_SIZE = const hart::STACK_SIZE,
+);
 
 // Until a filesystem is implemented this is good enough for me :^)
-const INIT_ELF: &[u8] = include_bytes!("../../target/riscv64gc-unknown-none-elf/debug/init");
+const INIT_ELF: &[u8] = include_bytes!("../../target/riscv64gc-unknown-none-elf/debug/shell");
 
 #[no_mangle]
 extern "C" fn kernel_main() {
-    uart::UART.lock_with(|uart| uart.init());
-
     unsafe {
         trap::attach_supervisor_trap_vector();
         memory::init();
-        trap::plic::set_global_threshold(0);
+        trap::plic::init();
+        trap::clint::init();
+        hart::start_others();
 
         // No needs for interrupts in non-integration tests
         #[cfg(not(test))]
@@ -50,19 +54,25 @@ extern "C" fn kernel_main() {
     #[cfg(test)]
     test_entry_point();
 
-    let mut thread = thread::Thread::new();
-    let entry_point = elf::load_elf(INIT_ELF, &mut thread.page_table);
-    println!("entry point: {entry_point:#x}");
-    thread.trap_frame.user_state[thread::context::Registers::ProgramCounter] = entry_point;
-    println!("trap frame: {thread:#?}\nrunning");
-
-    thread.page_table.identity_map(
-        uart::BASE_ADDR as usize,
-        uart::BASE_ADDR as usize + 5,
-        memory::page::EntryAttributes::UserReadWrite,
+    process::scheduler::insert(
+        process::Process::new(INIT_ELF.into(), None, Some("init")).expect("init is a valid ELF"),
     );
+    process::scheduler::schedule();
+}
+
+/// The entry point of every hart other than the boot hart, these only start scheduling once the kernel is set up.
+#[no_mangle]
+extern "C" fn hart_main() {
+    hart::wait_for_boot_hart();
+
+    unsafe {
+        trap::attach_supervisor_trap_vector();
+        memory::init_hart();
+        trap::plic::init_hart();
+
+        #[cfg(not(test))]
+        trap::enable_interrupts();
+    }
 
-    memory::allocator().disable();
-    thread::set_thread_controller(thread);
-    unsafe { (*thread::thread_controller()).thread.switch_into() };
+    process::scheduler::schedule();
 }
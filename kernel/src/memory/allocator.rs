@@ -1,11 +1,14 @@
-use super::{align_page_up, sections, PAGE_SIZE, TOTAL_PAGES};
+use super::{pages_needed, PAGE_SIZE};
 use crate::spinlock::SpinLock;
 use core::{
     alloc::{GlobalAlloc, Layout},
+    mem::size_of,
     ptr,
 };
 
-#[global_allocator]
+/// The number of block sizes, the largest block spans `2^(MAX_ORDER - 1)` pages (128 MiB).
+const MAX_ORDER: usize = 16;
+
 pub static ALLOCATOR: SpinLock<Allocator> = SpinLock::new(Allocator::new());
 
 unsafe impl GlobalAlloc for SpinLock<Allocator> {
@@ -20,9 +23,34 @@ unsafe impl GlobalAlloc for SpinLock<Allocator> {
     }
 }
 
+/// Bookkeeping for a single page, only meaningful for the first page of a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PageState {
+    /// Part of a larger block, or not managed at all.
+    Unused,
+    /// The start of a free block of the given order.
+    Free(u8),
+    /// The start of an allocated block of the given order.
+    Allocated(u8),
+}
+
+/// The links of a free list, stored inside of the free block itself.
+#[repr(C)]
+struct FreeBlock {
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+/// A binary buddy allocator handing out naturally aligned blocks of `2^order` pages.
 pub struct Allocator {
-    /// Keeps track of which pages are free.
-    pages: [usize; TOTAL_PAGES],
+    /// The first page of every free block, linked together per order.
+    free_lists: [Option<usize>; MAX_ORDER],
+    /// Keeps track of the state of every page, lives at the start of the managed memory.
+    pages: *mut PageState,
+    /// The amount of pages managed by the allocator.
+    page_count: usize,
+    /// The amount of pages that are currently free.
+    free_pages: usize,
     /// The base address of the heap.
     base_addr: usize,
     /// Whether we are allowed to allocate or deallocate memory.
@@ -32,83 +60,306 @@ pub struct Allocator {
 impl Allocator {
     const fn new() -> Self {
         Self {
-            pages: [0; TOTAL_PAGES],
+            free_lists: [None; MAX_ORDER],
+            pages: ptr::null_mut(),
+            page_count: 0,
+            free_pages: 0,
             active: false,
             // Must be initialized later as we cannot access the heap symbols from a const fn.
             base_addr: 0,
         }
     }
 
+    /// Take ownership of the memory between `start` and `end`, which must be page aligned.
+    /// The bookkeeping is stored at the start of the given memory.
+    unsafe fn init(&mut self, start: usize, end: usize) {
+        let total_pages = (end - start) / PAGE_SIZE;
+        let metadata_pages = pages_needed(total_pages * size_of::<PageState>());
+
+        self.pages = start as *mut PageState;
+        self.page_count = total_pages - metadata_pages;
+        self.base_addr = start + (metadata_pages * PAGE_SIZE);
+        self.free_lists = [None; MAX_ORDER];
+        self.free_pages = 0;
+
+        for page in 0..self.page_count {
+            self.pages.add(page).write(PageState::Unused);
+        }
+
+        // Carve the memory up into the largest naturally aligned blocks that fit
+        let mut page = 0;
+        while page < self.page_count {
+            let order = (0..MAX_ORDER)
+                .rev()
+                .find(|&order| page % (1 << order) == 0 && page + (1 << order) <= self.page_count)
+                .unwrap();
+
+            self.push_free(page, order);
+            self.free_pages += 1 << order;
+            page += 1 << order;
+        }
+
+        self.active = true;
+    }
+
     const fn offset_addr_of(&self, page: usize) -> usize {
         self.base_addr + (page * PAGE_SIZE)
     }
 
-    const unsafe fn offset_id_of(&self, page: usize) -> *mut u8 {
-        self.offset_addr_of(page) as *mut u8
+    /// The page that starts at the given pointer, or `None` if it does not point to the start of a managed page.
+    fn offset_page_of(&self, ptr: *mut u8) -> Option<usize> {
+        let offset = (ptr as usize).checked_sub(self.base_addr)?;
+        let page = offset / PAGE_SIZE;
+        (offset % PAGE_SIZE == 0 && page < self.page_count).then_some(page)
     }
 
-    pub fn disable(&mut self) {
-        self.active = false;
+    /// The first page and order of the allocated block that starts at the given pointer.
+    fn allocated_block(&self, ptr: *mut u8) -> Option<(usize, usize)> {
+        let page = self.offset_page_of(ptr)?;
+        match self.state(page) {
+            PageState::Allocated(order) => Some((page, order as _)),
+            _ => None,
+        }
     }
 
-    pub fn enable(&mut self) {
-        self.active = true;
+    fn state(&self, page: usize) -> PageState {
+        assert!(page < self.page_count, "page {page} is out of bounds");
+        unsafe { self.pages.add(page).read() }
     }
 
-    fn offset_page_of(&self, ptr: *mut u8) -> usize {
-        (ptr as usize).saturating_sub(self.base_addr) / PAGE_SIZE
+    fn set_state(&mut self, page: usize, state: PageState) {
+        assert!(page < self.page_count, "page {page} is out of bounds");
+        unsafe { self.pages.add(page).write(state) }
     }
 
-    pub fn allocate(&mut self, size: usize) -> Option<*mut u8> {
-        assert!(self.active, "allocator is inactive but allocate was called");
-        let pages_needed = align_page_up(size) / PAGE_SIZE;
-        for i in 0..TOTAL_PAGES {
-            if self.pages[i] != 0 {
-                continue;
-            }
+    fn free_block(&self, page: usize) -> *mut FreeBlock {
+        self.offset_addr_of(page) as *mut FreeBlock
+    }
+
+    fn push_free(&mut self, page: usize, order: usize) {
+        let head = self.free_lists[order];
+        unsafe {
+            self.free_block(page).write(FreeBlock {
+                prev: None,
+                next: head,
+            });
 
-            // Check if the gap is big enough
-            let mut found = true;
-            for j in 0..pages_needed {
-                if *self.pages.get(i + j)? != 0 {
-                    found = false;
-                    break;
-                }
+            if let Some(head) = head {
+                (*self.free_block(head)).prev = Some(page);
             }
+        }
+
+        self.free_lists[order] = Some(page);
+        self.set_state(page, PageState::Free(order as _));
+    }
 
-            if found {
-                for j in 0..pages_needed {
-                    self.pages[i + j] = pages_needed - j;
-                }
+    fn remove_free(&mut self, page: usize, order: usize) {
+        let FreeBlock { prev, next } = unsafe { self.free_block(page).read() };
+        unsafe {
+            if let Some(next) = next {
+                (*self.free_block(next)).prev = prev;
+            }
 
-                return Some(unsafe { self.offset_id_of(i) });
+            if let Some(prev) = prev {
+                (*self.free_block(prev)).next = next;
+            } else {
+                self.free_lists[order] = next;
             }
         }
 
-        // Not enough free pages were found.
-        None
+        self.set_state(page, PageState::Unused);
+    }
+
+    fn pop_free(&mut self, order: usize) -> Option<usize> {
+        let page = self.free_lists[order]?;
+        self.remove_free(page, order);
+        Some(page)
+    }
+
+    /// The smallest order of block that can hold the given amount of bytes.
+    fn order_of(size: usize) -> usize {
+        pages_needed(size)
+            .max(1)
+            .next_power_of_two()
+            .trailing_zeros() as usize
+    }
+
+    pub fn allocate(&mut self, size: usize) -> Option<*mut u8> {
+        assert!(self.active, "allocator is inactive but allocate was called");
+        let order = Self::order_of(size);
+
+        // Find the smallest free block that is big enough
+        let mut current_order = (order..MAX_ORDER).find(|&o| self.free_lists[o].is_some())?;
+        let page = self.pop_free(current_order).unwrap();
+
+        // Split it in half until it has the requested size, freeing the upper halves
+        while current_order > order {
+            current_order -= 1;
+            self.push_free(page + (1 << current_order), current_order);
+        }
+
+        self.set_state(page, PageState::Allocated(order as _));
+        self.free_pages -= 1 << order;
+        Some(self.offset_addr_of(page) as *mut u8)
     }
 
     /// Deallocates a pointer.
     pub fn deallocate(&mut self, ptr: *mut u8) {
-        assert!(self.active, "allocator is inactive but deallocate was called");
-        let id = self.offset_page_of(ptr);
-        let page_stride = self.pages.get(id).cloned().unwrap();
-        for i in 0..page_stride {
-            self.pages[id + i] = 0;
+        assert!(
+            self.active,
+            "allocator is inactive but deallocate was called"
+        );
+
+        let Some((mut page, mut order)) = self.allocated_block(ptr) else {
+            panic!("attempted to deallocate a pointer that was not allocated: {ptr:?}");
+        };
+
+        self.free_pages += 1 << order;
+
+        // Merge with our buddy for as long as it is free as well
+        while order < MAX_ORDER - 1 {
+            let buddy = page ^ (1 << order);
+            if buddy >= self.page_count || self.state(buddy) != PageState::Free(order as _) {
+                break;
+            }
+
+            self.remove_free(buddy, order);
+            page = page.min(buddy);
+            order += 1;
         }
+
+        self.push_free(page, order);
     }
 
-    // pub fn size_of(&self, ptr: *mut u8) -> usize {
-    //     let id = self.offset_page_of(ptr);
-    //     self.pages[id] * PAGE_SIZE
-    // }
+    /// The size of the allocation starting at the given pointer in bytes, or zero if it was not allocated.
+    pub fn size_of(&self, ptr: *mut u8) -> usize {
+        self.allocated_block(ptr)
+            .map_or(0, |(_, order)| PAGE_SIZE << order)
+    }
+
+    /// The amount of pages that are not allocated.
+    pub const fn free_pages(&self) -> usize {
+        self.free_pages
+    }
 }
 
 // TODO: use core::cell::OnceCell once it is stabilized.
-pub unsafe fn init() {
-    ALLOCATOR.lock_with(|alloc| {
-        alloc.base_addr = align_page_up(sections::heap_start());
-        alloc.enable();
-    });
+pub unsafe fn init(start: usize, end: usize) {
+    ALLOCATOR.lock_with(|alloc| alloc.init(start, end));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::is_page_aligned;
+
+    const REGION_PAGES: usize = 64;
+
+    /// Run the given function with an allocator that manages a fresh region of `REGION_PAGES` pages.
+    fn with_allocator<F>(f: F)
+    where
+        F: FnOnce(&mut Allocator),
+    {
+        // Reserve one extra page for the bookkeeping
+        let size = (REGION_PAGES + 1) * PAGE_SIZE;
+        let region = ALLOCATOR.lock().allocate(size).unwrap();
+
+        let mut alloc = Allocator::new();
+        unsafe { alloc.init(region as usize, region as usize + size) };
+        assert_eq!(alloc.free_pages(), REGION_PAGES);
+        f(&mut alloc);
+
+        ALLOCATOR.lock().deallocate(region);
+    }
+
+    #[test_case]
+    fn rounds_up_to_power_of_two() {
+        with_allocator(|alloc| {
+            let ptr = alloc.allocate(3 * PAGE_SIZE).unwrap();
+            assert_eq!(alloc.size_of(ptr), 4 * PAGE_SIZE);
+            assert_eq!(alloc.free_pages(), REGION_PAGES - 4);
+            assert!(is_page_aligned(ptr as usize));
+
+            alloc.deallocate(ptr);
+            assert_eq!(alloc.free_pages(), REGION_PAGES);
+        });
+    }
+
+    #[test_case]
+    fn splits_without_wasting_memory() {
+        with_allocator(|alloc| {
+            let small = alloc.allocate(PAGE_SIZE).unwrap();
+            let large = alloc.allocate((REGION_PAGES / 2) * PAGE_SIZE).unwrap();
+            assert_ne!(small, large);
+            assert_eq!(alloc.free_pages(), (REGION_PAGES / 2) - 1);
+
+            alloc.deallocate(small);
+            alloc.deallocate(large);
+            assert_eq!(alloc.free_pages(), REGION_PAGES);
+        });
+    }
+
+    #[test_case]
+    fn fragmentation_prevents_contiguous_allocation() {
+        with_allocator(|alloc| {
+            let mut pages = [ptr::null_mut(); REGION_PAGES];
+            for page in pages.iter_mut() {
+                *page = alloc.allocate(PAGE_SIZE).unwrap();
+            }
+            assert!(alloc.allocate(PAGE_SIZE).is_none());
+
+            // Free every other page, none of the free pages are buddies
+            for page in pages.iter().step_by(2) {
+                alloc.deallocate(*page);
+            }
+            assert_eq!(alloc.free_pages(), REGION_PAGES / 2);
+            assert!(alloc.allocate(2 * PAGE_SIZE).is_none());
+
+            // Freeing the rest should coalesce everything back into a single block
+            for page in pages.iter().skip(1).step_by(2) {
+                alloc.deallocate(*page);
+            }
+            let ptr = alloc.allocate(REGION_PAGES * PAGE_SIZE).unwrap();
+            alloc.deallocate(ptr);
+        });
+    }
+
+    #[test_case]
+    fn only_block_starts_are_allocated() {
+        with_allocator(|alloc| {
+            let ptr = alloc.allocate(2 * PAGE_SIZE).unwrap();
+            let free = alloc.allocate(PAGE_SIZE).unwrap();
+            alloc.deallocate(free);
+
+            // Neither the middle of a block nor memory outside of the heap belongs to an allocation
+            assert_eq!(alloc.size_of(ptr), 2 * PAGE_SIZE);
+            assert_eq!(alloc.size_of(ptr.wrapping_add(8)), 0);
+            assert_eq!(alloc.size_of(ptr.wrapping_add(PAGE_SIZE)), 0);
+            assert_eq!(alloc.size_of(free), 0);
+            assert_eq!(alloc.size_of(ptr::null_mut()), 0);
+            assert_eq!(alloc.size_of(ptr.wrapping_sub(PAGE_SIZE)), 0);
+            assert_eq!(alloc.size_of(ptr.wrapping_add(REGION_PAGES * PAGE_SIZE)), 0);
+
+            alloc.deallocate(ptr);
+            assert_eq!(alloc.free_pages(), REGION_PAGES);
+        });
+    }
+
+    #[test_case]
+    fn coalesces_in_any_order() {
+        with_allocator(|alloc| {
+            let mut blocks = [ptr::null_mut(); 8];
+            for block in blocks.iter_mut() {
+                *block = alloc.allocate(8 * PAGE_SIZE).unwrap();
+            }
+
+            for index in [5, 2, 7, 0, 3, 6, 1, 4] {
+                alloc.deallocate(blocks[index]);
+            }
+
+            assert_eq!(alloc.free_pages(), REGION_PAGES);
+            let ptr = alloc.allocate(REGION_PAGES * PAGE_SIZE).unwrap();
+            alloc.deallocate(ptr);
+        });
+    }
 }
@@ -1,34 +1,50 @@
 mod allocator;
 pub mod page;
 pub mod sections;
+pub mod shared;
+pub mod slab;
 
-use crate::spinlock::SpinLockGuard;
+use crate::{devicetree, spinlock::SpinLockGuard};
 
 const PAGE_ORDER: usize = 12;
 pub const PAGE_SIZE: usize = 1 << PAGE_ORDER; // 4 KiB
 
-// TODO: Assuming 128 MiB of memory as qemu uses that.
-const TOTAL_PAGES: usize = (128 * (1024 * 1024)) / PAGE_SIZE;
-
 pub fn allocator() -> SpinLockGuard<'static, allocator::Allocator> {
     allocator::ALLOCATOR.lock()
 }
 
 pub unsafe fn init() {
+    let heap_start = align_page_up(sections::heap_start());
+    let heap_end = align_page_down(devicetree::heap_end(heap_start));
+
     println!("initializing allocator...");
-    allocator::init();
-    println!("allocator initialized");
+    allocator::init(heap_start, heap_end);
+    println!(
+        "allocator initialized with {} free pages",
+        allocator().free_pages()
+    );
 
     println!("mapping kernel sections...");
     // Some funky unsafe magic to get around the borrow checker
     let mut root_table = page::root_table();
-    sections::map_kernel(&mut root_table);
+    sections::map_kernel(&mut root_table, heap_end);
     println!("succesfully mapped kernel sections");
 
-    // TODO: This must be called for every hart, will need to be moved later
     println!("enabling paging...");
     page::init(&root_table);
     println!("paging enabled");
+
+    for cache in slab::statistics().filter(|cache| cache.total_allocations != 0) {
+        println!(
+            "{}-byte objects: {} in use on {} slabs after {} allocations",
+            cache.object_size, cache.objects_in_use, cache.slabs, cache.total_allocations
+        );
+    }
+}
+
+/// Enable paging on a hart other than the boot hart, once `init` has set up the kernels page table.
+pub unsafe fn init_hart() {
+    page::init(&page::root_table());
 }
 
 /// Align an address to upper bound according to specified order.
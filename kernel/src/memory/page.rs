@@ -1,6 +1,6 @@
 use super::{align_page_down, align_page_up, PAGE_SIZE};
 use crate::spinlock::{SpinLock, SpinLockGuard};
-use alloc::boxed::Box;
+use alloc::{boxed::Box, vec::Vec};
 use core::{arch::asm, mem::size_of, ptr::read_volatile};
 
 pub static KERNEL_PAGE_TABLE: SpinLock<Table> = SpinLock::new(Table::new());
@@ -20,18 +20,6 @@ pub fn init(root_table: &Table) {
 #[repr(transparent)]
 pub struct Page(pub [u8; PAGE_SIZE]);
 
-impl Page {
-    pub const fn new() -> Self {
-        Self([0; PAGE_SIZE])
-    }
-
-    pub fn from_slice(slice: &[u8]) -> Self {
-        let mut page = Self::new();
-        page.0[..slice.len()].copy_from_slice(slice);
-        page
-    }
-}
-
 /// <https://five-embeddev.com/riscv-isa-manual/latest/supervisor.html#sec:translation>
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
@@ -75,6 +63,11 @@ impl Entry {
         self.0 & 0xe != 0
     }
 
+    const fn is_user_writable(&self) -> bool {
+        let flags = EntryAttributes::Writable as usize | EntryAttributes::User as usize;
+        self.0 & flags == flags
+    }
+
     const fn paddr(&self) -> usize {
         (self.0 & !0x3ff) << 2
     }
@@ -179,7 +172,8 @@ impl Table {
         }
     }
 
-    pub fn physical_addr(&self, vaddr: usize) -> Option<usize> {
+    /// The leaf entry that maps the given address.
+    fn leaf(&self, vaddr: usize) -> Option<&Entry> {
         let vpn = VirtualPageNumber(vaddr);
         let mut v = &self.entries[vpn.vpn2()];
 
@@ -196,7 +190,57 @@ impl Table {
             };
         }
 
-        Some(v.paddr() + (vaddr % PAGE_SIZE))
+        v.is_valid().then_some(v)
+    }
+
+    pub fn physical_addr(&self, vaddr: usize) -> Option<usize> {
+        self.leaf(vaddr)
+            .map(|entry| entry.paddr() + (vaddr % PAGE_SIZE))
+    }
+
+    /// Copy `len` bytes starting at `vaddr` out of this address space,
+    /// the backing pages do not need to be physically contiguous.
+    pub fn copy_from(&self, vaddr: usize, len: usize) -> Option<Vec<u8>> {
+        let mut result = Vec::with_capacity(len);
+        let end = vaddr + len;
+        let mut addr = vaddr;
+
+        while addr < end {
+            let chunk_len = (align_page_down(addr) + PAGE_SIZE).min(end) - addr;
+            let paddr = self.physical_addr(addr)?;
+            result.extend_from_slice(unsafe {
+                core::slice::from_raw_parts(paddr as *const u8, chunk_len)
+            });
+            addr += chunk_len;
+        }
+
+        Some(result)
+    }
+
+    /// Copy `data` into this address space starting at `vaddr`. Nothing is copied
+    /// unless every page of the destination is mapped and writable from user mode.
+    pub fn copy_to(&self, vaddr: usize, data: &[u8]) -> Option<()> {
+        let end = vaddr.checked_add(data.len())?;
+        let writable = (align_page_down(vaddr)..end)
+            .step_by(PAGE_SIZE)
+            .all(|page| self.leaf(page).is_some_and(Entry::is_user_writable));
+
+        if !writable {
+            return None;
+        }
+
+        let mut addr = vaddr;
+        while addr < end {
+            let chunk_len = (align_page_down(addr) + PAGE_SIZE).min(end) - addr;
+            let chunk = &data[addr - vaddr..][..chunk_len];
+            let paddr = self.physical_addr(addr)?;
+            unsafe {
+                core::ptr::copy_nonoverlapping(chunk.as_ptr(), paddr as *mut u8, chunk_len);
+            }
+            addr += chunk_len;
+        }
+
+        Some(())
     }
 
     pub fn unmap(&mut self, vaddr: usize) {
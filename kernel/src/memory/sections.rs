@@ -1,14 +1,5 @@
 use super::{page, PAGE_SIZE};
-use crate::{
-    power,
-    trap::{clint, plic},
-    uart,
-};
-use core::mem::size_of;
-
-// TODO: dont hardcode this into the kernel
-const VIRTIO_START: usize = 0x10001000;
-const VIRTIO_END: usize = 0x10008000;
+use crate::{devicetree, power};
 
 /// Generate a safe wrapper to access a linker section.
 macro_rules! section {
@@ -26,7 +17,6 @@ macro_rules! section {
 }
 
 section!(heap_start, _heap_start);
-section!(heap_end, _heap_end);
 
 section!(text_start, _text_start);
 section!(text_end, _text_end);
@@ -58,8 +48,8 @@ pub fn map_trampoline(page_table: &mut page::Table) {
     );
 }
 
-/// Map the kernel sections into the given page table.
-pub fn map_kernel(page_table: &mut page::Table) {
+/// Map the kernel sections into the given page table, with the heap ending at `heap_end`.
+pub fn map_kernel(page_table: &mut page::Table, heap_end: usize) {
     // Map the linker sections
     map_trampoline(page_table);
 
@@ -73,30 +63,10 @@ pub fn map_kernel(page_table: &mut page::Table) {
     page_table.identity_map(data_start(), data_end(), page::EntryAttributes::ReadWrite);
     page_table.identity_map(bss_start(), bss_end(), page::EntryAttributes::ReadWrite);
     page_table.identity_map(stack_start(), stack_end(), page::EntryAttributes::ReadWrite);
-    page_table.identity_map(heap_start(), heap_end(), page::EntryAttributes::ReadWrite);
+    page_table.identity_map(heap_start(), heap_end - 1, page::EntryAttributes::ReadWrite);
 
-    // Map peripherals devices. TODO: Could be prettier.
-    page_table.identity_map(
-        plic::BASE_ADDR,
-        plic::BASE_ADDR + 0x400000,
-        page::EntryAttributes::ReadWrite,
-    );
-
-    page_table.identity_map(
-        clint::MTIME,
-        clint::MTIME + size_of::<u64>(),
-        page::EntryAttributes::ReadWrite,
-    );
-
-    // TODO: We only need to map this into the kernel so that we can identity map it into userspace drivers.
-    // How do we avoid mapping it into the kernel at all, if we need to be able to resolve the physcial address?
-    page_table.identity_map(VIRTIO_START, VIRTIO_END, page::EntryAttributes::ReadWrite);
-
-    page_table.map_page(
-        uart::BASE_ADDR as _,
-        uart::BASE_ADDR as _,
-        page::EntryAttributes::ReadWrite,
-    );
+    // Map the peripheral devices
+    devicetree::map(page_table);
 
     page_table.map_page(
         power::BASE_ADDR,
@@ -113,7 +83,6 @@ mod tests {
     #[test_case]
     fn symbols_exist() {
         assert!(heap_start() > 0);
-        assert!(heap_end() > 0);
         assert!(text_start() > 0);
         assert!(text_end() > 0);
         assert!(rodata_start() > 0);
@@ -0,0 +1,69 @@
+use super::{allocator, pages_needed, PAGE_SIZE};
+use alloc::vec::Vec;
+
+/// Zero-filled memory that can be mapped into several address spaces at once.
+/// It is shared through an `Arc`, so its frames are freed once the last handle and mapping is gone.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SharedMemory {
+    frames: Vec<usize>,
+}
+
+impl SharedMemory {
+    /// Allocate enough pages for `size` bytes, or `None` if there is not enough memory.
+    pub fn new(size: usize) -> Option<Self> {
+        let mut memory = Self {
+            frames: Vec::with_capacity(pages_needed(size)),
+        };
+
+        for _ in 0..pages_needed(size) {
+            // The frames that were allocated so far are freed when `memory` is dropped
+            let frame = allocator().allocate(PAGE_SIZE)?;
+            unsafe { core::ptr::write_bytes(frame, 0, PAGE_SIZE) };
+            memory.frames.push(frame as _);
+        }
+
+        Some(memory)
+    }
+
+    /// The size in bytes, which is always a multiple of the page size.
+    pub fn size(&self) -> usize {
+        self.frames.len() * PAGE_SIZE
+    }
+
+    /// The frame backing the page at the given offset.
+    pub fn frame(&self, offset: usize) -> Option<usize> {
+        self.frames.get(offset / PAGE_SIZE).copied()
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        let mut alloc = allocator();
+        for frame in self.frames.drain(..) {
+            alloc.deallocate(frame as _);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn frees_frames_when_dropped() {
+        let free_pages = allocator().free_pages();
+
+        let memory = SharedMemory::new(PAGE_SIZE + 1).unwrap();
+        assert_eq!(memory.size(), 2 * PAGE_SIZE);
+        let frame = memory.frame(PAGE_SIZE).unwrap();
+        assert!(
+            unsafe { core::slice::from_raw_parts(frame as *const u8, PAGE_SIZE) }
+                .iter()
+                .all(|&b| b == 0)
+        );
+        assert_eq!(memory.frame(2 * PAGE_SIZE), None);
+
+        drop(memory);
+        assert_eq!(allocator().free_pages(), free_pages);
+    }
+}
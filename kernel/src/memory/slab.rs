@@ -0,0 +1,314 @@
+//! Size-class caches serving allocations smaller than a page, layered on top of the page allocator.
+
+use super::{align_page_down, allocator::ALLOCATOR, PAGE_SIZE};
+use crate::spinlock::SpinLock;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem::size_of,
+    ptr::{self, NonNull},
+};
+
+/// The object sizes served by the caches, anything larger goes straight to the page allocator.
+const SIZE_CLASSES: [usize; 7] = [16, 32, 64, 128, 256, 512, 1024];
+
+#[global_allocator]
+static SLAB_ALLOCATOR: SlabAllocator = SlabAllocator::new();
+
+pub struct SlabAllocator {
+    caches: [SpinLock<Cache>; SIZE_CLASSES.len()],
+}
+
+impl SlabAllocator {
+    const fn new() -> Self {
+        Self {
+            caches: [
+                SpinLock::new(Cache::new(SIZE_CLASSES[0])),
+                SpinLock::new(Cache::new(SIZE_CLASSES[1])),
+                SpinLock::new(Cache::new(SIZE_CLASSES[2])),
+                SpinLock::new(Cache::new(SIZE_CLASSES[3])),
+                SpinLock::new(Cache::new(SIZE_CLASSES[4])),
+                SpinLock::new(Cache::new(SIZE_CLASSES[5])),
+                SpinLock::new(Cache::new(SIZE_CLASSES[6])),
+            ],
+        }
+    }
+
+    /// The cache serving the given layout, if it fits into any of them.
+    fn cache_for(&self, layout: Layout) -> Option<&SpinLock<Cache>> {
+        // Objects are aligned to their size, so this covers the alignment as well
+        let size = layout.size().max(layout.align());
+        SIZE_CLASSES
+            .iter()
+            .position(|&class| size <= class)
+            .map(|index| &self.caches[index])
+    }
+}
+
+unsafe impl GlobalAlloc for SlabAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if let Some(cache) = self.cache_for(layout) {
+            cache.lock().allocate().unwrap_or(ptr::null_mut())
+        } else {
+            ALLOCATOR.alloc(layout)
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(cache) = self.cache_for(layout) {
+            cache.lock().deallocate(ptr);
+        } else {
+            ALLOCATOR.dealloc(ptr, layout);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Statistics {
+    /// The size of the objects served by the cache.
+    pub object_size: usize,
+    /// The amount of pages currently owned by the cache.
+    pub slabs: usize,
+    /// The amount of objects currently handed out.
+    pub objects_in_use: usize,
+    /// The amount of allocations served over the lifetime of the cache.
+    pub total_allocations: usize,
+}
+
+/// A free object, linked together with the other free objects of the same slab.
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+/// The header of a single page split up into objects, stored at the start of the page itself.
+#[repr(C)]
+struct Slab {
+    prev: Option<NonNull<Slab>>,
+    next: Option<NonNull<Slab>>,
+    free: Option<NonNull<FreeObject>>,
+    in_use: usize,
+}
+
+/// A cache of equally sized objects.
+pub struct Cache {
+    /// Slabs that have at least one free object.
+    partial: Option<NonNull<Slab>>,
+    statistics: Statistics,
+}
+
+impl Cache {
+    const fn new(object_size: usize) -> Self {
+        assert!(object_size.is_power_of_two() && object_size >= size_of::<FreeObject>());
+
+        Self {
+            partial: None,
+            statistics: Statistics {
+                object_size,
+                slabs: 0,
+                objects_in_use: 0,
+                total_allocations: 0,
+            },
+        }
+    }
+
+    /// The offset of the first object in a slab, placed after the header while keeping it aligned to its size.
+    const fn first_object_offset(&self) -> usize {
+        (size_of::<Slab>() + self.statistics.object_size - 1) & !(self.statistics.object_size - 1)
+    }
+
+    /// The amount of objects that fit into a single slab.
+    const fn capacity(&self) -> usize {
+        (PAGE_SIZE - self.first_object_offset()) / self.statistics.object_size
+    }
+
+    fn push_partial(&mut self, mut slab: NonNull<Slab>) {
+        unsafe {
+            slab.as_mut().prev = None;
+            slab.as_mut().next = self.partial;
+            if let Some(mut head) = self.partial {
+                head.as_mut().prev = Some(slab);
+            }
+        }
+
+        self.partial = Some(slab);
+    }
+
+    fn remove_partial(&mut self, slab: NonNull<Slab>) {
+        let Slab { prev, next, .. } = unsafe { slab.as_ptr().read() };
+        unsafe {
+            if let Some(mut next) = next {
+                next.as_mut().prev = prev;
+            }
+
+            if let Some(mut prev) = prev {
+                prev.as_mut().next = next;
+            } else {
+                self.partial = next;
+            }
+        }
+    }
+
+    /// Request a new page from the page allocator and split it up into objects.
+    fn grow(&mut self) -> Option<NonNull<Slab>> {
+        let page = ALLOCATOR.lock().allocate(PAGE_SIZE)?;
+
+        let mut free = None;
+        for index in (0..self.capacity()).rev() {
+            let offset = self.first_object_offset() + (index * self.statistics.object_size);
+            let object = unsafe { page.add(offset) } as *mut FreeObject;
+            unsafe { object.write(FreeObject { next: free }) };
+            free = NonNull::new(object);
+        }
+
+        let slab = page as *mut Slab;
+        unsafe {
+            slab.write(Slab {
+                prev: None,
+                next: None,
+                free,
+                in_use: 0,
+            })
+        };
+
+        let slab = NonNull::new(slab)?;
+        self.push_partial(slab);
+        self.statistics.slabs += 1;
+        Some(slab)
+    }
+
+    pub fn allocate(&mut self) -> Option<*mut u8> {
+        let mut slab = match self.partial {
+            Some(slab) => slab,
+            None => self.grow()?,
+        };
+
+        let object = {
+            let slab = unsafe { slab.as_mut() };
+            let object = slab.free.expect("partial slab without free objects");
+            slab.free = unsafe { object.as_ref().next };
+            slab.in_use += 1;
+            object
+        };
+
+        // Full slabs are only found again once one of their objects is freed
+        if unsafe { slab.as_ref().free.is_none() } {
+            self.remove_partial(slab);
+        }
+
+        self.statistics.objects_in_use += 1;
+        self.statistics.total_allocations += 1;
+        Some(object.as_ptr() as *mut u8)
+    }
+
+    pub fn deallocate(&mut self, ptr: *mut u8) {
+        let mut slab = NonNull::new(align_page_down(ptr as usize) as *mut Slab).unwrap();
+        let (was_full, now_empty, is_only_slab) = unsafe {
+            let slab = slab.as_mut();
+            let was_full = slab.free.is_none();
+
+            let object = ptr as *mut FreeObject;
+            object.write(FreeObject { next: slab.free });
+            slab.free = NonNull::new(object);
+            slab.in_use -= 1;
+
+            (
+                was_full,
+                slab.in_use == 0,
+                slab.prev.is_none() && slab.next.is_none(),
+            )
+        };
+
+        if was_full {
+            self.push_partial(slab);
+        } else if now_empty && !is_only_slab {
+            // Keep a single empty slab around, so that we do not thrash the page allocator
+            self.remove_partial(slab);
+            ALLOCATOR.lock().deallocate(slab.as_ptr() as _);
+            self.statistics.slabs -= 1;
+        }
+
+        self.statistics.objects_in_use -= 1;
+    }
+
+    pub const fn statistics(&self) -> Statistics {
+        self.statistics
+    }
+}
+
+/// Return the slabs to the page allocator, which requires every object to be freed as full slabs are not tracked.
+impl Drop for Cache {
+    fn drop(&mut self) {
+        assert_eq!(
+            self.statistics.objects_in_use, 0,
+            "cache dropped while in use"
+        );
+
+        while let Some(slab) = self.partial {
+            self.remove_partial(slab);
+            ALLOCATOR.lock().deallocate(slab.as_ptr() as _);
+        }
+    }
+}
+
+/// The statistics of every cache, from the smallest to the largest object size.
+pub fn statistics() -> impl Iterator<Item = Statistics> {
+    SLAB_ALLOCATOR
+        .caches
+        .iter()
+        .map(|cache| cache.lock().statistics())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn objects_are_aligned_and_distinct() {
+        let free_pages = ALLOCATOR.lock().free_pages();
+        let mut cache = Cache::new(64);
+        let mut objects = [ptr::null_mut(); 128];
+        for object in objects.iter_mut() {
+            *object = cache.allocate().unwrap();
+            assert_eq!(*object as usize % 64, 0);
+        }
+
+        for (i, a) in objects.iter().enumerate() {
+            assert!(objects[i + 1..].iter().all(|b| a != b));
+        }
+
+        let slabs = (objects.len() + cache.capacity() - 1) / cache.capacity();
+        assert_eq!(cache.statistics().slabs, slabs);
+        assert_eq!(cache.statistics().objects_in_use, objects.len());
+
+        objects.iter().for_each(|object| cache.deallocate(*object));
+        assert_eq!(cache.statistics().objects_in_use, 0);
+        assert_eq!(cache.statistics().slabs, 1);
+
+        drop(cache);
+        assert_eq!(ALLOCATOR.lock().free_pages(), free_pages);
+    }
+
+    #[test_case]
+    fn statistics_cover_every_cache() {
+        let object = alloc::boxed::Box::new([0u8; 48]);
+        assert!(statistics().map(|cache| cache.object_size).eq(SIZE_CLASSES));
+
+        let cache = statistics().find(|cache| cache.object_size == 64).unwrap();
+        assert!(cache.objects_in_use >= 1);
+        drop(object);
+    }
+
+    #[test_case]
+    fn reuses_freed_objects() {
+        let free_pages = ALLOCATOR.lock().free_pages();
+        let mut cache = Cache::new(1024);
+        let first = cache.allocate().unwrap();
+        cache.deallocate(first);
+        assert_eq!(cache.allocate().unwrap(), first);
+        assert_eq!(cache.statistics().total_allocations, 2);
+        assert_eq!(cache.statistics().slabs, 1);
+
+        cache.deallocate(first);
+        drop(cache);
+        assert_eq!(ALLOCATOR.lock().free_pages(), free_pages);
+    }
+}
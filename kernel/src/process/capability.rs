@@ -0,0 +1,190 @@
+use crate::memory::shared::SharedMemory;
+use alloc::{sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+use syscall::Rights;
+
+static NEXT_CAPABILITY_ID: AtomicU64 = AtomicU64::new(1);
+
+/// What a capability refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Object {
+    /// The endpoint of the server with the given ID.
+    Server(u64),
+    Memory(Arc<SharedMemory>),
+}
+
+/// The right to use an object, processes can only message servers and map memory they hold a capability for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capability {
+    pub object: Object,
+    pub rights: Rights,
+    /// Unique among all capabilities, revoking a capability removes every capability derived from it.
+    pub id: u64,
+    /// The IDs of the capabilities this one was derived from, starting with the one of the server itself.
+    ancestors: Vec<u64>,
+}
+
+impl Capability {
+    fn new(object: Object, rights: Rights) -> Self {
+        Self {
+            object,
+            rights,
+            id: NEXT_CAPABILITY_ID.fetch_add(1, Ordering::Relaxed),
+            ancestors: Vec::new(),
+        }
+    }
+
+    /// The capability a server holds for its own endpoint, with all rights.
+    pub fn server(server_id: u64) -> Self {
+        Self::new(
+            Object::Server(server_id),
+            Rights::SEND.union(Rights::RECEIVE).union(Rights::GRANT),
+        )
+    }
+
+    /// The capability for newly created shared memory, which may be mapped readable and writable.
+    pub fn memory(memory: SharedMemory) -> Self {
+        Self::new(
+            Object::Memory(Arc::new(memory)),
+            Rights::READ.union(Rights::WRITE).union(Rights::GRANT),
+        )
+    }
+
+    /// A copy of this capability with at most the given rights, or `None` if it lacks the right to be granted.
+    /// The receive right always stays with the server.
+    pub fn derive(&self, rights: Rights) -> Option<Self> {
+        if !self.rights.contains(Rights::GRANT) {
+            return None;
+        }
+
+        let mut ancestors = self.ancestors.clone();
+        ancestors.push(self.id);
+
+        Some(Self {
+            object: self.object.clone(),
+            rights: self.rights.intersection(rights).difference(Rights::RECEIVE),
+            id: NEXT_CAPABILITY_ID.fetch_add(1, Ordering::Relaxed),
+            ancestors,
+        })
+    }
+
+    pub fn is_derived_from(&self, id: u64) -> bool {
+        self.ancestors.contains(&id)
+    }
+
+    /// The IDs of the capabilities this one was derived from.
+    pub fn ancestors(&self) -> &[u64] {
+        &self.ancestors
+    }
+}
+
+/// The capabilities of a process, which are shared between all of its threads.
+/// Handles are indices into this table, and are reused once they are closed.
+#[derive(Debug, Default)]
+pub struct CapabilityTable {
+    slots: Vec<Option<Capability>>,
+}
+
+impl CapabilityTable {
+    /// Add a capability, returning its handle.
+    pub fn insert(&mut self, capability: Capability) -> u64 {
+        if let Some(index) = self.slots.iter().position(Option::is_none) {
+            self.slots[index] = Some(capability);
+            index as _
+        } else {
+            self.slots.push(Some(capability));
+            self.slots.len() as u64 - 1
+        }
+    }
+
+    pub fn get(&self, handle: u64) -> Option<&Capability> {
+        self.slots.get(usize::try_from(handle).ok()?)?.as_ref()
+    }
+
+    /// The server the given handle refers to, if the handle has all of the given rights.
+    pub fn server_with(&self, handle: u64, rights: Rights) -> Option<u64> {
+        match self.get(handle)? {
+            Capability {
+                object: Object::Server(server_id),
+                rights: held,
+                ..
+            } if held.contains(rights) => Some(*server_id),
+            _ => None,
+        }
+    }
+
+    /// The capability for the shared memory the given handle refers to, if the handle has all of the given rights.
+    pub fn memory_with(&self, handle: u64, rights: Rights) -> Option<&Capability> {
+        self.get(handle).filter(|capability| {
+            matches!(capability.object, Object::Memory(_)) && capability.rights.contains(rights)
+        })
+    }
+
+    pub fn remove(&mut self, handle: u64) -> Option<Capability> {
+        self.slots.get_mut(usize::try_from(handle).ok()?)?.take()
+    }
+
+    /// Remove every capability that was derived from the one with the given ID.
+    pub fn revoke(&mut self, id: u64) {
+        for slot in &mut self.slots {
+            if slot.as_ref().is_some_and(|c| c.is_derived_from(id)) {
+                *slot = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn derived_capabilities_lose_rights() {
+        let server = Capability::server(1);
+        let client = server.derive(Rights::SEND).unwrap();
+        assert_eq!(client.rights, Rights::SEND);
+        assert!(client.derive(Rights::SEND).is_none());
+
+        // The receive right is never granted
+        let granted = server.derive(Rights::ALL).unwrap();
+        assert_eq!(granted.rights, Rights::SEND.union(Rights::GRANT));
+    }
+
+    #[test_case]
+    fn revoke_removes_derived_handles() {
+        let server = Capability::server(1);
+        let client = server.derive(Rights::ALL).unwrap();
+        let grandchild = client.derive(Rights::SEND).unwrap();
+
+        let mut table = CapabilityTable::default();
+        let server_handle = table.insert(server.clone());
+        let client_handle = table.insert(client);
+        let grandchild_handle = table.insert(grandchild);
+
+        table.revoke(server.id);
+        assert!(table.get(server_handle).is_some());
+        assert!(table.get(client_handle).is_none());
+        assert!(table.get(grandchild_handle).is_none());
+
+        // Freed handles are reused
+        assert_eq!(table.insert(Capability::server(2)), client_handle);
+        assert_eq!(table.server_with(client_handle, Rights::RECEIVE), Some(2));
+    }
+
+    #[test_case]
+    fn memory_can_be_shared_read_only() {
+        let owner = Capability::memory(SharedMemory::new(1).unwrap());
+        let reader = owner.derive(Rights::READ.union(Rights::GRANT)).unwrap();
+
+        let mut table = CapabilityTable::default();
+        let owner_handle = table.insert(owner);
+        let reader_handle = table.insert(reader);
+
+        assert!(table.memory_with(owner_handle, Rights::WRITE).is_some());
+        assert!(table.memory_with(reader_handle, Rights::READ).is_some());
+        assert!(table.memory_with(reader_handle, Rights::WRITE).is_none());
+
+        // Memory is no endpoint
+        assert_eq!(table.server_with(owner_handle, Rights::NONE), None);
+    }
+}
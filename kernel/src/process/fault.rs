@@ -0,0 +1,39 @@
+use super::{scheduler, ProcessState};
+use crate::{ipc, thread::region::Access, trap::plic};
+use core::fmt;
+use syscall::{ExitStatus, KillReason};
+
+/// Try to resolve a page fault raised by the current process by populating the faulting page.
+/// Returns whether the process can retry the access.
+pub fn handle_page_fault(vaddr: usize, access: Access) -> bool {
+    let mut procs = scheduler::PROCESSES.lock();
+    let proc = procs.current().unwrap();
+    let resolved = proc.thread.address_space().handle_page_fault(vaddr, access);
+    resolved
+}
+
+/// Terminate the current process after it raised an exception that cannot be resolved,
+/// and print a report of its state at the time of the fault.
+pub fn kill_current(cause: impl fmt::Debug, code: usize, stval: usize, sepc: usize) {
+    let mut procs = scheduler::PROCESSES.lock();
+    let status = ExitStatus::Killed(KillReason::Exception(code as _));
+    let proc = procs.remove_current(status).unwrap();
+    ipc::server_list().lock().remove_by_pid(proc.pid);
+
+    // Release the interrupts of a faulting driver, including the one it was handling, which would stay claimed forever
+    if let ProcessState::HandlingInterrupt { interrupt_id, .. } = proc.state {
+        plic::complete(interrupt_id);
+    }
+    plic::try_remove_user(proc.pid);
+
+    println!(
+        "process {} caused an unhandled {cause:?} at {sepc:#x}, stval={stval:#x}. Killing process",
+        proc.pid
+    );
+    if let Some(region) = proc.thread.address_space().regions.find(stval) {
+        println!("    stval lies in {region:?}");
+    }
+    for (name, value) in proc.thread.trap_frame.user_state.iter_names() {
+        println!("    {name:?}: {value:#x}");
+    }
+}
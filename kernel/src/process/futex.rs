@@ -0,0 +1,68 @@
+//! Wait queues of threads blocked on a futex. Futexes are keyed on the physical address of their word,
+//! so that processes which share memory also share its futexes.
+
+use crate::spinlock::SpinLock;
+use alloc::collections::{BTreeMap, VecDeque};
+
+pub static FUTEXES: SpinLock<WaitQueues> = SpinLock::new(WaitQueues::new());
+
+pub struct WaitQueues {
+    /// The IDs of the threads waiting on every futex, in the order they started waiting.
+    queues: BTreeMap<usize, VecDeque<usize>>,
+}
+
+impl WaitQueues {
+    const fn new() -> Self {
+        Self {
+            queues: BTreeMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, paddr: usize, tid: usize) {
+        self.queues.entry(paddr).or_default().push_back(tid);
+    }
+
+    /// Take the thread that has been waiting on the given futex for the longest time.
+    pub fn pop(&mut self, paddr: usize) -> Option<usize> {
+        let queue = self.queues.get_mut(&paddr)?;
+        let tid = queue.pop_front();
+        if queue.is_empty() {
+            self.queues.remove(&paddr);
+        }
+
+        tid
+    }
+
+    /// Stop the given thread from waiting, for example because its wait timed out.
+    pub fn remove(&mut self, paddr: usize, tid: usize) {
+        let Some(queue) = self.queues.get_mut(&paddr) else {
+            return;
+        };
+
+        queue.retain(|&waiting| waiting != tid);
+        if queue.is_empty() {
+            self.queues.remove(&paddr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn wakes_in_order() {
+        let mut queues = WaitQueues::new();
+        queues.push(0x1000, 1);
+        queues.push(0x2000, 2);
+        queues.push(0x1000, 3);
+        queues.push(0x1000, 4);
+        queues.remove(0x1000, 3);
+
+        assert_eq!(queues.pop(0x1000), Some(1));
+        assert_eq!(queues.pop(0x1000), Some(4));
+        assert_eq!(queues.pop(0x1000), None);
+        assert_eq!(queues.pop(0x2000), Some(2));
+        assert!(queues.queues.is_empty());
+    }
+}
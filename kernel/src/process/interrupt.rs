@@ -1,49 +1,34 @@
-use super::{scheduler, trapframe::Registers, Process, ProcessState};
-use crate::memory::PAGE_SIZE;
-use alloc::boxed::Box;
+use super::scheduler;
+use crate::{hart, trap::clint};
+use syscall::Event;
 
-/// Handle an external interrupt for the given process, by context switching into its designated handler.
-/// The signature of this function must match the definition in `plic.s`
+/// Handle an external interrupt for the given process, by having it run its designated handler the next time it gets scheduled.
 pub fn handle(interrupt_id: u32, handler_ptr: usize, pid: usize) {
-    let proc = scheduler::PROCESSES.lock_with(|procs| {
-        // Update the state of the previously running process
-        if let Some(curr) = procs.current() {
-            if curr.state == ProcessState::Running {
-                curr.state = ProcessState::Ready;
-            }
-        }
-
-        // Reorder the process list so that `procs.current()` remains valid
-        let pos = procs.find_pid_position(pid).unwrap();
-        procs.rotate_right(procs.len() - pos);
-
-        let proc = procs.current().unwrap();
-        let old_state = Box::new(proc.state.clone());
-        let old_registers = Box::new(proc.trap_frame.registers);
-
-        // Stash away the old state so that we can restore it when the interrupt handler returns
-        proc.state = ProcessState::HandlingInterrupt {
-            old_state,
-            old_registers,
-            interrupt_id,
-        };
-
-        // Ensure we dont depend on any previous state (except `SATP`)
-        proc.trap_frame.registers[Registers::ProgramCounter as _..].fill(0);
-
-        // Allocate a new stack for the interrupt handler, a single page should be plenty
-        let new_stack = Process::map_user_stack(&mut proc.page_table, PAGE_SIZE);
-        proc.trap_frame.registers[Registers::StackPointer as usize] = new_stack as _;
-
-        // Start execution at the interrupt handler
-        proc.trap_frame.registers[Registers::ProgramCounter as usize] = handler_ptr as _;
-
-        // Bypass the borrow checker so that we can release the processes lock
-        let proc = proc as *mut Process;
-        Some(unsafe { &mut *proc })
-    });
+    let mut procs = scheduler::PROCESSES.lock();
+
+    let Some(proc) = procs.find_pid(pid) else {
+        panic!("interrupt {interrupt_id} is registered to non-existent process with PID {pid}!");
+    };
+    proc.pending_interrupts
+        .push_back((interrupt_id, handler_ptr));
+    let tid = proc.tid;
+    let missed_interrupt = proc.missed_interrupt.clone();
+
+    // Let a thread of the process that waits for interrupts know about it as well,
+    // or the next one that does if none is waiting yet
+    let delivered = procs
+        .iter_mut()
+        .filter(|p| p.pid == pid)
+        .any(|p| p.finish_wait_events(Event::Interrupt, interrupt_id as _));
+    if !delivered {
+        *missed_interrupt.lock() = Some(interrupt_id);
+    }
 
-    if let Some(proc) = proc {
-        proc.run();
+    // A process running on another hart would only notice the interrupt after its quantum has expired,
+    // so interrupt that hart to make it reschedule right away. The current hart reschedules after this trap anyway.
+    if let Some(hart) = procs.running_on(tid) {
+        if hart != hart::id() {
+            clint::send_ipi(hart);
+        }
     }
 }
@@ -1,31 +1,36 @@
+pub mod capability;
+pub mod fault;
+pub mod futex;
 pub mod interrupt;
 pub mod scheduler;
+pub mod signal;
 pub mod syscall;
-pub mod trapframe;
 
-use self::trapframe::TrapFrame;
 use crate::{
-    elf::load_elf,
-    memory::{align_page_down, allocator, page, sections::map_trampoline, PAGE_SIZE},
+    elf::{load_elf, program_name},
+    ipc::Message,
+    memory::{allocator, page, pages_needed, PAGE_SIZE},
+    spinlock::SpinLock,
+    thread::{
+        context::{Registers, UserState},
+        Thread,
+    },
+    trap::clint,
 };
-use alloc::boxed::Box;
+use ::syscall::{Event, Events, ExitStatus, Priority, RunState, Signal, SyscallError};
+use alloc::{boxed::Box, collections::VecDeque, string::String, sync::Arc};
+use capability::CapabilityTable;
 use core::{
-    arch::global_asm,
     fmt,
     sync::atomic::{AtomicUsize, Ordering},
     time::Duration,
 };
+use signal::Signals;
 
-extern "C" {
-    // Defined in `context_switch.s`
-    fn user_enter(trap_frame: *const TrapFrame) -> !;
-}
-
-const STACK_SIZE: usize = 40 * PAGE_SIZE;
-const TRAPFRAME_ADDR: usize = align_page_down(usize::MAX);
-static NEXT_PID: AtomicUsize = AtomicUsize::new(1);
+static NEXT_PID: AtomicUsize = AtomicUsize::new(INIT_PID);
 
-global_asm!(include_str!("context_switch.asm"), TRAPFRAME_ADDR = const TRAPFRAME_ADDR);
+/// The first process, which is allowed to manage the priorities of every other process.
+pub const INIT_PID: usize = 1;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 enum ProcessState {
@@ -37,37 +42,122 @@ enum ProcessState {
         duration: Duration,
     },
 
-    ChildExited {
-        child_pid: usize,
+    /// Waiting for the given child to exit, or for any child if there is none.
+    WaitingForChild {
+        pid: Option<usize>,
+    },
+
+    /// Waiting for room in the queue of the given server, to retry sending to it.
+    WaitingToSend {
+        server_id: u64,
+    },
+
+    WaitingForThread {
+        tid: usize,
+    },
+
+    /// Waiting for the server to reply to the call with the given token.
+    WaitingForReply {
+        token: u64,
+    },
+
+    /// Waiting for any of the given events, or until the deadline passed.
+    WaitingForEvents {
+        events: Events,
+        deadline: Option<Duration>,
+    },
+
+    /// Waiting for a server with the given name to register.
+    WaitingForServer {
+        name: String,
     },
 
-    MessageSent {
-        receiver_sid: u64,
+    FutexWait {
+        paddr: usize,
+        deadline: Option<Duration>,
     },
 
+    /// The process was killed while this thread was running on another hart, it is removed once that hart reschedules.
+    Killed,
+
     HandlingInterrupt {
         old_state: Box<ProcessState>,
-        old_registers: Box<[u64; trapframe::Registers::len()]>,
+        old_registers: Box<UserState>,
         interrupt_id: u32,
+        stack: usize,
     },
 }
 
-#[repr(C)]
-#[derive(Clone)]
+/// Pass a received message to a thread in its registers. The handle of the capability the message grants
+/// is passed in `T0` and its rights in `T1`, or `u64::MAX` in `T0` if it grants none.
+fn write_message(
+    user_state: &mut UserState,
+    capabilities: &SpinLock<CapabilityTable>,
+    message: &Message,
+) {
+    user_state[Registers::A0] = message.identifier;
+    user_state[Registers::A1] = message.sender_sid;
+    user_state[Registers::A2..=Registers::A6].copy_from_slice(message.data.as_slice());
+
+    if let Some(capability) = &message.capability {
+        user_state[Registers::T1] = capability.rights.raw();
+        user_state[Registers::T0] = capabilities.lock().insert(capability.clone());
+    } else {
+        user_state[Registers::T0] = u64::MAX;
+    }
+}
+
+/// The resources a process used so far, shared between all of its threads.
+#[derive(Debug, Default)]
+pub struct Usage {
+    pub cpu_time: Duration,
+    /// How often a thread of the process was switched to.
+    pub switches: u64,
+}
+
+/// A single thread of a process, all threads of a process share its PID and address space.
 pub struct Process {
     state: ProcessState,
     pub pid: usize,
-    page_table: Box<page::Table>,
-    pub trap_frame: Box<TrapFrame>,
+    /// Unique among all threads, the first thread of a process uses its PID.
+    pub tid: usize,
+    /// The process that spawned this one, which gets to know its exit status.
+    pub parent: Option<usize>,
+    pub thread: Thread,
+    pub priority: Priority,
+    /// How often the process was passed over by the scheduler while it was ready, used for aging.
+    starved: usize,
+    /// Interrupts whose handler has yet to run, as the interrupt ID and the address of the handler.
+    pending_interrupts: VecDeque<(u32, usize)>,
+    /// An interrupt that arrived while no thread of the process waited for interrupts,
+    /// which ends the next such wait right away so that it is not lost.
+    missed_interrupt: Arc<SpinLock<Option<u32>>>,
+    pub signals: Arc<SpinLock<Signals>>,
+    /// The handles of the process, through which it refers to the endpoints of servers.
+    pub capabilities: Arc<SpinLock<CapabilityTable>>,
+    /// The registers to restore once the signal handler this thread is running returns.
+    signal_context: Option<Box<UserState>>,
+    /// The top of the stack that this thread runs signal handlers on, instead of the stack it was interrupted on.
+    /// Every thread needs its own, as threads of a process may run handlers at the same time.
+    signal_stack: Option<usize>,
+    /// Whether nobody is going to join this thread, so that nothing needs to be kept once it exits.
+    detached: bool,
+    /// The name of the program, which is shared by all threads.
+    pub name: Arc<str>,
+    pub usage: Arc<SpinLock<Usage>>,
+    /// When the thread was last switched to.
+    scheduled_at: Duration,
 }
 
 impl Process {
-    pub fn map_user_stack(page_table: &mut page::Table, size: usize) -> *mut u8 {
+    /// Allocate a stack of the given size and map it into the page table, returning the top of the stack.
+    /// Returns `None` if there is not enough memory left.
+    pub fn map_user_stack(page_table: &mut page::Table, size: usize) -> Option<*mut u8> {
         // TODO: guard page
-        let user_stack = { allocator().allocate(size).unwrap() };
+        let user_stack = allocator().allocate(size)?;
 
         // Map the users stack
-        for page in 0..size {
+        for page in 0..pages_needed(size) {
             page_table.map_page(
                 user_stack as usize + (page * PAGE_SIZE),
                 user_stack as usize + (page * PAGE_SIZE),
@@ -75,50 +165,312 @@ impl Process {
             );
         }
 
-        unsafe { user_stack.add(size) }
+        Some(unsafe { user_stack.add(size) })
     }
 
-    pub fn new(elf: &[u8]) -> Self {
-        let mut page_table = Box::new(page::Table::new());
-        // TODO: both stacks desperately need a guard page beneath to catch stack overflows
-        let kernel_stack = { allocator().allocate(STACK_SIZE).unwrap() }; // For trapping into the kernel
-        let user_stack = Self::map_user_stack(&mut page_table, STACK_SIZE);
+    /// Create a process from the given ELF file, which is named after the program in it unless a name is given.
+    /// Returns `None` if the file is not a valid ELF for this machine.
+    pub fn new(elf: Arc<[u8]>, parent: Option<usize>, name: Option<&str>) -> Option<Self> {
+        let mut thread = Thread::new();
+        let name = match name {
+            Some(name) => name.into(),
+            None => program_name(&elf).as_deref().unwrap_or("unknown").into(),
+        };
 
-        // Map the initialisation code so that we can enter user mode after switching to the new page table
-        page_table.identity_map(
-            user_enter as usize,
-            user_enter as usize + PAGE_SIZE, // TODO: how do know the size of this?
-            page::EntryAttributes::ReadExecute,
-        );
+        // Pages of the users program are populated once they are accessed
+        let entry = load_elf(&elf, &mut thread.address_space().regions)?;
+        thread.trap_frame.user_state[Registers::ProgramCounter] = entry;
+
+        let pid = NEXT_PID.fetch_add(1, Ordering::Relaxed);
+        Some(Self {
+            thread,
+            state: ProcessState::Ready,
+            pid,
+            tid: pid,
+            parent,
+            priority: Priority::DEFAULT,
+            starved: 0,
+            pending_interrupts: VecDeque::new(),
+            missed_interrupt: Arc::new(SpinLock::new(None)),
+            signals: Arc::new(SpinLock::new(Signals::new())),
+            capabilities: Arc::new(SpinLock::new(CapabilityTable::default())),
+            signal_context: None,
+            signal_stack: None,
+            detached: false,
+            name,
+            usage: Arc::new(SpinLock::new(Usage::default())),
+            scheduled_at: Duration::ZERO,
+        })
+    }
+
+    /// Create another thread of this process, which starts at `entry` with `arg` as its argument.
+    /// Returns `None` if the process already has the maximum amount of threads.
+    pub fn spawn_thread(&self, entry: usize, stack: usize, arg: u64) -> Option<Self> {
+        Some(Self {
+            thread: self.thread.spawn(entry, stack, arg)?,
+            state: ProcessState::Ready,
+            pid: self.pid,
+            tid: NEXT_PID.fetch_add(1, Ordering::Relaxed),
+            parent: self.parent,
+            priority: self.priority,
+            starved: 0,
+            pending_interrupts: VecDeque::new(),
+            missed_interrupt: self.missed_interrupt.clone(),
+            signals: self.signals.clone(),
+            capabilities: self.capabilities.clone(),
+            signal_context: None,
+            signal_stack: None,
+            detached: false,
+            name: self.name.clone(),
+            usage: self.usage.clone(),
+            scheduled_at: Duration::ZERO,
+        })
+    }
 
-        map_trampoline(&mut page_table);
+    /// Wake this thread if it waits on the futex at the given physical address, returning whether it did.
+    /// A wait that was interrupted by an interrupt handler ends once the handler completes.
+    fn wake_from_futex(&mut self, paddr: usize) -> bool {
+        let state = match &mut self.state {
+            ProcessState::HandlingInterrupt { old_state, .. } => old_state.as_mut(),
+            state => state,
+        };
 
-        // Map the users program
-        let entry = load_elf(elf, &mut page_table);
+        if !matches!(*state, ProcessState::FutexWait { paddr: waiting, .. } if waiting == paddr) {
+            return false;
+        }
 
-        let mut trap_frame = TrapFrame::new(page_table.build_satp() as _, user_stack, unsafe {
-            kernel_stack.add(STACK_SIZE) // TODO: make this more consistent with the users stack
-        });
+        *state = ProcessState::Ready;
+        true
+    }
 
-        trap_frame.registers[trapframe::Registers::ProgramCounter as usize] = entry;
+    /// Complete the call with the given token if this thread is waiting for its reply, returning whether it was.
+    /// The reply is passed on once an interrupt handler the thread is running completes.
+    fn finish_call(&mut self, token: u64, reply: &Message) -> bool {
+        let (state, user_state) = match &mut self.state {
+            ProcessState::HandlingInterrupt {
+                old_state,
+                old_registers,
+                ..
+            } => (old_state.as_mut(), old_registers.as_mut()),
+            state => (state, &mut self.thread.trap_frame.user_state),
+        };
 
-        // Map the trap frame
-        page_table.map_page(
-            TRAPFRAME_ADDR,
-            trap_frame.as_ptr() as usize,
-            page::EntryAttributes::ReadWrite,
-        );
+        if *state != (ProcessState::WaitingForReply { token }) {
+            return false;
+        }
 
-        Self {
-            trap_frame,
-            page_table,
-            state: ProcessState::Ready,
-            pid: NEXT_PID.fetch_add(1, Ordering::Relaxed),
+        write_message(user_state, &self.capabilities, reply);
+        *state = ProcessState::Ready;
+        true
+    }
+
+    /// End a wait for events with the given one if this thread waits for it, returning whether it did.
+    /// The event is passed on once an interrupt handler the thread is running completes.
+    fn finish_wait_events(&mut self, event: Event, value: u64) -> bool {
+        let (state, user_state) = match &mut self.state {
+            ProcessState::HandlingInterrupt {
+                old_state,
+                old_registers,
+                ..
+            } => (old_state.as_mut(), old_registers.as_mut()),
+            state => (state, &mut self.thread.trap_frame.user_state),
+        };
+
+        let ProcessState::WaitingForEvents { events, deadline } = *state else {
+            return false;
+        };
+
+        let waits = match event {
+            Event::Message => events.contains(Events::MESSAGE),
+            Event::Interrupt => events.contains(Events::INTERRUPT),
+            Event::Timeout => deadline.is_some(),
+        };
+        if !waits {
+            return false;
+        }
+
+        user_state[Registers::A0] = event as _;
+        user_state[Registers::A1] = value;
+        *state = ProcessState::Ready;
+        true
+    }
+
+    /// Whether this thread waits for the given child process to exit, possibly from within an interrupt handler.
+    fn waits_for(&self, child: usize) -> bool {
+        let state = match &self.state {
+            ProcessState::HandlingInterrupt { old_state, .. } => old_state.as_ref(),
+            state => state,
+        };
+
+        match *state {
+            ProcessState::WaitingForChild { pid } => pid.is_none() || pid == Some(child),
+            _ => false,
+        }
+    }
+
+    /// Complete a wait for a child process, by returning its PID and exit status.
+    /// The status is passed on once an interrupt handler the thread is running completes.
+    fn finish_wait(&mut self, child: usize, status: ExitStatus) {
+        let (state, user_state) = match &mut self.state {
+            ProcessState::HandlingInterrupt {
+                old_state,
+                old_registers,
+                ..
+            } => (old_state.as_mut(), old_registers.as_mut()),
+            state => (state, &mut self.thread.trap_frame.user_state),
+        };
+
+        let (kind, value) = status.into_raw();
+        user_state[Registers::A0] = child as _;
+        user_state[Registers::A1] = kind;
+        user_state[Registers::A2] = value;
+        *state = ProcessState::Ready;
+    }
+
+    /// Complete a join of the given thread if this thread waits for it to exit, returning whether it did.
+    /// The join returns once an interrupt handler the thread is running completes.
+    fn finish_join(&mut self, tid: usize) -> bool {
+        let (state, user_state) = match &mut self.state {
+            ProcessState::HandlingInterrupt {
+                old_state,
+                old_registers,
+                ..
+            } => (old_state.as_mut(), old_registers.as_mut()),
+            state => (state, &mut self.thread.trap_frame.user_state),
+        };
+
+        if *state != (ProcessState::WaitingForThread { tid }) {
+            return false;
+        }
+
+        user_state[Registers::A0] = 0;
+        *state = ProcessState::Ready;
+        true
+    }
+
+    /// Whether the scheduler may pick this process to run, for example to run the handler of a pending interrupt.
+    fn is_runnable(&self) -> bool {
+        match self.state {
+            ProcessState::Ready | ProcessState::HandlingInterrupt { .. } => true,
+            ProcessState::Running | ProcessState::Killed => false,
+            // A call cannot be restarted, so signal handlers only run once the reply arrived
+            ProcessState::WaitingForReply { .. } => !self.pending_interrupts.is_empty(),
+            _ => !self.pending_interrupts.is_empty() || self.can_handle_signal(),
         }
     }
 
+    /// What the thread is doing while it is not running, as reported to userspace.
+    fn run_state(&self) -> RunState {
+        match self.state {
+            ProcessState::Sleeping { .. } => RunState::Sleeping,
+            ProcessState::Running
+            | ProcessState::Ready
+            | ProcessState::HandlingInterrupt { .. } => RunState::Ready,
+            _ if self.is_runnable() => RunState::Ready,
+            _ => RunState::Blocked,
+        }
+    }
+
+    /// Add the time since the thread was switched to onto the CPU time of its process.
+    fn account_cpu_time(&mut self) {
+        let now = clint::time_since_bootup();
+        self.usage.lock().cpu_time += now.saturating_sub(self.scheduled_at);
+        self.scheduled_at = now;
+    }
+
+    /// Whether this thread can run the handler of a pending signal, handlers do not nest.
+    fn can_handle_signal(&self) -> bool {
+        self.signal_context.is_none() && self.signals.lock().has_handled()
+    }
+
+    /// Run the given signal handler once the thread runs next, the previous context is restored when it returns.
+    fn enter_signal_handler(&mut self, signal: Signal, entry: usize, arg: u64) {
+        let user_state = &mut self.thread.trap_frame.user_state;
+
+        // Blocking system calls are interrupted by the handler. Waits that have no effect
+        // are restarted once it returns, the others return early.
+        match self.state {
+            ProcessState::FutexWait { paddr, .. } => {
+                futex::FUTEXES.lock().remove(paddr, self.tid);
+                user_state[Registers::T3] = SyscallError::Interrupted as _;
+            }
+            ProcessState::WaitUntilMessageReceived
+            | ProcessState::WaitingForChild { .. }
+            | ProcessState::WaitingForThread { .. }
+            | ProcessState::WaitingForServer { .. } => {
+                user_state[Registers::ProgramCounter] -= 4;
+            }
+            ProcessState::WaitingForEvents { .. } => {
+                user_state[Registers::T3] = SyscallError::Interrupted as _;
+            }
+            _ => (),
+        }
+
+        self.state = ProcessState::Ready;
+        self.signal_context = Some(Box::new(user_state.clone()));
+
+        let stack = self
+            .signal_stack
+            .unwrap_or(user_state[Registers::StackPointer] as usize);
+
+        user_state[Registers::StackPointer] = (stack & !0xf) as _;
+        user_state[Registers::ProgramCounter] = entry as _;
+        user_state[Registers::ReturnAddress] = 0;
+        user_state[Registers::A0] = signal as _;
+        user_state[Registers::A1] = arg;
+    }
+
+    /// Continue where the thread was interrupted by a signal, returns `false` if it is not running a handler.
+    fn return_from_signal(&mut self) -> bool {
+        let Some(context) = self.signal_context.take() else {
+            return false;
+        };
+
+        self.thread.trap_frame.user_state = *context;
+        true
+    }
+
+    /// Continue execution at the handler of the oldest pending interrupt once the process runs next, the previous
+    /// context is restored when the handler completes the interrupt. Returns `false` if no interrupt is pending, or if
+    /// there is no memory for the stack of the handler, in which case the interrupt stays pending.
+    fn enter_interrupt_handler(&mut self) -> bool {
+        let Some(&(interrupt_id, handler_ptr)) = self.pending_interrupts.front() else {
+            return false;
+        };
+
+        // Allocate a new stack for the interrupt handler, a single page should be plenty
+        let Some(new_stack) =
+            Self::map_user_stack(&mut self.thread.address_space().page_table, PAGE_SIZE)
+        else {
+            return false;
+        };
+        self.pending_interrupts.pop_front();
+
+        let old_state = Box::new(self.state.clone());
+        let old_registers = Box::new(self.thread.trap_frame.user_state.clone());
+
+        // Stash away the old state so that we can restore it when the interrupt handler returns
+        self.state = ProcessState::HandlingInterrupt {
+            old_state,
+            old_registers,
+            interrupt_id,
+            stack: new_stack as usize - PAGE_SIZE,
+        };
+
+        // Ensure we dont depend on any previous state (except `SATP`)
+        let user_state = &mut self.thread.trap_frame.user_state;
+        let satp = user_state[Registers::Satp];
+        *user_state = Default::default();
+        user_state[Registers::Satp] = satp;
+
+        // Start execution at the interrupt handler
+        user_state[Registers::StackPointer] = new_stack as _;
+        user_state[Registers::ProgramCounter] = handler_ptr as _;
+        true
+    }
+
     pub fn run(&mut self) -> ! {
-        unsafe { user_enter(self.trap_frame.as_ptr()) }
+        unsafe { self.thread.switch_into() }
     }
 }
 
@@ -126,9 +478,128 @@ impl fmt::Debug for Process {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Process")
             .field("pid", &self.pid)
+            .field("tid", &self.tid)
+            .field("name", &self.name)
             .field("state", &self.state)
-            .field("page_table", &(&self.page_table as *const _))
-            .field("trap_frame", &self.trap_frame)
+            .field("priority", &self.priority)
+            .field("thread", &self.thread)
             .finish_non_exhaustive()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// A 64-bit RISC-V executable with a readable segment for every `(offset, vaddr, file_size, memory_size)`.
+    fn elf(segments: &[(u64, u64, u64, u64)]) -> Arc<[u8]> {
+        let mut elf = Vec::new();
+        elf.extend_from_slice(b"\x7fELF\x02\x01\x01\x00");
+        elf.extend_from_slice(&[0; 8]);
+        elf.extend_from_slice(&2u16.to_le_bytes()); // Executable
+        elf.extend_from_slice(&0xf3u16.to_le_bytes()); // RISC-V
+        elf.extend_from_slice(&1u32.to_le_bytes());
+        elf.extend_from_slice(&0x1000u64.to_le_bytes()); // Entry point
+        elf.extend_from_slice(&64u64.to_le_bytes()); // Program headers
+        elf.extend_from_slice(&0u64.to_le_bytes()); // Section headers
+        elf.extend_from_slice(&0u32.to_le_bytes());
+        for value in [64, 56, segments.len() as u16, 64, 0, 0] {
+            elf.extend_from_slice(&value.to_le_bytes());
+        }
+
+        for &(offset, vaddr, file_size, memory_size) in segments {
+            elf.extend_from_slice(&1u32.to_le_bytes()); // Loadable
+            elf.extend_from_slice(&4u32.to_le_bytes()); // Readable
+            for value in [offset, vaddr, vaddr, file_size, memory_size, 0x1000] {
+                elf.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        elf.into()
+    }
+
+    /// Let the thread wait in the given state from within an interrupt handler.
+    fn interrupt(thread: &mut Process, state: ProcessState) {
+        thread.state = ProcessState::HandlingInterrupt {
+            old_state: Box::new(state),
+            old_registers: Box::default(),
+            interrupt_id: 1,
+            stack: 0,
+        };
+    }
+
+    /// The state the thread returns to once its interrupt handler completes.
+    fn old_state(thread: &Process) -> &ProcessState {
+        match &thread.state {
+            ProcessState::HandlingInterrupt { old_state, .. } => old_state,
+            _ => panic!("thread is not handling an interrupt"),
+        }
+    }
+
+    #[test_case]
+    fn futex_wakes_reach_interrupted_waits() {
+        let mut thread = Process::new(elf(&[(0, 0x1000, 0x40, 0x40)]), None, None).unwrap();
+
+        // Futex wakes only end waits on the same word
+        let futex = ProcessState::FutexWait {
+            paddr: 0x8000,
+            deadline: None,
+        };
+        thread.state = futex.clone();
+        assert!(!thread.wake_from_futex(0x9000));
+        assert!(thread.wake_from_futex(0x8000));
+        assert_eq!(thread.state, ProcessState::Ready);
+
+        interrupt(&mut thread, futex);
+        assert!(thread.wake_from_futex(0x8000));
+        assert_eq!(*old_state(&thread), ProcessState::Ready);
+    }
+
+    #[test_case]
+    fn joins_and_child_waits_end_after_interrupt_handlers() {
+        let mut thread = Process::new(elf(&[(0, 0x1000, 0x40, 0x40)]), None, None).unwrap();
+
+        interrupt(&mut thread, ProcessState::WaitingForThread { tid: 7 });
+        assert!(!thread.finish_join(8));
+        assert!(thread.finish_join(7));
+        assert_eq!(*old_state(&thread), ProcessState::Ready);
+
+        interrupt(&mut thread, ProcessState::WaitingForChild { pid: Some(3) });
+        assert!(!thread.waits_for(4));
+        assert!(thread.waits_for(3));
+        thread.finish_wait(3, ExitStatus::SUCCESS);
+        assert_eq!(*old_state(&thread), ProcessState::Ready);
+        let ProcessState::HandlingInterrupt { old_registers, .. } = &thread.state else {
+            unreachable!();
+        };
+        assert_eq!(old_registers[Registers::A0], 3);
+        assert_eq!(thread.thread.trap_frame.user_state[Registers::A0], 0);
+    }
+
+    #[test_case]
+    fn rejects_malformed_segments() {
+        let process = Process::new(elf(&[(0, 0x1000, 0x40, 0x2000)]), None, None).unwrap();
+        assert_eq!(
+            process.thread.trap_frame.user_state[Registers::ProgramCounter],
+            0x1000
+        );
+
+        // Data past the end of the file
+        assert!(Process::new(elf(&[(0, 0x1000, 0x1000, 0x1000)]), None, None).is_none());
+        assert!(Process::new(elf(&[(u64::MAX, 0x1000, 2, 2)]), None, None).is_none());
+
+        // More data than memory, and memory past the end of the address space
+        assert!(Process::new(elf(&[(0, 0x1000, 0x40, 0x20)]), None, None).is_none());
+        assert!(Process::new(elf(&[(0, u64::MAX - 0x10, 0, 0x20)]), None, None).is_none());
+        assert!(Process::new(elf(&[(0, u64::MAX - 0x10, 0, 0x8)]), None, None).is_none());
+
+        // Segments that share a page
+        let overlapping = [(0, 0x1000, 0x40, 0x40), (0, 0x1800, 0x40, 0x40)];
+        assert!(Process::new(elf(&overlapping), None, None).is_none());
+
+        // A file that ends within the program headers
+        let truncated = elf(&[(0, 0x1000, 0x40, 0x40)]);
+        assert!(Process::new(Arc::from(&truncated[..100]), None, None).is_none());
+    }
+}
@@ -1,169 +1,545 @@
-use super::{Process, ProcessState};
+use super::{futex, Process, ProcessState};
 use crate::{
+    hart::{self, MAX_HARTS},
     ipc,
     spinlock::SpinLock,
-    trap::{clint, plic},
+    thread::context::Registers,
+    trap::{self, clint, plic},
+};
+use alloc::{collections::VecDeque, vec::Vec};
+use syscall::{
+    Event, Events, ExitStatus, KillReason, Priority, ProcessInfo, RunState, Signal, SyscallError,
 };
-use alloc::collections::VecDeque;
-use core::arch::asm;
 
 pub static PROCESSES: SpinLock<ProcessList> = SpinLock::new(ProcessList::new());
 
+/// How many scheduling decisions a ready process may be passed over for, before it moves up a level.
+const AGING_THRESHOLD: usize = 8;
+
 pub struct ProcessList {
-    processes: VecDeque<Process>,
+    /// One run queue per priority level. A process is queued at the level of its priority,
+    /// unless it aged into a higher one while it was waiting.
+    queues: [VecDeque<Process>; Priority::LEVELS],
+    /// The process every hart is currently running, these are not part of any queue.
+    running: [Option<Process>; MAX_HARTS],
+    /// The thread every hart ran last, so that picking it again does not count as a context switch.
+    previous: [Option<usize>; MAX_HARTS],
+    /// Processes that exited before their parent waited for them.
+    exited_children: Vec<ExitedChild>,
+    /// Threads that exited before another thread of their process joined them, as their PID and TID.
+    /// Detached threads are not recorded, as nobody is going to join them.
+    exited_threads: Vec<(usize, usize)>,
+}
+
+struct ExitedChild {
+    parent: usize,
+    pid: usize,
+    status: ExitStatus,
 }
 
 impl ProcessList {
+    const EMPTY_QUEUE: VecDeque<Process> = VecDeque::new();
+    const NOT_RUNNING: Option<Process> = None;
+
     const fn new() -> Self {
         Self {
-            processes: VecDeque::new(),
+            queues: [Self::EMPTY_QUEUE; Priority::LEVELS],
+            running: [Self::NOT_RUNNING; MAX_HARTS],
+            previous: [None; MAX_HARTS],
+            exited_children: Vec::new(),
+            exited_threads: Vec::new(),
         }
     }
 
-    #[inline]
-    pub fn len(&self) -> usize {
-        self.processes.len()
+    pub fn push(&mut self, process: Process) {
+        self.queues[process.priority.level() as usize].push_back(process);
     }
 
-    #[inline]
-    pub fn rotate_right(&mut self, amount: usize) {
-        self.processes.rotate_right(amount);
+    /// The thread running on the current hart.
+    pub fn current(&mut self) -> Option<&mut Process> {
+        self.running[hart::id()].as_mut()
     }
 
-    pub fn push(&mut self, process: Process) {
-        self.processes.push_back(process);
+    /// Remove the current thread along with every other thread of its process, which ends with the given status.
+    pub fn remove_current(&mut self, status: ExitStatus) -> Option<Process> {
+        let proc = self.running[hart::id()].take()?;
+        self.kill_threads(proc.pid);
+        self.exited(proc.pid, proc.parent, status);
+        Some(proc)
     }
 
-    pub fn next(&mut self) -> Option<&mut Process> {
-        self.processes.rotate_right(1);
-        self.current()
+    /// Kill every thread of the given process, which ends with the given status. Returns whether the process existed.
+    pub fn kill(&mut self, pid: usize, status: ExitStatus) -> bool {
+        let Some(parent) = self
+            .iter_mut()
+            .find(|p| p.pid == pid && p.state != ProcessState::Killed)
+            .map(|p| p.parent)
+        else {
+            return false;
+        };
+
+        self.kill_threads(pid);
+        ipc::server_list().lock().remove_by_pid(pid);
+        self.exited(pid, parent, status);
+        true
     }
 
-    pub fn current(&mut self) -> Option<&mut Process> {
-        self.processes.front_mut()
+    fn kill_threads(&mut self, pid: usize) {
+        for thread in self.take_queued(pid) {
+            if let ProcessState::FutexWait { paddr, .. } = thread.state {
+                futex::FUTEXES.lock().remove(paddr, thread.tid);
+            }
+        }
+
+        // Threads running on other harts cannot be dropped from under them,
+        // so they are only marked and removed once their hart reschedules.
+        for (hart, thread) in self.running.iter_mut().enumerate() {
+            if let Some(thread) = thread.as_mut().filter(|t| t.pid == pid) {
+                thread.state = ProcessState::Killed;
+                if hart != hart::id() {
+                    clint::send_ipi(hart);
+                }
+            }
+        }
     }
 
-    pub fn remove_current(&mut self) -> Option<Process> {
-        let proc = self.processes.pop_front()?;
+    /// Make the harts running threads of the given process reschedule, so that they notice a new signal right away.
+    pub fn interrupt(&self, pid: usize) {
+        for (hart, thread) in self.running.iter().enumerate() {
+            if hart != hart::id() && thread.as_ref().is_some_and(|t| t.pid == pid) {
+                clint::send_ipi(hart);
+            }
+        }
+    }
+
+    /// Make sure that no hart still reaches memory through page table entries that were removed from the given processes.
+    pub fn flush_tlbs(&self, pids: &[usize]) {
+        let harts = self
+            .running
+            .iter()
+            .enumerate()
+            .filter(|(_, thread)| thread.as_ref().is_some_and(|t| pids.contains(&t.pid)))
+            .map(|(hart, _)| hart);
+
+        hart::flush_tlbs(harts);
+    }
+
+    /// Remove only the current thread, also returning whether it was the last thread of its process.
+    pub fn remove_current_thread(&mut self) -> Option<(Process, bool)> {
+        let mut thread = self.running[hart::id()].take()?;
+        thread.account_cpu_time();
+
+        // Let threads continue that were waiting for this one to exit
+        let mut joined = false;
+        for proc in self.iter_mut() {
+            joined |= proc.finish_join(thread.tid);
+        }
 
-        // Let the parent process continue if it was waiting on us
-        self.processes
+        let last = !self
             .iter_mut()
-            .find(|p| match p.state {
-                ProcessState::ChildExited { child_pid } => child_pid == proc.pid,
-                _ => false,
-            })
-            .map(|p| p.state = ProcessState::Ready)
-            .unwrap_or(());
+            .any(|p| p.pid == thread.pid && p.state != ProcessState::Killed);
+        if last {
+            self.exited(thread.pid, thread.parent, ExitStatus::SUCCESS);
+        } else if !joined && !thread.detached {
+            self.exited_threads.push((thread.pid, thread.tid));
+        }
 
-        plic::try_remove_user(proc.pid);
+        Some((thread, last))
+    }
 
-        Some(proc)
+    /// Clean up after the last thread of the given process is gone, and report its status to the parent.
+    fn exited(&mut self, pid: usize, parent: Option<usize>, status: ExitStatus) {
+        plic::try_remove_user(pid);
+
+        // Nobody is left to wait for the children of this process, or to join its threads
+        self.exited_children.retain(|child| child.parent != pid);
+        self.exited_threads.retain(|&(exited, _)| exited != pid);
+
+        let Some(parent) = parent else {
+            return;
+        };
+
+        if let Some(parent) = self.find_pid(parent) {
+            parent.signals.lock().raise(Signal::Child);
+        }
+
+        // Hand the status to the parent right away if it is waiting for us, otherwise keep it until it does
+        if let Some(waiting) = self
+            .iter_mut()
+            .find(|p| p.pid == parent && p.waits_for(pid))
+        {
+            waiting.finish_wait(pid, status);
+            return;
+        }
+
+        if self
+            .iter_mut()
+            .any(|p| p.pid == parent && p.state != ProcessState::Killed)
+        {
+            self.exited_children.push(ExitedChild {
+                parent,
+                pid,
+                status,
+            });
+        }
     }
 
-    pub fn find_pid(&mut self, pid: usize) -> Option<&mut Process> {
-        self.processes.iter_mut().find(|p| p.pid == pid)
+    /// Take the status of a child of the given process that already exited,
+    /// either of the one with the given PID or of any child.
+    pub fn reap(&mut self, parent: usize, child: Option<usize>) -> Option<(usize, ExitStatus)> {
+        let index = self.exited_children.iter().position(|exited| {
+            exited.parent == parent && (child.is_none() || child == Some(exited.pid))
+        })?;
+
+        let exited = self.exited_children.remove(index);
+        Some((exited.pid, exited.status))
+    }
+
+    /// Take the record of a thread of the given process that exited without being joined, returning whether there was one.
+    pub fn reap_thread(&mut self, pid: usize, tid: usize) -> bool {
+        let Some(index) = self
+            .exited_threads
+            .iter()
+            .position(|&exited| exited == (pid, tid))
+        else {
+            return false;
+        };
+
+        self.exited_threads.swap_remove(index);
+        true
+    }
+
+    /// Whether the given process has a child that is still running, either the one with the given PID or any.
+    pub fn has_child(&mut self, parent: usize, child: Option<usize>) -> bool {
+        self.iter_mut().any(|p| {
+            p.parent == Some(parent)
+                && p.state != ProcessState::Killed
+                && (child.is_none() || child == Some(p.pid))
+        })
     }
 
-    pub fn find_pid_position(&mut self, pid: usize) -> Option<usize> {
-        self.processes
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Process> {
+        self.queues
             .iter_mut()
-            .enumerate()
-            .find(|(_pos, proc)| proc.pid == pid)
-            .map(|(pos, _proc)| pos)
+            .flatten()
+            .chain(self.running.iter_mut().flatten())
     }
-}
 
-pub fn insert(process: Process) {
-    PROCESSES.lock_with(|processes| processes.push(process));
-}
+    /// Any thread of the given process.
+    pub fn find_pid(&mut self, pid: usize) -> Option<&mut Process> {
+        self.iter_mut().find(|p| p.pid == pid)
+    }
 
-pub fn schedule() -> ! {
-    loop {
-        let proc: Option<&mut Process> = PROCESSES.lock_with(|procs| {
-            if let Some(current) = procs.current() {
-                if current.state == ProcessState::Running {
-                    current.state = ProcessState::Ready;
-                }
+    pub fn find_tid(&mut self, tid: usize) -> Option<&mut Process> {
+        self.iter_mut().find(|p| p.tid == tid)
+    }
+
+    /// The hart the given thread is running on, if it is running at all.
+    pub fn running_on(&self, tid: usize) -> Option<usize> {
+        self.running
+            .iter()
+            .position(|p| p.as_ref().is_some_and(|p| p.tid == tid))
+    }
+
+    /// Remove all threads of the given process from the run queues.
+    fn take_queued(&mut self, pid: usize) -> Vec<Process> {
+        let mut threads = Vec::new();
+        for queue in &mut self.queues {
+            while let Some(index) = queue.iter().position(|p| p.pid == pid) {
+                threads.extend(queue.remove(index));
             }
+        }
+
+        threads
+    }
+
+    /// A snapshot of every process, ordered by PID.
+    pub fn snapshot(&self) -> Vec<ProcessInfo> {
+        let now = clint::time_since_bootup();
+        let mut infos: Vec<ProcessInfo> = Vec::new();
+
+        let queued = self.queues.iter().flatten().map(|p| (p, false));
+        let running = self.running.iter().flatten().map(|p| (p, true));
+        for (thread, is_running) in queued.chain(running) {
+            if thread.state == ProcessState::Killed {
+                continue;
+            }
+
+            let info = if let Some(index) = infos.iter().position(|i| i.pid == thread.pid as u64) {
+                &mut infos[index]
+            } else {
+                let usage = thread.usage.lock();
+                let mut info = ProcessInfo::new(thread.pid as _, &thread.name);
+                info.parent = thread.parent.map_or(u64::MAX, |parent| parent as _);
+                info.state = u64::MAX;
+                info.priority = thread.priority.level() as _;
+                info.cpu_time = usage.cpu_time.as_nanos() as _;
+                info.switches = usage.switches;
+                infos.push(info);
+                infos.last_mut().unwrap()
+            };
+
+            // The time of running threads is only accounted once they stop running
+            let state = if is_running {
+                info.cpu_time += now.saturating_sub(thread.scheduled_at).as_nanos() as u64;
+                RunState::Running
+            } else {
+                thread.run_state()
+            };
+
+            info.threads += 1;
+            info.state = info.state.min(state as u64);
+        }
+
+        infos.sort_unstable_by_key(|info| info.pid);
+        infos
+    }
+
+    /// Change the priority of all threads of the given process, returning its previous priority.
+    pub fn set_priority(&mut self, pid: usize, priority: Priority) -> Option<Priority> {
+        let old_priority = self.find_pid(pid)?.priority;
+
+        // Running threads are requeued according to their priority once they stop running
+        for proc in self.running.iter_mut().flatten().filter(|p| p.pid == pid) {
+            proc.priority = priority;
+        }
+
+        for mut proc in self.take_queued(pid) {
+            proc.priority = priority;
+            self.push(proc);
+        }
+
+        Some(old_priority)
+    }
+
+    /// Stop running the thread of the current hart, and queue it up again behind the other threads of its priority.
+    fn deschedule(&mut self) {
+        let Some(mut proc) = self.running[hart::id()].take() else {
+            return;
+        };
+
+        proc.account_cpu_time();
+        self.previous[hart::id()] = Some(proc.tid);
+
+        match proc.state {
+            ProcessState::Running => proc.state = ProcessState::Ready,
+            ProcessState::Killed => return,
+            _ => (),
+        }
+
+        proc.starved = 0;
+        self.push(proc);
+    }
 
-            let len = procs.processes.len();
-            let mut next_proc = procs.next().expect("no processes to schedule");
-            let mut found = false;
+    /// Mark processes as ready whose reason to block has gone away.
+    fn wake(&mut self) {
+        let mut servers = Vec::new();
 
-            for _ in 0..len {
-                match next_proc.state {
-                    ProcessState::Ready | ProcessState::HandlingInterrupt { .. } => {
-                        found = true;
-                        break;
+        for proc in self.iter_mut() {
+            match proc.state {
+                ProcessState::Sleeping { duration } => {
+                    if clint::time_since_bootup() >= duration {
+                        proc.state = ProcessState::Ready;
                     }
+                }
+
+                ProcessState::FutexWait {
+                    paddr,
+                    deadline: Some(deadline),
+                } => {
+                    if clint::time_since_bootup() >= deadline {
+                        futex::FUTEXES.lock().remove(paddr, proc.tid);
+                        proc.thread.trap_frame.user_state[Registers::T3] =
+                            SyscallError::TimedOut as _;
+                        proc.state = ProcessState::Ready;
+                    }
+                }
+
+                // The call failed if the server exited before replying
+                ProcessState::WaitingForReply { token } => {
+                    if !ipc::server_list().lock().is_call_pending(token) {
+                        proc.thread.trap_frame.user_state[Registers::T3] = SyscallError::Gone as _;
+                        proc.state = ProcessState::Ready;
+                    }
+                }
+
+                ProcessState::WaitingForServer { ref name } => {
+                    let capability = ipc::server_list()
+                        .lock()
+                        .get_by_name(name)
+                        .map(|server| server.client_capability());
+
+                    if let Some(capability) = capability {
+                        proc.thread.trap_frame.user_state[Registers::A0] =
+                            proc.capabilities.lock().insert(capability);
+                        proc.state = ProcessState::Ready;
+                    }
+                }
+
+                // Retry sending once there is room, or fail if the server is gone
+                ProcessState::WaitingToSend { server_id } => {
+                    let has_room = ipc::server_list()
+                        .lock()
+                        .get_by_sid(server_id)
+                        .is_none_or(|server| !server.is_full());
 
-                    ProcessState::Sleeping { duration } => {
-                        if clint::time_since_bootup() >= duration {
-                            next_proc.state = ProcessState::Ready;
-                            found = true;
-                            break;
-                        }
+                    if has_room {
+                        proc.state = ProcessState::Ready;
                     }
+                }
+
+                // Wake up one thread of every server that has messages waiting
+                ProcessState::WaitUntilMessageReceived => {
+                    let has_messages = ipc::server_list()
+                        .lock()
+                        .get_by_pid(proc.pid)
+                        .is_some_and(|server| server.has_messages());
 
-                    ProcessState::MessageSent { receiver_sid } => {
-                        let mut server_list = ipc::server_list().lock();
-                        let server = server_list.get_by_sid(receiver_sid).unwrap_or_else(|| {
-                            panic!(
-                                "attempted to look up non-existent server with SID {receiver_sid}!"
-                            )
-                        });
-
-                        if server.has_messages() {
-                            if let Some(server_proc) = procs.find_pid(server.process_id) {
-                                if let ProcessState::WaitUntilMessageReceived = server_proc.state {
-                                    server_proc.state = ProcessState::Ready;
-                                }
-                            } else {
-                                panic!(
-                                    "attempted to wake up non-existent process with PID {}!",
-                                    server.process_id
-                                );
-                            }
-                        } else {
-                            println!(
-                                "server {receiver_sid} dropped a message from {}!",
-                                next_proc.pid
-                            );
-
-                            next_proc.state = ProcessState::Ready;
-                            found = true;
-                            break;
-                        }
+                    if has_messages && !servers.contains(&proc.pid) {
+                        servers.push(proc.pid);
+                        proc.state = ProcessState::Ready;
                     }
+                }
+
+                ProcessState::WaitingForEvents { events, deadline } => {
+                    let has_messages = events.contains(Events::MESSAGE)
+                        && ipc::server_list()
+                            .lock()
+                            .get_by_pid(proc.pid)
+                            .is_some_and(|server| server.has_messages());
 
-                    _ => (),
+                    if has_messages && !servers.contains(&proc.pid) {
+                        servers.push(proc.pid);
+                        proc.finish_wait_events(Event::Message, 0);
+                    } else if deadline
+                        .is_some_and(|deadline| clint::time_since_bootup() >= deadline)
+                    {
+                        proc.finish_wait_events(Event::Timeout, 0);
+                    }
                 }
 
-                next_proc = procs.next().expect("no processes to schedule");
+                _ => (),
+            }
+        }
+    }
+
+    /// Terminate processes that have a deliverable signal, whose action is to terminate them.
+    fn apply_signals(&mut self) {
+        let fatal: Vec<_> = self
+            .iter_mut()
+            .filter_map(|p| Some((p.pid, p.signals.lock().take_fatal()?)))
+            .collect();
+
+        for (pid, signal) in fatal {
+            if self.kill(pid, ExitStatus::Killed(KillReason::Signal(signal))) {
+                println!("process {pid} was killed by signal {signal:?}");
             }
+        }
+    }
 
-            if !found {
-                return None;
+    /// Pick the first runnable process of the highest priority level, and run it on the current hart.
+    fn pick_next(&mut self) -> Option<&mut Process> {
+        let (level, index) = self
+            .queues
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(level, queue)| {
+                let index = queue.iter().position(Process::is_runnable)?;
+                Some((level, index))
+            })?;
+
+        self.age(level);
+
+        let mut proc = self.queues[level].remove(index)?;
+        if self.previous[hart::id()] != Some(proc.tid) {
+            proc.usage.lock().switches += 1;
+        }
+
+        proc.scheduled_at = clint::time_since_bootup();
+        Some(self.running[hart::id()].insert(proc))
+    }
+
+    /// Account for ready processes below the given level being passed over,
+    /// moving those that waited for too long up a level. Processes never age into the real-time class.
+    fn age(&mut self, below: usize) {
+        let real_time = Priority::REAL_TIME.level() as usize;
+
+        for level in (0..below.min(real_time)).rev() {
+            let mut index = 0;
+            while let Some(proc) = self.queues[level].get_mut(index) {
+                if proc.state == ProcessState::Ready {
+                    proc.starved += 1;
+                }
+
+                if proc.starved >= AGING_THRESHOLD && level + 1 < real_time {
+                    let mut proc = self.queues[level].remove(index).unwrap();
+                    proc.starved = 0;
+                    self.queues[level + 1].push_back(proc);
+                } else {
+                    index += 1;
+                }
             }
+        }
+    }
+}
+
+pub fn insert(process: Process) {
+    PROCESSES.lock_with(|processes| processes.push(process));
+}
+
+/// Whether the process of the thread running on the current hart was killed by another hart.
+pub fn current_is_killed() -> bool {
+    PROCESSES
+        .lock()
+        .current()
+        .is_some_and(|p| p.state == ProcessState::Killed)
+}
+
+pub fn schedule() -> ! {
+    loop {
+        let proc: Option<&mut Process> = PROCESSES.lock_with(|procs| {
+            procs.deschedule();
+            procs.wake();
+            procs.apply_signals();
+            let next_proc = procs.pick_next()?;
 
             if let ProcessState::HandlingInterrupt { .. } = next_proc.state {
                 // Do nothing
+            } else if next_proc.enter_interrupt_handler() {
+                // The handler runs once we switch to the process
+            } else if !next_proc.pending_interrupts.is_empty()
+                && next_proc.state != ProcessState::Ready
+            {
+                // There is no memory for the stack of the handler, so the thread keeps waiting until there is
+                procs.deschedule();
+                return None;
             } else {
+                let signal = next_proc
+                    .signal_context
+                    .is_none()
+                    .then(|| next_proc.signals.lock().take_handled())
+                    .flatten();
+
+                if let Some((signal, entry, arg)) = signal {
+                    next_proc.enter_signal_handler(signal, entry, arg);
+                }
+
                 next_proc.state = ProcessState::Running;
             }
 
             // We need a reference to the process that remains valid *after* dropping the PROCESSES lock,
             // should probably use a smart pointer instead of the unsafe raw pointer.
-            let next_proc = next_proc as *mut _ as *mut Process;
+            let next_proc = next_proc as *mut Process;
             Some(unsafe { &mut *next_proc })
         });
 
         if let Some(proc) = proc {
+            clint::start_quantum();
             proc.run()
         } else {
-            // We should never get here unless all processes are non-runnable, in which case we wait for an interrupt to wake us up to avoid a busy loop.
-            unsafe { asm!("wfi") }
+            // We get here if all processes are non-runnable or running on other harts, in which case we wait for an interrupt to wake us up to avoid a busy loop.
+            trap::wait_for_interrupt();
         }
     }
 }
@@ -0,0 +1,150 @@
+//! Signals are asynchronous notifications between processes. A signal either runs a handler the receiving process
+//! installed for it, or has its default action. Handlers run on one of the threads of the process, after which the
+//! thread continues where it was interrupted.
+
+use syscall::{DefaultAction, Signal, SignalSet};
+
+/// How a process wants to handle a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Default,
+    Ignore,
+    /// Run the handler at `entry`, with the signal and `arg` as its arguments.
+    Handle {
+        entry: usize,
+        arg: u64,
+    },
+}
+
+/// The signal state of a process, shared by all of its threads.
+#[derive(Debug)]
+pub struct Signals {
+    actions: [Action; u64::BITS as usize],
+    /// Signals that stay pending instead of being delivered, until they are unmasked.
+    pub mask: SignalSet,
+    pending: SignalSet,
+}
+
+impl Signals {
+    pub const fn new() -> Self {
+        Self {
+            actions: [Action::Default; u64::BITS as usize],
+            mask: SignalSet::EMPTY,
+            pending: SignalSet::EMPTY,
+        }
+    }
+
+    /// Change the action of the given signal, returning the previous one or `None` if it cannot be changed.
+    pub fn set_action(&mut self, signal: Signal, action: Action) -> Option<Action> {
+        if !signal.can_be_handled() {
+            return None;
+        }
+
+        let previous = core::mem::replace(&mut self.actions[signal as usize], action);
+        if action == Action::Ignore {
+            self.pending.remove(signal);
+        }
+
+        Some(previous)
+    }
+
+    fn action(&self, signal: Signal) -> Action {
+        if !signal.can_be_handled() {
+            return Action::Default;
+        }
+
+        match self.actions[signal as usize] {
+            Action::Default if signal.default_action() == DefaultAction::Ignore => Action::Ignore,
+            action => action,
+        }
+    }
+
+    /// The pending signals that are not masked.
+    fn deliverable(&self) -> SignalSet {
+        let unmaskable = SignalSet::EMPTY.with(Signal::Kill);
+        self.pending.difference(self.mask.difference(unmaskable))
+    }
+
+    /// Raise the given signal, returning whether it terminates the process right away.
+    /// Otherwise it stays pending until it can be delivered, unless it is ignored.
+    pub fn raise(&mut self, signal: Signal) -> bool {
+        match self.action(signal) {
+            Action::Ignore => false,
+            action => {
+                self.pending.insert(signal);
+                action == Action::Default && self.deliverable().contains(signal)
+            }
+        }
+    }
+
+    /// Take a deliverable signal that terminates the process, if there is one.
+    pub fn take_fatal(&mut self) -> Option<Signal> {
+        let signal = self
+            .deliverable()
+            .iter()
+            .find(|&signal| self.action(signal) == Action::Default)?;
+
+        self.pending.remove(signal);
+        Some(signal)
+    }
+
+    /// Take a deliverable signal that has a handler, along with the handler.
+    pub fn take_handled(&mut self) -> Option<(Signal, usize, u64)> {
+        self.deliverable()
+            .iter()
+            .find_map(|signal| match self.action(signal) {
+                Action::Handle { entry, arg } => Some((signal, entry, arg)),
+                _ => None,
+            })
+            .inspect(|&(signal, ..)| self.pending.remove(signal))
+    }
+
+    /// Whether there is a signal that a thread of the process has to run the handler of.
+    pub fn has_handled(&self) -> bool {
+        self.deliverable()
+            .iter()
+            .any(|signal| matches!(self.action(signal), Action::Handle { .. }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HANDLER: Action = Action::Handle {
+        entry: 0x1000,
+        arg: 0,
+    };
+
+    #[test_case]
+    fn default_actions() {
+        let mut signals = Signals::new();
+        assert!(!signals.raise(Signal::Child));
+        assert!(signals.raise(Signal::Terminate));
+        assert!(signals.set_action(Signal::Kill, Action::Ignore).is_none());
+    }
+
+    #[test_case]
+    fn masked_signals_stay_pending() {
+        let mut signals = Signals::new();
+        signals.set_action(Signal::User1, HANDLER).unwrap();
+        signals.mask = SignalSet::EMPTY
+            .with(Signal::User1)
+            .with(Signal::Interrupt)
+            .with(Signal::Kill);
+
+        assert!(!signals.raise(Signal::User1));
+        assert!(!signals.raise(Signal::Interrupt));
+        assert!(!signals.has_handled());
+        assert_eq!(signals.take_fatal(), None);
+
+        // Kill cannot be masked
+        assert!(signals.raise(Signal::Kill));
+
+        signals.mask = SignalSet::EMPTY;
+        assert_eq!(signals.take_fatal(), Some(Signal::Interrupt));
+        assert_eq!(signals.take_fatal(), Some(Signal::Kill));
+        assert_eq!(signals.take_handled(), Some((Signal::User1, 0x1000, 0)));
+        assert!(!signals.has_handled());
+    }
+}
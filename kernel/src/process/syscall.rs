@@ -1,26 +1,183 @@
-use super::{scheduler, trapframe::Registers, Process, ProcessState};
+use super::capability::{Capability, Object};
+use super::signal::Action;
+use super::{
+    futex, scheduler, scheduler::ProcessList, write_message, Process, ProcessState, INIT_PID,
+};
 use crate::{
-    ipc::{self, Message, MessageData},
-    memory,
+    devicetree,
+    ipc::{self, trace::trace, Message, MessageData},
+    memory::{self, shared::SharedMemory},
+    thread::context::Registers,
     trap::{clint, plic},
 };
-use core::time::Duration;
-use syscall::SystemCall;
+use alloc::{string::String, vec::Vec};
+use core::{
+    mem::size_of_val,
+    slice,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+use syscall::{
+    Event, Events, ExitStatus, KillReason, MaskHow, Priority, Rights, Signal, SignalSet,
+    SyscallError, SystemCall, TraceKind,
+};
+
+/// The status of processes that are killed for misusing a system call.
+const MISUSED: ExitStatus = ExitStatus::Killed(KillReason::InvalidSystemCall);
+
+/// Fail the system call of the thread with the given error, which is returned in `T3`.
+fn fail(proc: &mut Process, error: SyscallError) {
+    proc.thread.trap_frame.user_state[Registers::T3] = error as _;
+}
+
+/// Whether the caller may change the priority of the target process from `current` to `priority`.
+fn may_set_priority(caller: usize, target: usize, current: Priority, priority: Priority) -> bool {
+    if caller == INIT_PID {
+        return true;
+    }
+
+    // Everyone else may only lower their own priority, or raise it to the default.
+    // Device drivers are the exception, as they may request the real-time class for themselves.
+    caller == target
+        && (priority <= current.max(Priority::DEFAULT)
+            || (priority.is_real_time() && plic::has_user(caller)))
+}
+
+/// Whether the process may trace the messages of servers, which only init and the processes it started may.
+fn may_trace(procs: &mut ProcessList, pid: usize) -> bool {
+    pid == INIT_PID
+        || procs
+            .find_pid(pid)
+            .is_some_and(|proc| proc.parent == Some(INIT_PID))
+}
+
+/// The server the handle in `A0` refers to, if the process holds it with the right to send.
+fn target_server(proc: &Process) -> Option<u64> {
+    let handle = proc.thread.trap_frame.user_state[Registers::A0];
+    proc.capabilities.lock().server_with(handle, Rights::SEND)
+}
+
+/// The capability a message grants, derived from the handle in `T0` with at most the rights in `T1`.
+/// `T0` is `u64::MAX` to grant nothing, an error means the process may not grant the handle.
+fn granted_capability(proc: &Process) -> Result<Option<Capability>, ()> {
+    let handle = proc.thread.trap_frame.user_state[Registers::T0];
+    if handle == u64::MAX {
+        return Ok(None);
+    }
+
+    let rights = Rights::from_raw(proc.thread.trap_frame.user_state[Registers::T1]);
+    let capabilities = proc.capabilities.lock();
+    let capability = capabilities.get(handle).and_then(|c| c.derive(rights));
+    capability.map(Some).ok_or(())
+}
+
+/// The UTF-8 string at the address in one register with the length in another, or `None` if the length is 0.
+/// An error means the string is not mapped or not valid UTF-8.
+fn user_string(proc: &Process, ptr: Registers, len: Registers) -> Result<Option<String>, ()> {
+    let user_state = &proc.thread.trap_frame.user_state;
+    let (ptr, len) = (user_state[ptr], user_state[len]);
+    if len == 0 {
+        return Ok(None);
+    }
+
+    proc.thread
+        .address_space()
+        .copy_from_user(ptr as _, len as _)
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .map(Some)
+        .ok_or(())
+}
+
+/// Queue the message in the argument registers for the server the handle in `A0` refers to, along with the handle
+/// granted in `T0`. Returns the PID of the server and the token to reply with for calls, or `None` if the queue is
+/// full and the thread waits for room to retry the system call.
+fn queue_message(
+    proc: &mut Process,
+    call: bool,
+    block: bool,
+) -> Result<Option<(usize, Option<u64>)>, SyscallError> {
+    let server_id = target_server(proc).ok_or(SyscallError::InvalidHandle)?;
+    let capability = granted_capability(proc).map_err(|()| SyscallError::InvalidGrant)?;
+
+    let user_state = &mut proc.thread.trap_frame.user_state;
+    let identifier = user_state[Registers::A1];
+    let data = MessageData::from_slice(&user_state[Registers::A2..=Registers::A6]);
+
+    // Senders that are not servers themselves are identified as 0
+    let mut server_list = ipc::server_list().lock();
+    let sender_sid = server_list
+        .get_by_pid(proc.pid)
+        .map_or(0, |server| server.server_id);
+    let server = server_list
+        .get_by_sid(server_id)
+        .ok_or(SyscallError::Gone)?;
+
+    if server.is_full() {
+        let handling_interrupt = matches!(proc.state, ProcessState::HandlingInterrupt { .. });
+        return match (block, handling_interrupt) {
+            (false, _) => Err(SyscallError::Full),
+            (true, true) => Err(SyscallError::WouldBlock),
+            (true, false) => {
+                user_state[Registers::ProgramCounter] -= 4;
+                proc.state = ProcessState::WaitingToSend { server_id };
+                Ok(None)
+            }
+        };
+    }
+
+    let message = if call {
+        Message::call(proc.pid, sender_sid, identifier, data)
+    } else {
+        Message::new(proc.pid, sender_sid, identifier, data)
+    };
+    let token = message.reply_token;
+
+    let kind = if call {
+        TraceKind::Call
+    } else {
+        TraceKind::Send
+    };
+    trace().lock().record(kind, &message, server_id, token);
+
+    server
+        .send_message(Message {
+            capability,
+            ..message
+        })
+        .expect("the queue has room");
+    Ok(Some((server.process_id, token)))
+}
+
+/// Wake up one of the threads of the given server that wait for a message.
+fn wake_receiver(procs: &mut ProcessList, server_pid: usize) {
+    for proc in procs.iter_mut().filter(|p| p.pid == server_pid) {
+        if proc.state == ProcessState::WaitUntilMessageReceived {
+            proc.state = ProcessState::Ready;
+            return;
+        }
+
+        if proc.finish_wait_events(Event::Message, 0) {
+            return;
+        }
+    }
+}
 
 pub fn handle() {
     let mut procs = scheduler::PROCESSES.lock();
     let proc = procs.current().unwrap();
-    let syscall = SystemCall::try_from(proc.trap_frame.registers[Registers::A7 as usize]);
+    let syscall = SystemCall::try_from(proc.thread.trap_frame.user_state[Registers::A7]);
 
-    // Skip past the `ecall` instruction
-    proc.trap_frame.registers[Registers::ProgramCounter as usize] += 4;
+    // Skip past the `ecall` instruction, every system call succeeds unless it fails with an error in `T3`
+    proc.thread.trap_frame.user_state[Registers::ProgramCounter] += 4;
+    proc.thread.trap_frame.user_state[Registers::T3] = 0;
 
     if let Ok(syscall) = syscall {
         match syscall {
             SystemCall::Exit => {
-                let pid = procs.remove_current().unwrap().pid;
+                let code = proc.thread.trap_frame.user_state[Registers::A0] as i32;
+                let pid = procs.remove_current(ExitStatus::Exited(code)).unwrap().pid;
                 ipc::server_list().lock().remove_by_pid(pid);
-                println!("\nprocess {pid} gracefully exited");
+                println!("\nprocess {pid} exited with code {code}");
             }
 
             SystemCall::Yield => {
@@ -29,74 +186,90 @@ pub fn handle() {
             }
 
             SystemCall::Allocate => {
-                let size = proc.trap_frame.registers[Registers::A0 as usize] as usize;
+                let size = proc.thread.trap_frame.user_state[Registers::A0] as usize;
                 let ptr = memory::allocator().allocate(size);
 
                 if let Some(ptr) = ptr {
                     let allocated_size = memory::align_page_up(size);
-                    proc.page_table.identity_map(
+                    proc.thread.address_space().page_table.identity_map(
                         ptr as usize,
                         ptr as usize + allocated_size,
                         memory::page::EntryAttributes::UserReadWrite,
                     );
 
-                    proc.trap_frame.registers[Registers::A0 as usize] = ptr as _;
+                    proc.thread.trap_frame.user_state[Registers::A0] = ptr as _;
                 } else {
-                    let pid = procs.remove_current().unwrap().pid;
-                    println!("failed to allocate memory for process {pid} with size {size:#x}. Killing process");
+                    fail(proc, SyscallError::OutOfMemory);
                 }
             }
 
             SystemCall::Deallocate => {
-                let ptr = proc.trap_frame.registers[Registers::A0 as usize] as usize;
+                let ptr = proc.thread.trap_frame.user_state[Registers::A0] as usize;
 
-                // Check if it was mapped in the first place
-                if let Some(physical_addr) = proc.page_table.physical_addr(ptr) {
-                    let mut alloc = memory::allocator();
-                    for i in 0..alloc.size_of(physical_addr as _) {
-                        proc.page_table.unmap(ptr + i);
+                // Check if it was mapped and allocated in the first place
+                let mut alloc = memory::allocator();
+                let allocation = proc
+                    .thread
+                    .address_space()
+                    .page_table
+                    .physical_addr(ptr)
+                    .map(|addr| (addr, alloc.size_of(addr as _)))
+                    .filter(|(_, size)| *size != 0);
+
+                if let Some((physical_addr, size)) = allocation {
+                    for offset in memory::page_offsets(size) {
+                        proc.thread.address_space().page_table.unmap(ptr + offset);
                     }
 
+                    // Other threads of the process may still reach the memory through their TLB
+                    let pid = proc.pid;
+                    procs.flush_tlbs(&[pid]);
                     alloc.deallocate(physical_addr as _);
                 } else {
-                    let pid = procs.remove_current().unwrap().pid;
-                    println!("process {pid} attempted to deallocate unmapped memory: {ptr:#x}. Killing process");
+                    fail(proc, SyscallError::InvalidAddress);
                 }
             }
 
             SystemCall::Spawn => {
-                let elf_ptr = proc.trap_frame.registers[Registers::A0 as usize];
-                let elf_size = proc.trap_frame.registers[Registers::A1 as usize];
-                let blocking = proc.trap_frame.registers[Registers::A2 as usize] != 0;
+                let elf_ptr = proc.thread.trap_frame.user_state[Registers::A0];
+                let elf_size = proc.thread.trap_frame.user_state[Registers::A1];
 
-                let elf = {
-                    let elf_ptr = { proc.page_table.physical_addr(elf_ptr as _).unwrap() };
-                    unsafe { core::slice::from_raw_parts(elf_ptr as *const u8, elf_size as _) }
+                // The ELF is not guaranteed to be physically contiguous, so copy it out of the callers address space
+                let elf = proc
+                    .thread
+                    .address_space()
+                    .copy_from_user(elf_ptr as _, elf_size as _);
+                let Some(elf) = elf else {
+                    fail(proc, SyscallError::InvalidAddress);
+                    return;
                 };
 
-                let new_proc = if blocking {
-                    let new_proc = Process::new(elf);
-                    proc.state = ProcessState::ChildExited {
-                        child_pid: new_proc.pid,
-                    };
-                    new_proc
-                } else {
-                    Process::new(elf)
+                // Without a name the process is named after the program in the ELF
+                let Ok(name) = user_string(proc, Registers::A2, Registers::A3) else {
+                    fail(proc, SyscallError::InvalidArgument);
+                    return;
+                };
+
+                let Some(new_proc) = Process::new(elf.into(), Some(proc.pid), name.as_deref())
+                else {
+                    fail(proc, SyscallError::InvalidArgument);
+                    return;
                 };
 
+                proc.thread.trap_frame.user_state[Registers::A0] = new_proc.pid as _;
                 procs.push(new_proc);
             }
 
             SystemCall::DurationSinceBootup => {
                 let time = clint::time_since_bootup();
-                proc.trap_frame.registers[Registers::A0 as usize] = time.as_secs() as _;
-                proc.trap_frame.registers[Registers::A1 as usize] = time.subsec_nanos() as _;
+                proc.thread.trap_frame.user_state[Registers::A0] = time.as_secs() as _;
+                proc.thread.trap_frame.user_state[Registers::A1] = time.subsec_nanos() as _;
             }
 
             SystemCall::Sleep => {
                 let duration = {
-                    let seconds = proc.trap_frame.registers[Registers::A0 as usize];
-                    let nanoseconds = proc.trap_frame.registers[Registers::A1 as usize] as u32;
+                    let seconds = proc.thread.trap_frame.user_state[Registers::A0];
+                    let nanoseconds = proc.thread.trap_frame.user_state[Registers::A1] as u32;
                     Duration::new(seconds, nanoseconds)
                 };
 
@@ -106,28 +279,29 @@ pub fn handle() {
 
             // TODO: Capabilities, not every process should be allowed to do this.
             // TODO: Maybe it would make more sense to only allow this when spawing a new process?
-            // TODO: Ensure the passed range is page-aligned.
             SystemCall::IdentityMap => {
-                let start = proc.trap_frame.registers[Registers::A0 as usize] as usize;
-                let end = proc.trap_frame.registers[Registers::A1 as usize] as usize;
+                let start = proc.thread.trap_frame.user_state[Registers::A0] as usize;
+                let end = proc.thread.trap_frame.user_state[Registers::A1] as usize;
 
                 if !memory::is_page_aligned(start) || !memory::is_page_aligned(end) {
-                    let pid = procs.remove_current().unwrap().pid;
-                    println!(
-                        "process {pid} tried to identity map an unaligned address. Killing process"
-                    );
+                    fail(proc, SyscallError::InvalidArgument);
                     return;
                 }
 
                 let root_table = memory::page::root_table();
 
                 // TODO: this will not work if the given address is not already mapped by the kernel.
-                let physical_start = root_table.physical_addr(start).unwrap();
-                let physical_end = root_table.physical_addr(end).unwrap();
+                let physical_range = root_table
+                    .physical_addr(start)
+                    .zip(root_table.physical_addr(end))
+                    .filter(|&(start, end)| start != 0 && end != 0);
 
-                assert!(physical_start != 0 && physical_end != 0);
+                let Some((physical_start, physical_end)) = physical_range else {
+                    fail(proc, SyscallError::InvalidAddress);
+                    return;
+                };
 
-                proc.page_table.identity_map(
+                proc.thread.address_space().page_table.identity_map(
                     physical_start,
                     physical_end,
                     memory::page::EntryAttributes::UserReadWrite, // Execute permissions dont seem like a good idea
@@ -138,82 +312,254 @@ pub fn handle() {
                 proc.state = ProcessState::WaitUntilMessageReceived;
             }
 
+            // Returns the event that ended the wait, along with the interrupt id for interrupts.
+            // Fails with `Interrupted` if a signal handler runs first.
+            SystemCall::WaitEvents => {
+                let user_state = &mut proc.thread.trap_frame.user_state;
+                let events = Events::from_raw(user_state[Registers::A0]);
+                let timeout = user_state[Registers::A1];
+
+                let has_messages = events.contains(Events::MESSAGE)
+                    && ipc::server_list()
+                        .lock()
+                        .get_by_pid(proc.pid)
+                        .is_some_and(|server| server.has_messages());
+
+                // An interrupt that arrived since the last wait ends this one, it would be lost otherwise
+                let missed_interrupt = (!has_messages && events.contains(Events::INTERRUPT))
+                    .then(|| proc.missed_interrupt.lock().take())
+                    .flatten();
+
+                if has_messages {
+                    user_state[Registers::A0] = Event::Message as _;
+                } else if let Some(interrupt_id) = missed_interrupt {
+                    user_state[Registers::A0] = Event::Interrupt as _;
+                    user_state[Registers::A1] = interrupt_id as _;
+                } else if matches!(proc.state, ProcessState::HandlingInterrupt { .. }) {
+                    fail(proc, SyscallError::WouldBlock);
+                } else if events == Events::NONE && timeout == u64::MAX {
+                    // Nothing could ever end the wait
+                    fail(proc, SyscallError::InvalidArgument);
+                } else {
+                    let deadline = (timeout != u64::MAX)
+                        .then(|| clint::time_since_bootup() + Duration::from_nanos(timeout));
+                    proc.state = ProcessState::WaitingForEvents { events, deadline };
+                }
+            }
+
             SystemCall::SendMessage => {
-                let server_id = proc.trap_frame.registers[Registers::A0 as usize];
-                let identifier = proc.trap_frame.registers[Registers::A1 as usize];
-                let data = MessageData::from_slice(
-                    &proc.trap_frame.registers[Registers::A2 as usize..=Registers::A6 as usize],
-                );
+                let block = proc.thread.trap_frame.user_state[Registers::T2] != 0;
+
+                match queue_message(proc, false, block) {
+                    Ok(Some((server_pid, _))) => wake_receiver(&mut procs, server_pid),
+                    Ok(None) => (),
+                    Err(error) => fail(proc, error),
+                }
+            }
+
+            SystemCall::ReceiveMessage => {
+                // Only the server itself holds a handle to its endpoint with the right to receive
+                let handle = proc.thread.trap_frame.user_state[Registers::A0];
+                let server_id = proc
+                    .capabilities
+                    .lock()
+                    .server_with(handle, Rights::RECEIVE);
+                let Some(server_id) = server_id else {
+                    fail(proc, SyscallError::InvalidHandle);
+                    return;
+                };
 
                 let mut server_list = ipc::server_list().lock();
-                let curr_sid = if let Some(server) = server_list.get_by_pid(proc.pid) {
-                    server.server_id
-                } else {
-                    let pid = procs.remove_current().unwrap().pid;
-                    println!("process {pid} tried to send a message without being a server. Killing process");
+                let Some(server) = server_list.get_by_sid(server_id) else {
+                    fail(proc, SyscallError::NotFound);
                     return;
                 };
 
-                if let Some(server) = server_list.get_by_sid(server_id) {
-                    server.send_message(Message::new(proc.pid, curr_sid, identifier, data));
+                if let Some(msg) = server.receive_message() {
+                    trace()
+                        .lock()
+                        .record(TraceKind::Receive, &msg, server_id, msg.reply_token);
 
-                    if let ProcessState::HandlingInterrupt { .. } = proc.state {
-                        // Do nothing, this needs to be asynchronous
-                    } else {
-                        proc.state = ProcessState::MessageSent {
-                            receiver_sid: server.server_id,
-                        };
-                    }
+                    let user_state = &mut proc.thread.trap_frame.user_state;
+                    write_message(user_state, &proc.capabilities, &msg);
 
-                    if let Some(proc) = procs.find_pid(server.process_id) {
-                        if proc.state == ProcessState::WaitUntilMessageReceived {
-                            proc.state = ProcessState::Ready;
-                        }
+                    // Calls come with the token to reply with, other messages with 0
+                    user_state[Registers::A7] = msg.reply_token.unwrap_or(0);
+
+                    // Let the threads that wait for room in the queue retry
+                    let waiting = ProcessState::WaitingToSend { server_id };
+                    for sender in procs.iter_mut().filter(|p| p.state == waiting) {
+                        sender.state = ProcessState::Ready;
                     }
                 } else {
-                    let pid = procs.remove_current().unwrap().pid;
-                    println!("process {pid} tried to send a message to a non-existent server {server_id}. Killing process");
+                    fail(proc, SyscallError::WouldBlock);
                 }
             }
 
-            SystemCall::ReceiveMessage => {
-                let mut server_list = ipc::server_list().lock();
-                let server = server_list.get_by_pid(proc.pid).unwrap();
+            // Fails with `Gone` if the server exits before replying
+            SystemCall::Call => {
+                // Interrupt handlers may not block
+                if matches!(proc.state, ProcessState::HandlingInterrupt { .. }) {
+                    fail(proc, SyscallError::WouldBlock);
+                    return;
+                }
 
-                if let Some(msg) = server.receive_message() {
-                    proc.trap_frame.registers[Registers::A0 as usize] = msg.identifier;
-                    proc.trap_frame.registers[Registers::A1 as usize] = msg.sender_sid;
-                    proc.trap_frame.registers[Registers::A2 as usize..=Registers::A6 as usize]
-                        .copy_from_slice(msg.data.as_slice());
-
-                    let mut sender = procs.find_pid(msg.sender_pid).unwrap();
-                    if let ProcessState::MessageSent { receiver_sid } = sender.state {
-                        if receiver_sid == server.server_id {
-                            sender.state = ProcessState::Ready;
-                        }
+                match queue_message(proc, true, true) {
+                    Ok(Some((server_pid, token))) => {
+                        // The reply arrives in the same registers as a received message
+                        proc.state = ProcessState::WaitingForReply {
+                            token: token.unwrap(),
+                        };
+                        wake_receiver(&mut procs, server_pid);
                     }
-                } else {
-                    proc.trap_frame.registers[Registers::A0 as usize] = u64::MAX;
+                    Ok(None) => (),
+                    Err(error) => fail(proc, error),
                 }
             }
 
+            SystemCall::Reply => {
+                let token = proc.thread.trap_frame.user_state[Registers::A0];
+                let identifier = proc.thread.trap_frame.user_state[Registers::A1];
+                let data = MessageData::from_slice(
+                    &proc.thread.trap_frame.user_state[Registers::A2..=Registers::A6],
+                );
+
+                let Ok(capability) = granted_capability(proc) else {
+                    fail(proc, SyscallError::InvalidGrant);
+                    return;
+                };
+
+                let server_id = ipc::server_list()
+                    .lock()
+                    .get_by_pid(proc.pid)
+                    .and_then(|server| server.take_reply_right(token).then_some(server.server_id));
+
+                let Some(server_id) = server_id else {
+                    fail(proc, SyscallError::InvalidToken);
+                    return;
+                };
+
+                let reply = Message {
+                    capability,
+                    ..Message::new(proc.pid, server_id, identifier, data)
+                };
+
+                // The caller may have been killed in the meantime
+                let caller = procs
+                    .iter_mut()
+                    .find_map(|p| p.finish_call(token, &reply).then_some(p.pid));
+
+                let Some(caller) = caller else {
+                    fail(procs.current().unwrap(), SyscallError::Gone);
+                    return;
+                };
+
+                let caller_sid = ipc::server_list()
+                    .lock()
+                    .get_by_pid(caller)
+                    .map_or(0, |server| server.server_id);
+                trace()
+                    .lock()
+                    .record_reply(&reply, caller, caller_sid, token);
+            }
+
+            // Returns a handle to the new server with all rights
             SystemCall::RegisterServer => {
-                let public_name = proc.trap_frame.registers[Registers::A0 as usize];
+                let Ok(name) = user_string(proc, Registers::A0, Registers::A1) else {
+                    fail(proc, SyscallError::InvalidArgument);
+                    return;
+                };
 
-                let server_id = if public_name != 0 {
-                    ipc::server_list()
-                        .lock()
-                        .register(proc.pid, Some(public_name))
-                } else {
-                    ipc::server_list().lock().register(proc.pid, None)
+                // The queue limit is in `A2`, with 0 selecting the default
+                let queue_limit = proc.thread.trap_frame.user_state[Registers::A2] as usize;
+
+                let mut server_list = ipc::server_list().lock();
+                let capability = server_list
+                    .register(proc.pid, name)
+                    .and_then(|server_id| server_list.get_by_sid(server_id))
+                    .map(|server| {
+                        if queue_limit != 0 {
+                            server.queue_limit = queue_limit;
+                        }
+                        server.capability.clone()
+                    });
+
+                // Either the name is taken, or the process already is a server
+                let Some(capability) = capability else {
+                    fail(proc, SyscallError::AlreadyExists);
+                    return;
                 };
 
-                proc.trap_frame.registers[Registers::A0 as usize] = server_id.unwrap_or(u64::MAX);
+                proc.thread.trap_frame.user_state[Registers::A0] =
+                    proc.capabilities.lock().insert(capability);
+            }
+
+            // Anyone may send to servers that registered with a name, optionally waiting until one does
+            SystemCall::Connect => {
+                let Ok(Some(name)) = user_string(proc, Registers::A0, Registers::A1) else {
+                    fail(proc, SyscallError::InvalidArgument);
+                    return;
+                };
+                let wait = proc.thread.trap_frame.user_state[Registers::A2] != 0;
+
+                let capability = ipc::server_list()
+                    .lock()
+                    .get_by_name(&name)
+                    .map(|server| server.client_capability());
+
+                // Interrupt handlers may not block
+                let handling_interrupt =
+                    matches!(proc.state, ProcessState::HandlingInterrupt { .. });
+
+                match capability {
+                    Some(capability) => {
+                        proc.thread.trap_frame.user_state[Registers::A0] =
+                            proc.capabilities.lock().insert(capability);
+                    }
+                    None if !wait => fail(proc, SyscallError::NotFound),
+                    None if handling_interrupt => fail(proc, SyscallError::WouldBlock),
+                    None => proc.state = ProcessState::WaitingForServer { name },
+                }
+            }
+
+            SystemCall::CloseHandle => {
+                let handle = proc.thread.trap_frame.user_state[Registers::A0];
+                let closed = proc.capabilities.lock().remove(handle);
+                if closed.is_none() {
+                    fail(proc, SyscallError::InvalidHandle);
+                }
+            }
+
+            // Takes away every handle derived from the given one, from all processes
+            SystemCall::Revoke => {
+                let handle = proc.thread.trap_frame.user_state[Registers::A0];
+                let id = proc.capabilities.lock().get(handle).map(|c| c.id);
+
+                let Some(id) = id else {
+                    fail(proc, SyscallError::InvalidHandle);
+                    return;
+                };
+
+                let mut revoked = Vec::new();
+                let mut affected = Vec::new();
+                for proc in procs.iter_mut() {
+                    proc.capabilities.lock().revoke(id);
+                    let regions = proc.thread.address_space().revoke(id);
+                    if !regions.is_empty() {
+                        affected.push(proc.pid);
+                        revoked.extend(regions);
+                    }
+                }
+                ipc::server_list().lock().revoke(id);
+
+                // The revoked memory may only be freed once no other hart can reach it anymore
+                procs.flush_tlbs(&affected);
+                drop(revoked);
             }
 
             SystemCall::RegisterInterruptHandler => {
-                let interrupt = proc.trap_frame.registers[Registers::A0 as usize];
-                let handler = proc.trap_frame.registers[Registers::A1 as usize];
+                let interrupt = proc.thread.trap_frame.user_state[Registers::A0];
+                let handler = proc.thread.trap_frame.user_state[Registers::A1];
                 plic::add_user(interrupt as _, proc.pid, handler as _);
             }
 
@@ -222,57 +568,469 @@ pub fn handle() {
                     old_registers,
                     old_state,
                     interrupt_id,
+                    stack,
                 } = proc.state.clone()
                 {
                     // Deallocate the IRQ contexts stack
-                    let sp = proc.trap_frame.registers[Registers::StackPointer as usize] as _;
-                    proc.page_table.unmap(sp);
-                    memory::allocator().deallocate(sp as _);
+                    proc.thread.address_space().page_table.unmap(stack);
+                    memory::allocator().deallocate(stack as _);
 
                     // Restore the state before the interrupt
-                    proc.trap_frame.registers = *old_registers;
+                    proc.thread.trap_frame.user_state = *old_registers;
                     proc.state = *old_state;
                     plic::complete(interrupt_id);
                 } else {
-                    let pid = procs.remove_current().unwrap().pid;
+                    let pid = procs.remove_current(MISUSED).unwrap().pid;
                     println!("process {pid} tried to complete an interrupt without being in an interrupt handler. Killing process");
                 }
             }
 
-            SystemCall::TransferMemory => {
-                let sid = proc.trap_frame.registers[Registers::A0 as usize];
-                let start = proc.trap_frame.registers[Registers::A1 as usize] as usize;
-                let end = proc.trap_frame.registers[Registers::A2 as usize] as usize;
+            SystemCall::CreateMemory => {
+                let size = proc.thread.trap_frame.user_state[Registers::A0] as usize;
+                if size == 0 {
+                    fail(proc, SyscallError::InvalidArgument);
+                    return;
+                }
 
-                if !memory::is_page_aligned(start) || !memory::is_page_aligned(end) {
-                    let pid = procs.remove_current().unwrap().pid;
-                    println!("process {pid} tried to transfer memory that was not page aligned ({start:#x}..={end:#x}). Killing process");
+                let Some(memory) = SharedMemory::new(size) else {
+                    fail(proc, SyscallError::OutOfMemory);
+                    return;
+                };
+
+                proc.thread.trap_frame.user_state[Registers::A0] =
+                    proc.capabilities.lock().insert(Capability::memory(memory));
+            }
+
+            // Returns the address and the size of the mapping
+            SystemCall::MapMemory => {
+                let handle = proc.thread.trap_frame.user_state[Registers::A0];
+                let rights = Rights::from_raw(proc.thread.trap_frame.user_state[Registers::A1])
+                    .union(Rights::READ);
+
+                let capability = proc
+                    .capabilities
+                    .lock()
+                    .memory_with(handle, rights)
+                    .cloned();
+                let Some(capability) = capability else {
+                    fail(proc, SyscallError::InvalidHandle);
+                    return;
+                };
+
+                // Revoking a capability only removes the mappings through capabilities derived from it
+                let derived_from = capability.ancestors().to_vec();
+                let Object::Memory(memory) = capability.object else {
+                    unreachable!("memory handles refer to memory");
+                };
+
+                // Mappings only fail once there is no free range of addresses left for them
+                let size = memory.size();
+                let start = proc.thread.address_space().map_shared(
+                    memory,
+                    derived_from,
+                    rights.contains(Rights::WRITE),
+                );
+                let Some(start) = start else {
+                    fail(proc, SyscallError::OutOfMemory);
+                    return;
+                };
+
+                let user_state = &mut proc.thread.trap_frame.user_state;
+                user_state[Registers::A0] = start as _;
+                user_state[Registers::A1] = size as _;
+            }
+
+            SystemCall::UnmapMemory => {
+                let vaddr = proc.thread.trap_frame.user_state[Registers::A0] as usize;
+                let Some(region) = proc.thread.address_space().unmap_shared(vaddr) else {
+                    return fail(proc, SyscallError::InvalidAddress);
+                };
+
+                // Other threads of the process may still reach the memory, which could be freed along with the region
+                let pid = proc.pid;
+                procs.flush_tlbs(&[pid]);
+                drop(region);
+            }
+
+            SystemCall::Priority => {
+                let pid = proc.thread.trap_frame.user_state[Registers::A0];
+                let level = proc.thread.trap_frame.user_state[Registers::A1];
+
+                // `u64::MAX` selects the caller, and only queries the priority respectively
+                let caller = proc.pid;
+                let target = if pid == u64::MAX {
+                    caller
+                } else {
+                    pid as usize
+                };
+
+                let result = procs
+                    .find_pid(target)
+                    .map(|p| p.priority)
+                    .ok_or(SyscallError::NotFound)
+                    .and_then(|current| {
+                        if level == u64::MAX {
+                            return Ok(current);
+                        }
+
+                        let priority = u8::try_from(level)
+                            .ok()
+                            .and_then(Priority::new)
+                            .ok_or(SyscallError::InvalidArgument)?;
+                        if !may_set_priority(caller, target, current, priority) {
+                            return Err(SyscallError::PermissionDenied);
+                        }
+
+                        procs
+                            .set_priority(target, priority)
+                            .ok_or(SyscallError::NotFound)
+                    });
+
+                let proc = procs.current().unwrap();
+                match result {
+                    Ok(priority) => {
+                        proc.thread.trap_frame.user_state[Registers::A0] = priority.level() as _;
+                    }
+                    Err(error) => fail(proc, error),
+                }
+            }
+
+            SystemCall::ThreadCreate => {
+                let entry = proc.thread.trap_frame.user_state[Registers::A0] as usize;
+                let stack = proc.thread.trap_frame.user_state[Registers::A1] as usize;
+                let arg = proc.thread.trap_frame.user_state[Registers::A2];
+
+                let Some(thread) = proc.spawn_thread(entry, stack, arg) else {
+                    fail(proc, SyscallError::LimitReached);
+                    return;
+                };
+
+                proc.thread.trap_frame.user_state[Registers::A0] = thread.tid as _;
+                procs.push(thread);
+            }
+
+            SystemCall::ThreadExit => {
+                let (thread, last) = procs.remove_current_thread().unwrap();
+                if last {
+                    ipc::server_list().lock().remove_by_pid(thread.pid);
+                    println!("\nprocess {} exited with code 0", thread.pid);
+                }
+            }
+
+            SystemCall::ThreadJoin => {
+                let tid = proc.thread.trap_frame.user_state[Registers::A0] as usize;
+                let (pid, own_tid) = (proc.pid, proc.tid);
+
+                // Only undetached threads of the same process can be joined, and joining ourselves would never return.
+                // A thread that already exited is joined right away.
+                let exists = procs
+                    .find_tid(tid)
+                    .is_some_and(|thread| thread.pid == pid && !thread.detached);
+                let exited = !exists && procs.reap_thread(pid, tid);
+
+                let proc = procs.current().unwrap();
+                if tid == own_tid {
+                    fail(proc, SyscallError::InvalidArgument);
+                } else if exists {
+                    proc.state = ProcessState::WaitingForThread { tid };
+                } else if !exited {
+                    fail(proc, SyscallError::NotFound);
+                }
+            }
+
+            // Nothing is kept for a join once the thread exits, or the record of a thread that already did is dropped
+            SystemCall::ThreadDetach => {
+                let tid = proc.thread.trap_frame.user_state[Registers::A0] as usize;
+                let pid = proc.pid;
+
+                let found = match procs.find_tid(tid) {
+                    Some(thread) if thread.pid == pid => {
+                        thread.detached = true;
+                        true
+                    }
+                    _ => procs.reap_thread(pid, tid),
+                };
+
+                if !found {
+                    fail(procs.current().unwrap(), SyscallError::NotFound);
+                }
+            }
+
+            SystemCall::FutexWait => {
+                let vaddr = proc.thread.trap_frame.user_state[Registers::A0] as usize;
+                let expected = proc.thread.trap_frame.user_state[Registers::A1] as u32;
+                let timeout = proc.thread.trap_frame.user_state[Registers::A2];
+
+                if !(vaddr as *const AtomicU32).is_aligned() {
+                    fail(proc, SyscallError::InvalidArgument);
+                    return;
+                }
+
+                let Some(paddr) = proc.thread.address_space().resolve(vaddr) else {
+                    fail(proc, SyscallError::InvalidAddress);
+                    return;
+                };
+
+                // Waking up also requires the `PROCESSES` lock, so no wake up can be missed between this check and going to sleep
+                let value = unsafe { (*(paddr as *const AtomicU32)).load(Ordering::SeqCst) };
+
+                // Fails with `TimedOut` if the wait timed out. A changed value counts as a spurious wake up.
+                if value != expected {
+                    return;
+                }
+
+                // Interrupt handlers may not block
+                if matches!(proc.state, ProcessState::HandlingInterrupt { .. }) {
+                    fail(proc, SyscallError::WouldBlock);
+                } else {
+                    let deadline = (timeout != u64::MAX)
+                        .then(|| clint::time_since_bootup() + Duration::from_nanos(timeout));
+
+                    proc.state = ProcessState::FutexWait { paddr, deadline };
+                    futex::FUTEXES.lock().push(paddr, proc.tid);
+                }
+            }
+
+            SystemCall::FutexWake => {
+                let vaddr = proc.thread.trap_frame.user_state[Registers::A0] as usize;
+                let count = proc.thread.trap_frame.user_state[Registers::A1];
+
+                // Nobody can be waiting on memory that is not mapped
+                let paddr = proc.thread.address_space().page_table.physical_addr(vaddr);
+                let mut woken = 0;
+
+                if let Some(paddr) = paddr {
+                    let mut futexes = futex::FUTEXES.lock();
+                    while woken < count {
+                        let Some(tid) = futexes.pop(paddr) else {
+                            break;
+                        };
+
+                        if procs
+                            .find_tid(tid)
+                            .is_some_and(|thread| thread.wake_from_futex(paddr))
+                        {
+                            woken += 1;
+                        }
+                    }
+                }
+
+                procs.current().unwrap().thread.trap_frame.user_state[Registers::A0] = woken;
+            }
+
+            SystemCall::Wait => {
+                let child = proc.thread.trap_frame.user_state[Registers::A0];
+
+                // `u64::MAX` waits for any child
+                let child = (child != u64::MAX).then_some(child as usize);
+                let pid = proc.pid;
+
+                if let Some((child, status)) = procs.reap(pid, child) {
+                    procs.current().unwrap().finish_wait(child, status);
+                } else if procs.has_child(pid, child) {
+                    procs.current().unwrap().state = ProcessState::WaitingForChild { pid: child };
+                } else {
+                    fail(procs.current().unwrap(), SyscallError::NotFound);
+                }
+            }
+
+            // TODO: Capabilities, for now every process may signal every other one
+            SystemCall::Kill => {
+                let pid = proc.thread.trap_frame.user_state[Registers::A0] as usize;
+                let signal = Signal::try_from(proc.thread.trap_frame.user_state[Registers::A1]);
+
+                let Ok(signal) = signal else {
+                    fail(proc, SyscallError::InvalidArgument);
                     return;
+                };
+
+                let target = procs
+                    .iter_mut()
+                    .find(|p| p.pid == pid && p.state != ProcessState::Killed)
+                    .map(|target| target.signals.lock().raise(signal));
+
+                match target {
+                    Some(true) => {
+                        procs.kill(pid, ExitStatus::Killed(KillReason::Signal(signal)));
+                        println!("process {pid} was killed by signal {signal:?}");
+                    }
+
+                    Some(false) => procs.interrupt(pid),
+
+                    None => fail(procs.current().unwrap(), SyscallError::NotFound),
                 }
+            }
+
+            SystemCall::SignalAction => {
+                let signal = Signal::try_from(proc.thread.trap_frame.user_state[Registers::A0]);
+                let entry = proc.thread.trap_frame.user_state[Registers::A1] as usize;
+                let arg = proc.thread.trap_frame.user_state[Registers::A2];
 
-                let start = proc.page_table.physical_addr(start).unwrap();
-                let end = proc.page_table.physical_addr(end).unwrap();
+                // The entry is 0 for the default action, and 1 to ignore the signal
+                let action = match entry {
+                    0 => Action::Default,
+                    1 => Action::Ignore,
+                    entry => Action::Handle { entry, arg },
+                };
+
+                let previous = signal
+                    .ok()
+                    .and_then(|signal| proc.signals.lock().set_action(signal, action));
+
+                // The action of some signals cannot be changed
+                proc.thread.trap_frame.user_state[Registers::A0] = match previous {
+                    Some(Action::Default) => 0,
+                    Some(Action::Ignore) => 1,
+                    Some(Action::Handle { entry, .. }) => entry as _,
+                    None => return fail(proc, SyscallError::InvalidArgument),
+                };
+            }
+
+            SystemCall::SignalMask => {
+                let how =
+                    MaskHow::new_with_raw_value(proc.thread.trap_frame.user_state[Registers::A0]);
+                let set = SignalSet::from_raw(proc.thread.trap_frame.user_state[Registers::A1]);
+
+                let Ok(how) = how else {
+                    fail(proc, SyscallError::InvalidArgument);
+                    return;
+                };
 
-                for vaddr in (start..end).step_by(memory::PAGE_SIZE) {
-                    proc.page_table.unmap(vaddr);
+                let mut signals = proc.signals.lock();
+                let previous = signals.mask;
+                signals.mask = match how {
+                    MaskHow::Block => previous.union(set),
+                    MaskHow::Unblock => previous.difference(set),
+                    MaskHow::Set => set,
+                };
+
+                // Newly unmasked signals are delivered once the scheduler runs next
+                proc.thread.trap_frame.user_state[Registers::A0] = previous.raw();
+            }
+
+            SystemCall::SignalStack => {
+                let stack = proc.thread.trap_frame.user_state[Registers::A0] as usize;
+                proc.signal_stack = (stack != 0).then_some(stack);
+            }
+
+            SystemCall::SignalReturn => {
+                if !proc.return_from_signal() {
+                    let pid = procs.remove_current(MISUSED).unwrap().pid;
+                    println!("process {pid} tried to return from a signal handler without running one. Killing process");
+                }
+            }
+
+            SystemCall::ListProcesses => {
+                let buffer = proc.thread.trap_frame.user_state[Registers::A0] as usize;
+                let capacity = proc.thread.trap_frame.user_state[Registers::A1] as usize;
+
+                // Only as many processes as fit are written, but the total is always returned
+                let infos = procs.snapshot();
+                let written = &infos[..infos.len().min(capacity)];
+                let bytes = unsafe {
+                    slice::from_raw_parts(written.as_ptr().cast::<u8>(), size_of_val(written))
+                };
+
+                let proc = procs.current().unwrap();
+                if !bytes.is_empty()
+                    && proc
+                        .thread
+                        .address_space()
+                        .copy_to_user(buffer, bytes)
+                        .is_none()
+                {
+                    fail(proc, SyscallError::InvalidAddress);
+                    return;
                 }
 
-                if let Some(server) = ipc::server_list().lock().get_by_sid(sid) {
-                    if let Some(receiver) = procs.find_pid(server.process_id) {
-                        receiver.page_table.identity_map(
-                            start,
-                            end,
-                            memory::page::EntryAttributes::UserReadWrite,
-                        );
+                proc.thread.trap_frame.user_state[Registers::A0] = infos.len() as _;
+            }
+
+            // Returns the ID the server is recorded with
+            SystemCall::TraceServer => {
+                let traced = proc.thread.trap_frame.user_state[Registers::A1] != 0;
+                let server_id = target_server(proc);
+                let pid = proc.pid;
+
+                let result = match server_id {
+                    None => Err(SyscallError::InvalidHandle),
+                    Some(_) if !may_trace(&mut procs, pid) => Err(SyscallError::PermissionDenied),
+                    Some(server_id) => {
+                        trace().lock().set_traced(pid, server_id, traced);
+                        Ok(server_id)
                     }
+                };
+
+                let proc = procs.current().unwrap();
+                match result {
+                    Ok(server_id) => proc.thread.trap_frame.user_state[Registers::A0] = server_id,
+                    Err(error) => fail(proc, error),
+                }
+            }
+
+            // Takes the oldest records that fit into the buffer, returning how many were written
+            SystemCall::ReadTrace => {
+                let buffer = proc.thread.trap_frame.user_state[Registers::A0] as usize;
+                let capacity = proc.thread.trap_frame.user_state[Registers::A1] as usize;
+                let pid = proc.pid;
+
+                if !may_trace(&mut procs, pid) {
+                    fail(procs.current().unwrap(), SyscallError::PermissionDenied);
+                    return;
+                }
+
+                // Records are only removed once they were copied, so that none are lost to an invalid buffer
+                let mut trace = trace().lock();
+                let records = trace.peek(capacity);
+                let bytes = unsafe {
+                    slice::from_raw_parts(records.as_ptr().cast::<u8>(), size_of_val(&records[..]))
+                };
+
+                let proc = procs.current().unwrap();
+                if !bytes.is_empty()
+                    && proc
+                        .thread
+                        .address_space()
+                        .copy_to_user(buffer, bytes)
+                        .is_none()
+                {
+                    fail(proc, SyscallError::InvalidAddress);
+                    return;
+                }
+
+                trace.discard(records.len());
+                proc.thread.trap_frame.user_state[Registers::A0] = records.len() as _;
+            }
+
+            SystemCall::FindDevice => {
+                let compatible_ptr = proc.thread.trap_frame.user_state[Registers::A0];
+                let compatible_len = proc.thread.trap_frame.user_state[Registers::A1];
+                let index = proc.thread.trap_frame.user_state[Registers::A2];
+
+                let compatible = proc
+                    .thread
+                    .address_space()
+                    .copy_from_user(compatible_ptr as _, compatible_len as _)
+                    .and_then(|bytes| String::from_utf8(bytes).ok());
+
+                let Some(compatible) = compatible else {
+                    fail(proc, SyscallError::InvalidArgument);
+                    return;
+                };
+
+                let device = devicetree::find_devices(&compatible).nth(index as _);
+                if let Some(device) = device {
+                    proc.thread.trap_frame.user_state[Registers::A0] = device.region.start;
+                    proc.thread.trap_frame.user_state[Registers::A1] = device.region.size;
+                    proc.thread.trap_frame.user_state[Registers::A2] =
+                        device.interrupt.map_or(u64::MAX, u64::from);
                 } else {
-                    let pid = procs.remove_current().unwrap().pid;
-                    println!("process {pid} tried to transfer memory to a non-existent server {sid}. Killing process");
+                    fail(proc, SyscallError::NotFound);
                 }
             }
         }
     } else {
-        let offender = procs.remove_current().unwrap().pid;
+        let offender = procs.remove_current(MISUSED).unwrap().pid;
         println!("killed process {offender} because of an invalid system call: {syscall:?}");
     }
 }
@@ -280,7 +1038,7 @@ pub fn handle() {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use syscall::SystemCallError;
+    use syscall::{ProcessInfo, SystemCallError};
 
     #[test_case]
     fn raw_value() {
@@ -306,4 +1064,77 @@ mod tests {
             SystemCallError::Invalid(u64::MAX)
         );
     }
+
+    #[test_case]
+    fn exit_status_raw_value() {
+        let statuses = [
+            ExitStatus::SUCCESS,
+            ExitStatus::Exited(-1),
+            ExitStatus::Killed(KillReason::Exception(13)),
+            MISUSED,
+            ExitStatus::Killed(KillReason::OutOfMemory),
+        ];
+
+        for status in statuses {
+            let (kind, value) = status.into_raw();
+            assert_eq!(ExitStatus::from_raw(kind, value), Some(status));
+        }
+        assert_eq!(ExitStatus::from_raw(u64::MAX, 0), None);
+    }
+
+    #[test_case]
+    fn process_info_name_is_truncated() {
+        let info = ProcessInfo::new(1, "init");
+        assert_eq!(info.name(), "init");
+        assert_eq!(info.parent(), None);
+
+        // Names are cut off at a character boundary
+        let long = "ä".repeat(ProcessInfo::NAME_LEN);
+        let info = ProcessInfo::new(2, &long);
+        assert_eq!(info.name(), &long[..ProcessInfo::NAME_LEN]);
+
+        let long = alloc::format!("x{long}");
+        let info = ProcessInfo::new(3, &long);
+        assert_eq!(info.name(), &long[..ProcessInfo::NAME_LEN - 1]);
+    }
+
+    #[test_case]
+    fn priority_permissions() {
+        let raised = Priority::new(Priority::DEFAULT.level() + 1).unwrap();
+
+        // Init may do anything
+        assert!(may_set_priority(
+            INIT_PID,
+            42,
+            Priority::DEFAULT,
+            Priority::HIGHEST
+        ));
+
+        // Others may only lower their own priority, unless they are drivers
+        assert!(may_set_priority(
+            42,
+            42,
+            Priority::DEFAULT,
+            Priority::LOWEST
+        ));
+        assert!(may_set_priority(
+            42,
+            42,
+            Priority::LOWEST,
+            Priority::DEFAULT
+        ));
+        assert!(!may_set_priority(42, 42, Priority::DEFAULT, raised));
+        assert!(!may_set_priority(
+            42,
+            42,
+            Priority::DEFAULT,
+            Priority::REAL_TIME
+        ));
+        assert!(!may_set_priority(
+            42,
+            43,
+            Priority::DEFAULT,
+            Priority::LOWEST
+        ));
+    }
 }
@@ -1,8 +1,8 @@
 //! Integration for Rust language features that are not implemented by default on `no_std`.
 
+use crate::power;
 #[cfg(test)]
 use core::any::type_name;
-use crate::power;
 use core::{arch::asm, panic::PanicInfo};
 
 /// A wrapper around `Fn()` which can be used as a trait object,
@@ -1,14 +1,14 @@
-use super::{switch_into, user_trap_vector, TRAPFRAME_PTR};
-use crate::memory::page;
+use super::{switch_into, user_trap_vector};
+use crate::{hart, memory::page};
 use alloc::boxed::Box;
 use bitbybit::bitenum;
 use core::{
     arch::global_asm,
     fmt,
-    ops::{Index, IndexMut},
+    ops::{Index, IndexMut, RangeInclusive},
 };
 
-global_asm!(include_str!("switch.asm"), TRAPFRAME_PTR = const TRAPFRAME_PTR);
+global_asm!(include_str!("switch.asm"));
 
 /// NOTE: the numbering of these registers is important, as they are used as offsets in assembly.
 #[bitenum(u64)]
@@ -50,18 +50,39 @@ pub enum Registers {
     S7 = 28,
     S8 = 29,
     S9 = 30,
+    S10 = 31,
+    S11 = 32,
 }
 
 impl Registers {
     pub const fn len() -> usize {
-        31
+        33
     }
 }
 
-#[derive(Default, PartialEq, Eq)]
+/// The users floating point registers `f0`-`f31` and `fcsr`, stored as raw bits.
+/// They are only saved when the user modified them, as tracked by `sstatus.FS`, but always restored.
+#[derive(Default, PartialEq, Eq, Clone)]
+#[repr(C)]
+pub struct FloatState {
+    pub registers: [u64; 32],
+    pub fcsr: u64,
+}
+
+#[derive(PartialEq, Eq, Clone)]
 #[repr(C)]
 pub struct UserState {
     registers: [u64; Registers::len()],
+    pub float_state: FloatState,
+}
+
+impl Default for UserState {
+    fn default() -> Self {
+        Self {
+            registers: [0; Registers::len()],
+            float_state: FloatState::default(),
+        }
+    }
 }
 
 impl UserState {
@@ -95,25 +116,56 @@ impl IndexMut<Registers> for UserState {
     }
 }
 
+impl Index<RangeInclusive<Registers>> for UserState {
+    type Output = [u64];
+
+    fn index(&self, index: RangeInclusive<Registers>) -> &Self::Output {
+        let (start, end) = index.into_inner();
+        &self.registers[start as usize..=end as usize]
+    }
+}
+
+impl IndexMut<RangeInclusive<Registers>> for UserState {
+    fn index_mut(&mut self, index: RangeInclusive<Registers>) -> &mut Self::Output {
+        let (start, end) = index.into_inner();
+        &mut self.registers[start as usize..=end as usize]
+    }
+}
+
 #[derive(PartialEq, Eq)]
 #[repr(C)]
 struct KernelState {
     satp: u64,
     trap_vector_ptr: u64,
     stack_start: *const u8,
+    hart_id: u64,
+    /// Where the trap frame is mapped in the address space of the user.
+    trap_frame_vaddr: u64,
 }
 
 impl KernelState {
-    fn new(stack_start: *const u8) -> Self {
-        Self {
+    fn new() -> Self {
+        let mut result = Self {
             satp: page::root_table().build_satp() as _,
             trap_vector_ptr: user_trap_vector as *const u8 as _,
-            stack_start,
-        }
+            stack_start: core::ptr::null(),
+            hart_id: 0,
+            trap_frame_vaddr: 0,
+        };
+        result.set_hart();
+        result
+    }
+
+    /// Make traps return to the hart we are running on.
+    /// No context is preserved on the kernel stack between traps, so every thread can share the stack of its hart.
+    fn set_hart(&mut self) {
+        self.stack_start = hart::stack_top() as _;
+        self.hart_id = hart::id() as _;
     }
 }
 
-#[repr(C)]
+/// Page aligned, as it gets mapped into the address space of the user.
+#[repr(C, align(4096))]
 #[derive(Debug, PartialEq, Eq)]
 pub struct TrapFrame {
     kernel_state: KernelState,
@@ -121,13 +173,22 @@ pub struct TrapFrame {
 }
 
 impl TrapFrame {
-    pub fn new(kernel_stack: *const u8, user_stack: *const u8) -> Box<Self> {
+    pub fn new(user_stack: *const u8) -> Box<Self> {
         Box::new(Self {
-            kernel_state: KernelState::new(kernel_stack),
+            kernel_state: KernelState::new(),
             user_state: UserState::new(user_stack),
         })
     }
 
+    /// Where the trap frame is mapped in the address space of the user.
+    pub fn vaddr(&self) -> usize {
+        self.kernel_state.trap_frame_vaddr as _
+    }
+
+    pub fn set_vaddr(&mut self, vaddr: usize) {
+        self.kernel_state.trap_frame_vaddr = vaddr as _;
+    }
+
     pub fn set_user_satp(&mut self, satp: u64) {
         self.user_state[Registers::Satp] = satp;
     }
@@ -136,7 +197,8 @@ impl TrapFrame {
         self as *const _
     }
 
-    pub unsafe fn run(&self) -> ! {
+    pub unsafe fn run(&mut self) -> ! {
+        self.kernel_state.set_hart();
         switch_into(self)
     }
 }
@@ -147,6 +209,7 @@ impl fmt::Debug for UserState {
         for (name, value) in self.iter_names() {
             writeln!(f, "    {name:?}: {value:#x}, ")?;
         }
+        writeln!(f, "    Fcsr: {:#x}, ", self.float_state.fcsr)?;
         write!(f, "}}")?;
         Ok(())
     }
@@ -161,6 +224,65 @@ impl fmt::Debug for KernelState {
                 &format_args!("{:#x}", self.trap_vector_ptr),
             )
             .field("stack_start", &format_args!("{:#p}", self.stack_start))
+            .field("hart_id", &self.hart_id)
+            .field(
+                "trap_frame_vaddr",
+                &format_args!("{:#x}", self.trap_frame_vaddr),
+            )
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use core::mem::offset_of;
+
+    /// The register and offset of every `instruction register, offset(a0)` line in `switch.asm`.
+    fn accesses(instruction: &str) -> Vec<(&'static str, usize)> {
+        include_str!("switch.asm")
+            .lines()
+            .filter_map(|line| {
+                let operands = line.trim().strip_prefix(instruction)?.strip_prefix(' ')?;
+                let (register, offset) = operands.split_once(", ")?;
+                let offset = offset.strip_suffix("(a0)")?.parse().ok()?;
+                Some((register, offset))
+            })
+            .collect()
+    }
+
+    #[test_case]
+    fn layout_matches_switch_asm() {
+        assert_eq!(offset_of!(TrapFrame, user_state), 40);
+        assert_eq!(
+            offset_of!(TrapFrame, user_state) + offset_of!(UserState, float_state),
+            304
+        );
+        assert_eq!(
+            offset_of!(TrapFrame, user_state)
+                + offset_of!(UserState, float_state)
+                + offset_of!(FloatState, fcsr),
+            560
+        );
+    }
+
+    #[test_case]
+    fn float_registers_are_restored_from_where_they_are_saved() {
+        let float_state = offset_of!(TrapFrame, user_state) + offset_of!(UserState, float_state);
+        let fcsr = float_state + offset_of!(FloatState, fcsr);
+
+        for instruction in ["fsd", "fld"] {
+            let accesses = accesses(instruction);
+            assert_eq!(accesses.len(), 32);
+            for (index, (register, offset)) in accesses.into_iter().enumerate() {
+                assert_eq!(register, alloc::format!("f{index}"));
+                assert_eq!(offset, float_state + (index * 8));
+            }
+        }
+
+        // `fcsr` is saved and restored through `t0`
+        assert!(accesses("sd").contains(&("t0", fcsr)));
+        assert!(accesses("ld").contains(&("t0", fcsr)));
+    }
+}
@@ -1,11 +1,15 @@
 use crate::{
-    memory::{self, align_page_down, page, PAGE_SIZE},
-    spinlock::SpinLock,
+    hart,
+    memory::{self, align_page_down, page, shared::SharedMemory, PAGE_SIZE},
+    spinlock::{SpinLock, SpinLockGuard},
 };
-use alloc::{boxed::Box, fmt};
-use core::pin::Pin;
+use alloc::{boxed::Box, fmt, sync::Arc, vec::Vec};
+use context::Registers;
+use core::ops::Range;
+use region::{Access, Backing, Region, RegionList};
 
 pub mod context;
+pub mod region;
 
 // Defined in `switch.asm`
 extern "C" {
@@ -13,137 +17,264 @@ extern "C" {
     pub fn user_trap_vector();
 }
 
+/// The trap frame of the first thread, the ones of other threads are mapped below it.
 const TRAPFRAME_PTR: usize = align_page_down(usize::MAX);
-const KERNEL_STACK_SIZE: usize = PAGE_SIZE;
-const USER_STACK_SIZE: usize = 5 * PAGE_SIZE;
+const MAX_THREADS: usize = u64::BITS as usize;
 
-static THREAD_CONTROLLER: SpinLock<Option<ThreadController>> = SpinLock::new(None);
+/// The top of the user stack. The trapframe slots end one page above it, which stays unmapped as a guard between them.
+const USER_STACK_TOP: usize = TRAPFRAME_PTR - (MAX_THREADS * PAGE_SIZE);
+const USER_STACK_MAX_SIZE: usize = 256 * PAGE_SIZE; // 1 MiB
 
-#[derive(Debug)]
-pub struct ThreadController {
-    pub thread: Thread,
-    trap_handler: Option<*const fn()>,
+/// Where shared memory is mapped, far above the identity mapped heap and below the user stack.
+const SHARED_MEMORY_WINDOW: Range<usize> = 0x10_0000_0000..0x20_0000_0000;
+
+/// The memory of a process, shared between all of its threads.
+pub struct AddressSpace {
+    pub page_table: Box<page::Table>,
+    pub regions: RegionList,
+    /// A bit for every trapframe slot that is in use by a thread.
+    trap_frame_slots: u64,
 }
 
-impl ThreadController {
-    pub const fn new(thread: Thread) -> Self {
+impl AddressSpace {
+    fn new() -> Self {
+        let mut page_table = Box::new(page::Table::new());
+        let mut regions = RegionList::default();
+
+        // Map the trampoline so that we return to the kernel after a trap.
+        memory::sections::map_trampoline(&mut page_table);
+
+        // Map the entry function so that we can continue with it after switching to the target page table.
+        page_table.identity_map(
+            switch_into as usize,
+            switch_into as usize + PAGE_SIZE, // TODO: how do we know the size of this?
+            page::EntryAttributes::ReadExecute,
+        );
+
+        // The stack of the first thread starts out with a single page, and grows as it gets used.
+        regions.insert(Region::new(
+            (USER_STACK_TOP - PAGE_SIZE)..USER_STACK_TOP,
+            page::EntryAttributes::UserReadWrite,
+            Backing::Stack {
+                max_size: USER_STACK_MAX_SIZE,
+            },
+        ));
+
         Self {
-            thread,
-            trap_handler: None,
+            page_table,
+            regions,
+            trap_frame_slots: 0,
         }
     }
-}
 
-pub fn handle_trap(cause: u64) {
-    let controller: &mut ThreadController = unsafe { &mut *(thread_controller() as *mut _) };
-    if controller.thread.trap_frame.user_state[context::Registers::A0] == 1
-        && controller.trap_handler.is_none()
-    {
-        attach_trap_handler(controller);
+    /// Map the given trapframe into a free slot, returning the address it was mapped at.
+    fn map_trap_frame(&mut self, trap_frame: &context::TrapFrame) -> Option<usize> {
+        let slot = (!self.trap_frame_slots).trailing_zeros() as usize;
+        if slot >= MAX_THREADS {
+            return None;
+        }
+
+        let vaddr = TRAPFRAME_PTR - (slot * PAGE_SIZE);
+        self.trap_frame_slots |= 1 << slot;
+        self.page_table.map_page(
+            vaddr,
+            trap_frame.as_ptr() as _,
+            page::EntryAttributes::ReadWrite,
+        );
+
+        Some(vaddr)
     }
 
-    if let Some(trap_handler) = controller.trap_handler {
-        controller.thread.trap_frame.user_state[context::Registers::ProgramCounter] =
-            trap_handler as _;
-        controller.thread.trap_frame.user_state[context::Registers::A0] = cause;
+    fn unmap_trap_frame(&mut self, vaddr: usize) {
+        let slot = (TRAPFRAME_PTR - vaddr) / PAGE_SIZE;
+        self.trap_frame_slots &= !(1 << slot);
+        self.page_table.unmap(vaddr);
     }
 
-    unsafe { controller.thread.switch_into() };
-}
+    /// Resolve a page fault at the given address, returns whether the faulting access can be retried.
+    pub fn handle_page_fault(&mut self, vaddr: usize, access: Access) -> bool {
+        self.regions
+            .handle_fault(&mut self.page_table, vaddr, access)
+    }
 
-fn attach_trap_handler(controller: &mut ThreadController) {
-    assert!(controller.trap_handler.is_none());
-    assert_eq!(
-        controller.thread.trap_frame.user_state[context::Registers::A0],
-        1
-    );
+    /// Map shared memory at a free address, which is unmapped again once a capability it was derived from is revoked.
+    /// Returns the address it was mapped at.
+    pub fn map_shared(
+        &mut self,
+        memory: Arc<SharedMemory>,
+        derived_from: Vec<u64>,
+        writable: bool,
+    ) -> Option<usize> {
+        let start = self
+            .regions
+            .find_free(SHARED_MEMORY_WINDOW, memory.size())?;
+        let attributes = if writable {
+            page::EntryAttributes::UserReadWrite
+        } else {
+            page::EntryAttributes::UserRead
+        };
 
-    controller.trap_handler =
-        Some(controller.thread.trap_frame.user_state[context::Registers::A1] as *const fn());
-}
+        let range = start..start + memory.size();
+        let backing = Backing::Shared {
+            memory,
+            derived_from,
+        };
+        self.regions.insert(Region::new(range, attributes, backing));
+        Some(start)
+    }
 
-pub fn set_thread_controller(thread: Thread) {
-    THREAD_CONTROLLER.lock_with(|controller| {
-        *controller = Some(ThreadController::new(thread));
-    });
-}
+    /// Unmap the shared memory that was mapped at the given address, returning its region if there was any.
+    /// Its memory must only be freed once no hart can reach it through its TLB anymore, see `hart::flush_tlbs`.
+    pub fn unmap_shared(&mut self, vaddr: usize) -> Option<Region> {
+        self.regions.remove_shared(&mut self.page_table, vaddr)
+    }
+
+    /// Unmap the shared memory that was mapped through a capability derived from the one with the given ID,
+    /// returning the removed regions. The same as for `unmap_shared` applies to their memory.
+    pub fn revoke(&mut self, id: u64) -> Vec<Region> {
+        self.regions.revoke(&mut self.page_table, id)
+    }
 
-pub unsafe fn thread_controller() -> *const ThreadController {
-    THREAD_CONTROLLER.lock_with(|controller| controller.as_ref().unwrap() as _)
+    /// The physical address backing the given address, populating its page if it was not accessed yet.
+    pub fn resolve(&mut self, vaddr: usize) -> Option<usize> {
+        if let Some(paddr) = self.page_table.physical_addr(vaddr) {
+            return Some(paddr);
+        }
+
+        self.handle_page_fault(vaddr, Access::Read);
+        self.page_table.physical_addr(vaddr)
+    }
+
+    /// Copy memory out of this address space, populating any pages that were not accessed yet.
+    pub fn copy_from_user(&mut self, vaddr: usize, len: usize) -> Option<Vec<u8>> {
+        for page in memory::page_offsets(len + (vaddr - align_page_down(vaddr))) {
+            let page = align_page_down(vaddr) + page;
+            if self.page_table.physical_addr(page).is_none() {
+                self.handle_page_fault(page, Access::Read);
+            }
+        }
+
+        self.page_table.copy_from(vaddr, len)
+    }
+
+    /// Copy memory into this address space, populating any pages that were not accessed yet.
+    /// Returns `None` without copying anything if part of the destination is not writable.
+    pub fn copy_to_user(&mut self, vaddr: usize, data: &[u8]) -> Option<()> {
+        for page in memory::page_offsets(data.len() + (vaddr - align_page_down(vaddr))) {
+            let page = align_page_down(vaddr) + page;
+            if self.page_table.physical_addr(page).is_none() {
+                self.handle_page_fault(page, Access::Write);
+            }
+        }
+
+        self.page_table.copy_to(vaddr, data)
+    }
 }
 
 pub struct Thread {
     pub trap_frame: Box<context::TrapFrame>,
-    pub page_table: Box<page::Table>,
-    user_stack: Pin<Box<[u8; USER_STACK_SIZE]>>,
-    kernel_stack: Pin<Box<[u8; KERNEL_STACK_SIZE]>>,
+    pub address_space: Arc<SpinLock<AddressSpace>>,
 }
 
 impl Thread {
+    /// Create the first thread of a new address space.
     pub fn new() -> Self {
-        let kernel_stack = Box::pin([0; KERNEL_STACK_SIZE]);
-        let user_stack = Box::pin([0; USER_STACK_SIZE]);
-        let mut page_table = Box::new(page::Table::new());
-
-        let mut trap_frame = unsafe {
-            context::TrapFrame::new(
-                kernel_stack.as_ptr().add(kernel_stack.len()),
-                user_stack.as_ptr().add(user_stack.len()),
-            )
-        };
-
-        // Map the trampoline so that we return to the kernel after a trap.
-        memory::sections::map_trampoline(&mut page_table);
-
-        // Map the entry function so that we can continue with it after switching to the target page table.
-        page_table.identity_map(
-            switch_into as usize,
-            switch_into as usize + PAGE_SIZE, // TODO: how do we know the size of this?
-            page::EntryAttributes::ReadExecute,
-        );
-
-        // Map the trapframe for the user so that we can store context upon a trap.
-        page_table.map_page(
-            TRAPFRAME_PTR,
-            trap_frame.as_ptr() as _,
-            page::EntryAttributes::ReadWrite,
-        );
+        let address_space = Arc::new(SpinLock::new(AddressSpace::new()));
+        Self::new_in(address_space, USER_STACK_TOP as _).unwrap()
+    }
 
-        // Map the trapframe for the kernel so that we can store context upon a trap.
-        page::root_table().map_page(
-            TRAPFRAME_PTR,
-            trap_frame.as_ptr() as _,
-            page::EntryAttributes::ReadWrite,
-        );
+    /// Create a thread that shares the given address space, or `None` if it already has the maximum amount of threads.
+    pub fn new_in(address_space: Arc<SpinLock<AddressSpace>>, stack: *const u8) -> Option<Self> {
+        let mut trap_frame = context::TrapFrame::new(stack);
 
-        for page in 0..=memory::pages_needed(user_stack.len()) {
-            let page_addr = user_stack.as_ptr() as usize + (page * PAGE_SIZE);
-            println!("mapping user stack page {:x}", page_addr);
-            page_table.map_page(page_addr, page_addr, page::EntryAttributes::UserReadWrite);
+        {
+            let mut space = address_space.lock();
+            let vaddr = space.map_trap_frame(&trap_frame)?;
+            trap_frame.set_vaddr(vaddr);
+            trap_frame.set_user_satp(space.page_table.build_satp() as _);
         }
 
-        trap_frame.set_user_satp(page_table.build_satp() as _);
-
-        Self {
+        Some(Self {
             trap_frame,
-            page_table,
-            kernel_stack,
-            user_stack,
-        }
+            address_space,
+        })
+    }
+
+    /// Create a thread that shares the address space of this one, starting at `entry` with the given argument in `a0`.
+    pub fn spawn(&self, entry: usize, stack: usize, arg: u64) -> Option<Self> {
+        let mut thread = Self::new_in(self.address_space.clone(), stack as _)?;
+        let user_state = &mut thread.trap_frame.user_state;
+        user_state[Registers::ProgramCounter] = entry as _;
+        user_state[Registers::A0] = arg;
+        user_state[Registers::GlobalPointer] = self.trap_frame.user_state[Registers::GlobalPointer];
+        Some(thread)
     }
 
-    pub unsafe fn switch_into(&self) -> ! {
+    pub fn address_space(&self) -> SpinLockGuard<'_, AddressSpace> {
+        self.address_space.lock()
+    }
+
+    pub unsafe fn switch_into(&mut self) -> ! {
+        hart::enter_user();
         self.trap_frame.run();
     }
 }
 
+impl Drop for Thread {
+    fn drop(&mut self) {
+        let vaddr = self.trap_frame.vaddr();
+        self.address_space().unmap_trap_frame(vaddr);
+    }
+}
+
 impl fmt::Debug for Thread {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let space = self.address_space();
         f.debug_struct("Thread")
             .field("trap_frame", &self.trap_frame)
-            .field("page_table", &(&self.page_table as *const _))
-            .field("user_stack", &self.user_stack.as_ptr())
-            .field("kernel_stack", &self.kernel_stack.as_ptr())
+            .field("page_table", &(&space.page_table as *const _))
+            .field("regions", &space.regions)
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn threads_reuse_trap_frame_slots() {
+        let main = Thread::new();
+        assert_eq!(main.trap_frame.vaddr(), TRAPFRAME_PTR);
+
+        let second = main.spawn(0, USER_STACK_TOP, 0).unwrap();
+        assert_eq!(second.trap_frame.vaddr(), TRAPFRAME_PTR - PAGE_SIZE);
+
+        drop(second);
+        let third = main.spawn(0, USER_STACK_TOP, 0).unwrap();
+        assert_eq!(third.trap_frame.vaddr(), TRAPFRAME_PTR - PAGE_SIZE);
+    }
+
+    #[test_case]
+    fn copy_to_user_needs_writable_pages() {
+        let thread = Thread::new();
+        let mut space = thread.address_space();
+
+        // Crossing a page boundary of the stack
+        let vaddr = USER_STACK_TOP - PAGE_SIZE - 2;
+        assert_eq!(space.copy_to_user(vaddr, &[1, 2, 3, 4]), Some(()));
+        assert_eq!(
+            space.copy_from_user(vaddr, 4).as_deref(),
+            Some(&[1, 2, 3, 4][..])
+        );
+
+        let read_only = 0x4000_0000;
+        space.regions.insert(Region::new(
+            read_only..read_only + PAGE_SIZE,
+            page::EntryAttributes::UserRead,
+            Backing::Anonymous,
+        ));
+        assert_eq!(space.copy_to_user(read_only, &[1]), None);
+        assert_eq!(space.copy_to_user(TRAPFRAME_PTR, &[1]), None);
+    }
+}
@@ -0,0 +1,391 @@
+use crate::memory::{self, align_page_down, page, shared::SharedMemory, PAGE_SIZE};
+use alloc::{sync::Arc, vec::Vec};
+use core::{fmt, ops::Range};
+
+/// The kind of access that caused a page fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+/// Where the contents of a region come from once one of its pages is first accessed.
+#[derive(Clone)]
+pub enum Backing {
+    /// Zero-filled memory.
+    Anonymous,
+    /// A segment of an ELF file starting at `vaddr`, anything past its `size` bytes of data is zero-filled.
+    Elf {
+        data: Arc<[u8]>,
+        vaddr: usize,
+        offset: usize,
+        size: usize,
+    },
+    /// Zero-filled memory that grows downwards on faults, until the region spans `max_size` bytes.
+    /// The page below that is reserved as a guard, so that an overflowing stack never runs into another region.
+    Stack { max_size: usize },
+    /// Memory that is shared with other address spaces. The mapping is removed once any of the
+    /// capabilities it was mapped through, as given by their IDs, is revoked.
+    Shared {
+        memory: Arc<SharedMemory>,
+        derived_from: Vec<u64>,
+    },
+}
+
+impl fmt::Debug for Backing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Anonymous => write!(f, "Anonymous"),
+            Self::Elf { vaddr, size, .. } => write!(f, "Elf({vaddr:#x}, {size:#x})"),
+            Self::Stack { max_size } => write!(f, "Stack({max_size:#x})"),
+            Self::Shared { memory, .. } => write!(f, "Shared({:#x})", memory.size()),
+        }
+    }
+}
+
+/// A page aligned range of virtual memory whose pages are only allocated once they are accessed.
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub range: Range<usize>,
+    pub attributes: page::EntryAttributes,
+    pub backing: Backing,
+}
+
+impl Region {
+    pub fn new(range: Range<usize>, attributes: page::EntryAttributes, backing: Backing) -> Self {
+        assert!(memory::is_page_aligned(range.start) && memory::is_page_aligned(range.end));
+        Self {
+            range,
+            attributes,
+            backing,
+        }
+    }
+
+    /// The addresses this region may cover, including those a stack could still grow into and its guard page.
+    fn reserved(&self) -> Range<usize> {
+        match self.backing {
+            Backing::Stack { max_size } => (self.range.end - max_size - PAGE_SIZE)..self.range.end,
+            _ => self.range.clone(),
+        }
+    }
+
+    fn allows(&self, access: Access) -> bool {
+        let flag = match access {
+            Access::Read => page::EntryAttributes::Readable,
+            Access::Write => page::EntryAttributes::Writable,
+            Access::Execute => page::EntryAttributes::Executable,
+        };
+
+        (self.attributes.clone() as usize & flag as usize) != 0
+    }
+
+    /// Fill a freshly allocated page at the given virtual address with its initial contents.
+    fn fill(&self, vaddr: usize, page: &mut [u8]) {
+        page.fill(0);
+
+        if let Backing::Elf {
+            data,
+            vaddr: segment_start,
+            offset,
+            size,
+        } = &self.backing
+        {
+            let start = vaddr.max(*segment_start);
+            let end = (vaddr + PAGE_SIZE).min(segment_start + size);
+            if start < end {
+                let data_start = offset + (start - segment_start);
+                page[start - vaddr..end - vaddr]
+                    .copy_from_slice(&data[data_start..data_start + (end - start)]);
+            }
+        }
+    }
+}
+
+/// The regions of a single address space, along with the frames that were allocated for them.
+#[derive(Debug, Default)]
+pub struct RegionList {
+    regions: Vec<Region>,
+    frames: Vec<usize>,
+}
+
+impl RegionList {
+    pub fn insert(&mut self, region: Region) {
+        assert!(
+            self.is_free(&region.reserved()),
+            "region {region:?} overlaps with an existing region"
+        );
+
+        self.regions.push(region);
+    }
+
+    /// Whether no region covers or reserves any address in the given range.
+    pub fn is_free(&self, range: &Range<usize>) -> bool {
+        !self
+            .regions
+            .iter()
+            .map(Region::reserved)
+            .any(|other| range.start < other.end && other.start < range.end)
+    }
+
+    pub fn find(&self, vaddr: usize) -> Option<&Region> {
+        self.regions
+            .iter()
+            .find(|region| region.reserved().contains(&vaddr))
+    }
+
+    /// Map the page containing the given address if it belongs to a region that permits the access.
+    /// Returns whether the access can be retried.
+    pub fn handle_fault(
+        &mut self,
+        page_table: &mut page::Table,
+        vaddr: usize,
+        access: Access,
+    ) -> bool {
+        let page_addr = align_page_down(vaddr);
+
+        // The page is already mapped, so the access itself was not permitted
+        if page_table.physical_addr(page_addr).is_some() {
+            return false;
+        }
+
+        let Some(region) = self
+            .regions
+            .iter_mut()
+            .find(|region| region.reserved().contains(&vaddr))
+        else {
+            return false;
+        };
+
+        if !region.allows(access) {
+            return false;
+        }
+
+        // The stack overflowed into its guard page
+        if let Backing::Stack { max_size } = region.backing {
+            if page_addr < region.range.end - max_size {
+                return false;
+            }
+        }
+
+        // Shared memory already has its frames, which are freed along with it
+        if let Backing::Shared { memory, .. } = &region.backing {
+            let Some(frame) = memory.frame(page_addr - region.range.start) else {
+                return false;
+            };
+
+            page_table.map_page(page_addr, frame, region.attributes.clone());
+            return true;
+        }
+
+        let Some(frame) = memory::allocator().allocate(PAGE_SIZE) else {
+            return false;
+        };
+
+        // Grow the stack to include the faulting page
+        if let Backing::Stack { .. } = region.backing {
+            region.range.start = region.range.start.min(page_addr);
+        }
+
+        region.fill(page_addr, unsafe {
+            core::slice::from_raw_parts_mut(frame, PAGE_SIZE)
+        });
+
+        page_table.map_page(page_addr, frame as _, region.attributes.clone());
+        self.frames.push(frame as _);
+        true
+    }
+
+    /// The lowest page aligned address in `window` where `size` bytes fit without overlapping any region.
+    pub fn find_free(&self, window: Range<usize>, size: usize) -> Option<usize> {
+        let mut start = window.start;
+
+        loop {
+            let end = start.checked_add(size)?;
+            if end > window.end {
+                return None;
+            }
+
+            // Continue after the furthest region that is in the way
+            let overlapping = self
+                .regions
+                .iter()
+                .map(Region::reserved)
+                .filter(|other| start < other.end && other.start < end)
+                .map(|other| other.end)
+                .max();
+
+            match overlapping {
+                Some(other_end) => start = other_end,
+                None => return Some(start),
+            }
+        }
+    }
+
+    /// Remove the shared memory region starting at the given address, returning it if there was one.
+    pub fn remove_shared(&mut self, page_table: &mut page::Table, start: usize) -> Option<Region> {
+        let index = self.regions.iter().position(|region| {
+            region.range.start == start && matches!(region.backing, Backing::Shared { .. })
+        })?;
+
+        let region = self.regions.swap_remove(index);
+        Self::unmap(page_table, &region);
+        Some(region)
+    }
+
+    /// Remove the shared memory regions that were mapped through a capability derived from the one with the given ID.
+    /// The removed regions are returned, as their memory must outlive any TLB entries that still point to it.
+    pub fn revoke(&mut self, page_table: &mut page::Table, id: u64) -> Vec<Region> {
+        let (revoked, kept): (Vec<_>, _) = self.regions.drain(..).partition(|region| {
+            matches!(&region.backing, Backing::Shared { derived_from, .. } if derived_from.contains(&id))
+        });
+        self.regions = kept;
+
+        for region in &revoked {
+            Self::unmap(page_table, region);
+        }
+
+        revoked
+    }
+
+    fn unmap(page_table: &mut page::Table, region: &Region) {
+        for vaddr in region.range.clone().step_by(PAGE_SIZE) {
+            page_table.unmap(vaddr);
+        }
+    }
+}
+
+impl Drop for RegionList {
+    fn drop(&mut self) {
+        let mut alloc = memory::allocator();
+        for frame in self.frames.drain(..) {
+            alloc.deallocate(frame as _);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: usize = 0x4000_0000;
+
+    #[test_case]
+    fn fills_elf_segments() {
+        let data: Arc<[u8]> = Arc::from(&[0xaa; 0x10][..]);
+        let mut regions = RegionList::default();
+        let mut page_table = page::Table::new();
+
+        // The segment starts halfway into its first page and has more memory than data
+        let backing = Backing::Elf {
+            data,
+            vaddr: BASE + 0x800,
+            offset: 0,
+            size: 0x10,
+        };
+        regions.insert(Region::new(
+            BASE..BASE + (2 * PAGE_SIZE),
+            page::EntryAttributes::UserRead,
+            backing,
+        ));
+
+        assert!(regions.handle_fault(&mut page_table, BASE + 0x808, Access::Read));
+        let page = page_table.copy_from(BASE, PAGE_SIZE).unwrap();
+        assert!(page[..0x800].iter().all(|&b| b == 0));
+        assert!(page[0x800..0x810].iter().all(|&b| b == 0xaa));
+        assert!(page[0x810..].iter().all(|&b| b == 0));
+
+        // Not writable, and already mapped
+        assert!(!regions.handle_fault(&mut page_table, BASE + PAGE_SIZE, Access::Write));
+        assert!(!regions.handle_fault(&mut page_table, BASE, Access::Read));
+    }
+
+    #[test_case]
+    fn grows_stacks_until_limit() {
+        let mut regions = RegionList::default();
+        let mut page_table = page::Table::new();
+        let top = BASE + (4 * PAGE_SIZE);
+
+        regions.insert(Region::new(
+            (top - PAGE_SIZE)..top,
+            page::EntryAttributes::UserReadWrite,
+            Backing::Stack {
+                max_size: 2 * PAGE_SIZE,
+            },
+        ));
+
+        assert!(regions.handle_fault(&mut page_table, top - 8, Access::Write));
+        assert!(regions.handle_fault(&mut page_table, top - PAGE_SIZE - 8, Access::Write));
+        assert_eq!(
+            regions.find(top - 8).unwrap().range.start,
+            top - (2 * PAGE_SIZE)
+        );
+
+        // Past the limit is the guard page, which no other region can take
+        let guard = top - (3 * PAGE_SIZE);
+        assert!(!regions.handle_fault(&mut page_table, guard + 8, Access::Write));
+        assert_eq!(page_table.physical_addr(guard), None);
+        assert_eq!(regions.find_free(guard..top, PAGE_SIZE), None);
+        assert_eq!(
+            regions.find_free((guard - PAGE_SIZE)..top, PAGE_SIZE),
+            Some(guard - PAGE_SIZE)
+        );
+    }
+
+    #[test_case]
+    fn maps_shared_memory_until_revoked() {
+        let memory = Arc::new(SharedMemory::new(2 * PAGE_SIZE).unwrap());
+        let mut regions = RegionList::default();
+        let mut page_table = page::Table::new();
+
+        regions.insert(Region::new(
+            BASE..BASE + PAGE_SIZE,
+            page::EntryAttributes::UserRead,
+            Backing::Anonymous,
+        ));
+
+        // The first free address is right after the existing region
+        let start = regions
+            .find_free(BASE..BASE + (4 * PAGE_SIZE), memory.size())
+            .unwrap();
+        assert_eq!(start, BASE + PAGE_SIZE);
+        assert_eq!(
+            regions.find_free(BASE..BASE + (2 * PAGE_SIZE), memory.size()),
+            None
+        );
+
+        let backing = Backing::Shared {
+            memory: memory.clone(),
+            derived_from: alloc::vec![1],
+        };
+        regions.insert(Region::new(
+            start..start + memory.size(),
+            page::EntryAttributes::UserReadWrite,
+            backing,
+        ));
+
+        // Faults map the frames of the shared memory
+        assert!(regions.handle_fault(&mut page_table, start + PAGE_SIZE, Access::Write));
+        assert_eq!(
+            page_table.physical_addr(start + PAGE_SIZE),
+            memory.frame(PAGE_SIZE)
+        );
+        assert_eq!(Arc::strong_count(&memory), 2);
+
+        // Revoking an unrelated capability keeps the mapping
+        assert!(regions.revoke(&mut page_table, 2).is_empty());
+        assert!(regions.find(start).is_some());
+
+        // The memory is kept alive by the removed region until it is dropped
+        let revoked = regions.revoke(&mut page_table, 1);
+        assert_eq!(revoked.len(), 1);
+        assert!(regions.find(start).is_none());
+        assert_eq!(page_table.physical_addr(start + PAGE_SIZE), None);
+        assert_eq!(Arc::strong_count(&memory), 2);
+        drop(revoked);
+        assert_eq!(Arc::strong_count(&memory), 1);
+
+        // Only shared memory can be unmapped
+        assert!(regions.remove_shared(&mut page_table, BASE).is_none());
+    }
+}
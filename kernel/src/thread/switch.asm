@@ -4,57 +4,102 @@ user_trap_vector:
     # Disable interrupts
     csrw sie, zero
 
-    # Save the users a0 since we'll need to use it
-    csrw sscratch, a0
-
-    li a0, {TRAPFRAME_PTR}
+    # Swap the users a0 with the address of this threads trap frame, which `switch_into` left for us
+    csrrw a0, sscratch, a0
 
     # Save the users registers
-    sd sp, 40(a0)
-    sd ra, 48(a0)
-    sd gp, 56(a0)
-    sd tp, 64(a0)
+    sd sp, 56(a0)
+    sd ra, 64(a0)
+    sd gp, 72(a0)
+    sd tp, 80(a0)
     # a0 is skipped for now
-    sd a1, 80(a0)
-    sd a2, 88(a0)
-    sd a3, 96(a0)
-    sd a4, 104(a0)
-    sd a5, 112(a0)
-    sd a6, 120(a0)
-    sd a7, 128(a0)
-    sd t0, 136(a0)
-    sd t1, 144(a0)
-    sd t2, 152(a0)
-    sd t3, 160(a0)
-    sd t4, 168(a0)
-    sd t5, 176(a0)
-    sd t6, 184(a0)
-    sd s0, 192(a0)
-    sd s1, 200(a0)
-    sd s2, 208(a0)
-    sd s3, 216(a0)
-    sd s4, 224(a0)
-    sd s5, 232(a0)
-    sd s6, 240(a0)
-    sd s7, 248(a0)
-    sd s8, 256(a0)
-    sd s9, 264(a0)
+    sd a1, 96(a0)
+    sd a2, 104(a0)
+    sd a3, 112(a0)
+    sd a4, 120(a0)
+    sd a5, 128(a0)
+    sd a6, 136(a0)
+    sd a7, 144(a0)
+    sd t0, 152(a0)
+    sd t1, 160(a0)
+    sd t2, 168(a0)
+    sd t3, 176(a0)
+    sd t4, 184(a0)
+    sd t5, 192(a0)
+    sd t6, 200(a0)
+    sd s0, 208(a0)
+    sd s1, 216(a0)
+    sd s2, 224(a0)
+    sd s3, 232(a0)
+    sd s4, 240(a0)
+    sd s5, 248(a0)
+    sd s6, 256(a0)
+    sd s7, 264(a0)
+    sd s8, 272(a0)
+    sd s9, 280(a0)
+    sd s10, 288(a0)
+    sd s11, 296(a0)
+
+    # Save the users floating point registers, but only if they were modified since they were last restored
+    csrr t0, sstatus
+    srli t0, t0, 13
+    andi t0, t0, 3
+    li t1, 3 # Dirty
+    bne t0, t1, 1f
+    fsd f0, 304(a0)
+    fsd f1, 312(a0)
+    fsd f2, 320(a0)
+    fsd f3, 328(a0)
+    fsd f4, 336(a0)
+    fsd f5, 344(a0)
+    fsd f6, 352(a0)
+    fsd f7, 360(a0)
+    fsd f8, 368(a0)
+    fsd f9, 376(a0)
+    fsd f10, 384(a0)
+    fsd f11, 392(a0)
+    fsd f12, 400(a0)
+    fsd f13, 408(a0)
+    fsd f14, 416(a0)
+    fsd f15, 424(a0)
+    fsd f16, 432(a0)
+    fsd f17, 440(a0)
+    fsd f18, 448(a0)
+    fsd f19, 456(a0)
+    fsd f20, 464(a0)
+    fsd f21, 472(a0)
+    fsd f22, 480(a0)
+    fsd f23, 488(a0)
+    fsd f24, 496(a0)
+    fsd f25, 504(a0)
+    fsd f26, 512(a0)
+    fsd f27, 520(a0)
+    fsd f28, 528(a0)
+    fsd f29, 536(a0)
+    fsd f30, 544(a0)
+    fsd f31, 552(a0)
+    frcsr t0
+    sd t0, 560(a0)
+1:
 
     # Save the users a0
     csrr t0, sscratch
-    sd t0, 72(a0)
+    sd t0, 88(a0)
 
     # Save the users program counter
     csrr t0, sepc
-    sd t0, 32(a0)
+    sd t0, 48(a0)
 
     # Save the users SATP
     csrr t0, satp
-    sd t0, 24(a0)
+    sd t0, 40(a0)
 
-    # Kernels stack pointer, will never change as there is no need to preserve any context between traps.
+    # Kernels stack pointer, the stack of the hart we are running on as no context is preserved between traps.
     ld sp, 16(a0)
 
+    # The user may have changed the thread pointer, which holds the ID of the hart while in the kernel
+    ld tp, 24(a0)
+
     # Kernel trap handler
     ld t1, 8(a0)
 
@@ -79,8 +124,11 @@ switch_into:
     csrr t0, satp
     sd t0, 0(a0)
 
+    # Where the trapframe is mapped in the users address space, as every thread of a process has its own
+    ld t1, 32(a0)
+
     # Switch to the trapframes page table
-    ld a0, 24(a0)
+    ld a0, 40(a0)
     sfence.vma zero, zero # Flush the TLB
     csrw satp, a0
 
@@ -88,14 +136,17 @@ switch_into:
     la t0, user_trap_vector
     csrw stvec, t0
 
-    li a0, {TRAPFRAME_PTR}
+    mv a0, t1
+
+    # Leave the address of the trapframe for `user_trap_vector`
+    csrw sscratch, a0
 
     # Store the kernels trap handler
     la t0, user_trap_handler
     sd t0, 8(a0)
 
     # Load the users program counter
-    ld t0, 32(a0)
+    ld t0, 48(a0)
     csrw sepc, t0
 
     # Enable interrupts (still globally disabled at this point)
@@ -106,38 +157,86 @@ switch_into:
     li t0, 1 << 8
     csrc sstatus, t0
 
+    # Restore the users floating point registers, this requires the FPU to be enabled
+    li t0, 3 << 13
+    csrc sstatus, t0
+    li t0, 2 << 13 # Clean
+    csrs sstatus, t0
+    fld f0, 304(a0)
+    fld f1, 312(a0)
+    fld f2, 320(a0)
+    fld f3, 328(a0)
+    fld f4, 336(a0)
+    fld f5, 344(a0)
+    fld f6, 352(a0)
+    fld f7, 360(a0)
+    fld f8, 368(a0)
+    fld f9, 376(a0)
+    fld f10, 384(a0)
+    fld f11, 392(a0)
+    fld f12, 400(a0)
+    fld f13, 408(a0)
+    fld f14, 416(a0)
+    fld f15, 424(a0)
+    fld f16, 432(a0)
+    fld f17, 440(a0)
+    fld f18, 448(a0)
+    fld f19, 456(a0)
+    fld f20, 464(a0)
+    fld f21, 472(a0)
+    fld f22, 480(a0)
+    fld f23, 488(a0)
+    fld f24, 496(a0)
+    fld f25, 504(a0)
+    fld f26, 512(a0)
+    fld f27, 520(a0)
+    fld f28, 528(a0)
+    fld f29, 536(a0)
+    fld f30, 544(a0)
+    fld f31, 552(a0)
+    ld t0, 560(a0)
+    fscsr t0
+
+    # Loading marked the registers as dirty, but they match the trap frame so they dont need to be saved on the next trap
+    li t0, 3 << 13
+    csrc sstatus, t0
+    li t0, 2 << 13
+    csrs sstatus, t0
+
     # Load the users registers
-    ld sp, 40(a0)
-    ld ra, 48(a0)
-    ld gp, 56(a0)
-    ld tp, 64(a0)
+    ld sp, 56(a0)
+    ld ra, 64(a0)
+    ld gp, 72(a0)
+    ld tp, 80(a0)
     # a0 is skipped for now
-    ld a1, 80(a0)
-    ld a2, 88(a0)
-    ld a3, 96(a0)
-    ld a4, 104(a0)
-    ld a5, 112(a0)
-    ld a6, 120(a0)
-    ld a7, 128(a0)
-    ld t0, 136(a0)
-    ld t1, 144(a0)
-    ld t2, 152(a0)
-    ld t3, 160(a0)
-    ld t4, 168(a0)
-    ld t5, 176(a0)
-    ld t6, 184(a0)
-    ld s0, 192(a0)
-    ld s1, 200(a0)
-    ld s2, 208(a0)
-    ld s3, 216(a0)
-    ld s4, 224(a0)
-    ld s5, 232(a0)
-    ld s6, 240(a0)
-    ld s7, 248(a0)
-    ld s8, 256(a0)
-    ld s9, 264(a0)
-
-    ld a0, 72(a0) # Finally load the users a0
+    ld a1, 96(a0)
+    ld a2, 104(a0)
+    ld a3, 112(a0)
+    ld a4, 120(a0)
+    ld a5, 128(a0)
+    ld a6, 136(a0)
+    ld a7, 144(a0)
+    ld t0, 152(a0)
+    ld t1, 160(a0)
+    ld t2, 168(a0)
+    ld t3, 176(a0)
+    ld t4, 184(a0)
+    ld t5, 192(a0)
+    ld t6, 200(a0)
+    ld s0, 208(a0)
+    ld s1, 216(a0)
+    ld s2, 224(a0)
+    ld s3, 232(a0)
+    ld s4, 240(a0)
+    ld s5, 248(a0)
+    ld s6, 256(a0)
+    ld s7, 264(a0)
+    ld s8, 272(a0)
+    ld s9, 280(a0)
+    ld s10, 288(a0)
+    ld s11, 296(a0)
+
+    ld a0, 88(a0) # Finally load the users a0
 
     # Begin executing in user mode
     sret
@@ -1,45 +1,114 @@
-use core::{arch::asm, time::Duration};
+use crate::{
+    devicetree,
+    hart::{self, MAX_HARTS},
+};
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
 
-/// The interval at which machine timer interrupts are triggered.
-const INTERVAL: u64 = 10000;
+/// How long a thread may run before it is preempted, unless overridden with `quantum=<milliseconds>` in the boot arguments.
+const DEFAULT_QUANTUM: Duration = Duration::from_millis(10);
 
-pub const BASE_ADDR: usize = 0x0200_0000;
-pub const MTIME: usize = BASE_ADDR + 0xBFF8;
-pub const MTIMECMP: usize = BASE_ADDR + 0x4000;
+/// The duration of a single `mtime` tick.
+const TICK: Duration = Duration::from_nanos(100);
 
-/// Information saved between traps, assuming one HART. Layout must match `vector.s`.
-///     0: `mtime` pointer
-///     1: `mtimecmp` pointer
-///     2: `mtimecmp` interval
-static mut MSCRATCH: [u64; 3] = [0; 3];
+const MSIP_OFFSET: usize = 0x0;
+const MTIME_OFFSET: usize = 0xBFF8;
+const MTIMECMP_OFFSET: usize = 0x4000;
 
-/// Initializes the machine-mode timer. This has to be called before we enter supervisor mode.
+/// The address of the CLINT, as described by the device tree.
+static BASE_ADDR: AtomicUsize = AtomicUsize::new(0);
+
+/// The quantum in `mtime` ticks.
+static QUANTUM: AtomicU64 = AtomicU64::new(to_ticks(DEFAULT_QUANTUM));
+
+fn mtime() -> *mut u64 {
+    (BASE_ADDR.load(Ordering::Relaxed) + MTIME_OFFSET) as _
+}
+
+const fn to_ticks(duration: Duration) -> u64 {
+    (duration.as_nanos() / TICK.as_nanos()) as _
+}
+
+/// The software interrupt pending bit of the given hart, setting it raises a machine software interrupt.
+fn msip(hart: usize) -> *mut u32 {
+    (BASE_ADDR.load(Ordering::Relaxed) + MSIP_OFFSET + (hart * 4)) as _
+}
+
+/// Information saved between traps, one for every HART. Layout must match `vector.s`.
+///     0: `mtimecmp` pointer
+///     1: `msip` pointer
+///     2: saved a1
+///     3: saved a2
+static mut MSCRATCH: [[u64; 4]; MAX_HARTS] = [[0; 4]; MAX_HARTS];
+
+/// Initializes the machine-mode timer of the current hart. This has to be called before we enter supervisor mode.
 #[no_mangle]
 unsafe extern "C" fn machine_timer_init() {
-    let mtime: *mut u64 = MTIME as _;
-    let mtimecmp: *mut u64 = MTIMECMP as _;
+    let hart = hart::id();
+    let base_addr = devicetree::expect_device("riscv,clint0").region.start as usize;
+    BASE_ADDR.store(base_addr, Ordering::Relaxed);
+
+    let mtime = mtime();
+    let mtimecmp: *mut u64 = (base_addr + MTIMECMP_OFFSET + (hart * 8)) as _;
 
-    println!("initializing machine timer...");
+    println!("initializing machine timer of hart {hart}...");
 
-    // Set the machine timer to go off after the specified interval
-    mtimecmp.write_volatile(mtime.read_volatile() + INTERVAL);
+    // Set the machine timer to go off after the first quantum
+    mtimecmp.write_volatile(mtime.read_volatile() + QUANTUM.load(Ordering::Relaxed));
 
     // Save our context
-    MSCRATCH[0] = mtime as _;
-    MSCRATCH[1] = mtimecmp as _;
-    MSCRATCH[2] = INTERVAL;
-    asm!("csrw mscratch, {}", in(reg) MSCRATCH.as_mut_ptr() as usize);
+    let scratch = &mut MSCRATCH[hart];
+    scratch[0] = mtimecmp as _;
+    scratch[1] = msip(hart) as _;
+    asm!("csrw mscratch, {}", in(reg) scratch.as_mut_ptr() as usize);
 
     // Enable machine interrupts
     asm!("csrs mstatus, {}", in(reg) 1 << 3);
 
-    // Enable the machine timer interrupts
-    asm!("csrs mie, {}", in(reg) 1 << 7);
+    // Enable the machine timer and software interrupts
+    asm!("csrs mie, {}", in(reg) 1 << 7 | 1 << 3);
+
+    println!("machine timer of hart {hart} initialized");
+}
+
+/// Send an inter-processor interrupt to the given hart, which arrives as a supervisor software interrupt
+/// and causes the hart to reschedule.
+pub fn send_ipi(hart: usize) {
+    unsafe { msip(hart).write_volatile(1) };
+}
+
+/// Apply the quantum from the boot arguments, if there is one.
+pub fn init() {
+    let Some(quantum) = devicetree::boot_argument("quantum") else {
+        return;
+    };
+
+    match quantum.parse() {
+        Ok(millis) if millis > 0 => {
+            QUANTUM.store(to_ticks(Duration::from_millis(millis)), Ordering::Relaxed);
+            println!("using a scheduling quantum of {millis}ms");
+        }
+
+        _ => println!("ignoring invalid scheduling quantum {quantum:?}"),
+    }
+}
+
+/// Have a supervisor timer interrupt raised once `mtime` reaches the given value, this also clears a pending one.
+/// Only machine mode can write `mtimecmp` and clear the interrupt, so this is handled by `machine_trap_vector`.
+fn set_timer(mtimecmp: u64) {
+    unsafe { asm!("ecall", in("a0") mtimecmp) };
+}
 
-    println!("machine timer initialized");
+/// Start a new quantum for the thread that is about to run, after which it will be preempted.
+pub fn start_quantum() {
+    let now = unsafe { mtime().read_volatile() };
+    set_timer(now + QUANTUM.load(Ordering::Relaxed));
 }
 
 pub fn time_since_bootup() -> Duration {
-    let mtime = unsafe { (MTIME as *const u64).read_volatile() };
-    Duration::from_nanos(mtime * 100)
+    let mtime = unsafe { mtime().read_volatile() };
+    Duration::from_nanos(mtime * TICK.as_nanos() as u64)
 }
@@ -1,7 +1,7 @@
 pub mod clint;
 pub mod plic;
 
-use crate::memory::page;
+use crate::{hart, memory::page, process, thread::region::Access};
 use core::{
     arch::{asm, global_asm},
     fmt::Debug,
@@ -12,7 +12,7 @@ extern "C" {
     fn supervisor_trap_vector();
 }
 
-global_asm!(include_str!("./vector.asm"));
+global_asm!(include_str!("./vector.asm"), STACK_SIZE = const crate::hart::STACK_SIZE);
 
 pub unsafe fn attach_supervisor_trap_vector() {
     // Set the supervisor trap vector defined in `vector.s`, which will execute the Rust handler below
@@ -29,6 +29,16 @@ pub fn enable_interrupts() {
     }
 }
 
+/// Wait until an interrupt arrives and handle it, used when there is nothing to run.
+pub fn wait_for_interrupt() {
+    unsafe {
+        asm!("csrs sie, {}", in(reg) 1 << 9 | 1 << 5 | 1 << 1);
+        asm!("csrs sstatus, {}", in(reg) 1 << 1);
+        asm!("wfi");
+        asm!("csrc sstatus, {}", in(reg) 1 << 1);
+    }
+}
+
 #[allow(clippy::enum_variant_names)] // Just matching the spec
 #[derive(Debug, PartialEq, Eq)]
 enum Interrupt {
@@ -58,7 +68,8 @@ impl Interrupt {
                 unsafe { asm!("csrc sip, 2") }
             }
 
-            _ => panic!("unhandled interrupt: {self:?}"),
+            // The running thread has used up its quantum, the scheduler will rotate to the next one
+            Self::SupervisorTimer => clint::start_quantum(),
         }
     }
 }
@@ -101,13 +112,37 @@ impl From<usize> for Exception {
     }
 }
 
+/// The faulting address or instruction of the last exception.
+fn stval() -> usize {
+    unsafe {
+        let value: usize;
+        asm!("csrr {}, stval", lateout(reg) value);
+        value
+    }
+}
+
+/// The address of the instruction that raised the last exception.
+fn sepc() -> usize {
+    unsafe {
+        let value: usize;
+        asm!("csrr {}, sepc", lateout(reg) value);
+        value
+    }
+}
+
 impl Exception {
+    /// The kind of access that caused a page fault, if this is one.
+    const fn page_fault_access(&self) -> Option<Access> {
+        match self {
+            Self::InstructionPageFault => Some(Access::Execute),
+            Self::LoadPageFault => Some(Access::Read),
+            Self::StoreAmoPageFault => Some(Access::Write),
+            _ => None,
+        }
+    }
+
     fn handle(&self) {
-        let stval = unsafe {
-            let value: usize;
-            asm!("csrr {}, stval", lateout(reg) value);
-            value
-        };
+        let stval = stval();
 
         let sstatus = unsafe {
             let value: usize;
@@ -115,11 +150,7 @@ impl Exception {
             value
         };
 
-        let sepc = unsafe {
-            let value: usize;
-            asm!("csrr {}, sepc", lateout(reg) value);
-            value
-        };
+        let sepc = sepc();
 
         if let Some(paddr) = page::root_table().physical_addr(sepc) {
             panic!(
@@ -167,14 +198,38 @@ impl From<usize> for Trap {
     }
 }
 
+/// The trap handler for User mode, called by `user_trap_vector` after the users context has been saved
+/// into its trap frame. Execution never continues here, instead the scheduler picks the next thread to run.
 #[no_mangle]
-extern "C" fn user_trap_handler(cause: usize) {
-    let trap = Trap::from(cause);
-    if let Trap::Interrupt(intr) = trap {
-        intr.handle();
+extern "C" fn user_trap_handler(cause: usize) -> ! {
+    // Other harts may wait for this to know that we no longer use stale TLB entries
+    hart::leave_user();
+
+    // Traps raised while we are in the kernel should not go through the trampoline
+    unsafe { attach_supervisor_trap_vector() };
+
+    // Another hart killed our process while this thread was running, so it must not run any further
+    if process::scheduler::current_is_killed() {
+        process::scheduler::schedule();
+    }
+
+    match Trap::from(cause) {
+        Trap::Exception(Exception::UserEnvironmentCall) => process::syscall::handle(),
+        // Faults of a user process should never bring down the kernel, so kill the process instead
+        Trap::Exception(excp) => {
+            let stval = stval();
+            let resolved = excp
+                .page_fault_access()
+                .is_some_and(|access| process::fault::handle_page_fault(stval, access));
+
+            if !resolved {
+                process::fault::kill_current(excp, Trap::code(cause), stval, sepc());
+            }
+        }
+        trap => trap.handle(),
     }
 
-    crate::thread::handle_trap(cause as _);
+    process::scheduler::schedule();
 }
 
 /// The trap handler for Supervisor mode. This will be called by the respective
@@ -196,7 +251,7 @@ extern "C" fn supervisor_trap_handler(cause: usize) {
 }
 
 #[no_mangle]
-extern "C" fn machine_trap_handler() {
+extern "C" fn machine_trap_handler() -> ! {
     let mstatus = unsafe {
         let value: usize;
         asm!("csrr {}, mstatus", lateout(reg) value);
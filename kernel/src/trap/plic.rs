@@ -1,13 +1,31 @@
-use crate::spinlock::SpinLock;
+use crate::{devicetree, hart, process, spinlock::SpinLock};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-pub const BASE_ADDR: usize = 0x0c00_0000;
 const MAX_HANDLERS: usize = 1024;
 
+/// The address of the PLIC, as described by the device tree.
+static BASE_ADDR: AtomicUsize = AtomicUsize::new(0);
+
 type InterruptHandler = Option<fn()>;
 type UserInterruptHandler = Option<(usize, usize, u32)>;
 pub static INTERRUPT_HANDLERS: SpinLock<[(InterruptHandler, UserInterruptHandler); MAX_HANDLERS]> =
     SpinLock::new([(None, None); MAX_HANDLERS]);
 
+pub fn init() {
+    let device = devicetree::expect_device("riscv,plic0");
+    BASE_ADDR.store(device.region.start as _, Ordering::Relaxed);
+    init_hart();
+}
+
+/// Let the current hart receive every enabled interrupt, `init` must have been called before.
+pub fn init_hart() {
+    Context::current().set_threshold(0);
+}
+
+fn base_addr() -> usize {
+    BASE_ADDR.load(Ordering::Relaxed)
+}
+
 pub fn add_user(device_id: u16, pid: usize, handler_ptr: usize) {
     set_priority(device_id, 1);
     enable_device(device_id);
@@ -18,14 +36,28 @@ pub fn add_user(device_id: u16, pid: usize, handler_ptr: usize) {
     handlers[device_id as usize].1 = Some((pid, handler_ptr, device_id as _));
 }
 
+/// Whether the given process has registered an interrupt handler, which makes it a device driver.
+pub fn has_user(pid: usize) -> bool {
+    INTERRUPT_HANDLERS
+        .lock()
+        .iter()
+        .any(|(_, user)| user.is_some_and(|(p, _, _)| p == pid))
+}
+
+/// Remove every interrupt handler registered by the given process.
 pub fn try_remove_user(pid: usize) -> Option<()> {
     let handlers = &mut INTERRUPT_HANDLERS.lock();
-    let device_id = handlers
-        .iter()
-        .position(|(_, user)| user.map(|(p, _, _)| p == pid).unwrap_or(false))?;
-    assert!(handlers[device_id].0.is_none());
-    handlers[device_id].1 = None;
-    Some(())
+    let mut removed = None;
+
+    for (kernel, user) in handlers.iter_mut() {
+        if user.is_some_and(|(p, _, _)| p == pid) {
+            assert!(kernel.is_none());
+            *user = None;
+            removed = Some(());
+        }
+    }
+
+    removed
 }
 
 pub fn handle_interrupt() {
@@ -46,33 +78,53 @@ pub fn handle_interrupt() {
     }
 
     if let Some((pid, handler_ptr, irq_id)) = unlocked_handler {
-        panic!()
-        // process::interrupt::handle(irq_id, handler_ptr, pid);
+        process::interrupt::handle(irq_id, handler_ptr, pid);
     }
 }
 
-#[allow(clippy::enum_variant_names)] // Just matching the spec
-#[repr(usize)]
-pub enum Registers {
-    SupervisorEnable = 0x2080,
-    SupervisorPriority = 0x201000,
-    SupervisorClaim = 0x201004,
-}
+/// The registers through which a hart interacts with the PLIC, every hart has its own context for supervisor mode.
+#[derive(Debug, Clone, Copy)]
+struct Context(usize);
 
-impl Registers {
-    unsafe fn into_ptr(self) -> *mut u32 {
-        (BASE_ADDR + (self as usize)) as _
+impl Context {
+    const ENABLE_OFFSET: usize = 0x2000;
+    const ENABLE_STRIDE: usize = 0x80;
+    const THRESHOLD_OFFSET: usize = 0x20_0000;
+    const CLAIM_OFFSET: usize = 0x20_0004;
+    const CONTEXT_STRIDE: usize = 0x1000;
+
+    /// The supervisor context of the given hart, which follows its machine context on QEMU's virt machine.
+    const fn of(hart: usize) -> Self {
+        Self((hart * 2) + 1)
     }
 
-    fn read(self) -> u32 {
-        unsafe { self.into_ptr().read_volatile() }
+    fn current() -> Self {
+        Self::of(hart::id())
     }
 
-    fn write<T>(self, value: T)
-    where
-        T: Into<u32>,
-    {
-        unsafe { self.into_ptr().write_volatile(value.into()) }
+    fn register(self, offset: usize, stride: usize) -> *mut u32 {
+        (base_addr() + offset + (self.0 * stride)) as _
+    }
+
+    /// Set the threshold an interrupts priority has to exceed for it to be delivered
+    fn set_threshold(self, threshold: u8) {
+        let ptr = self.register(Self::THRESHOLD_OFFSET, Self::CONTEXT_STRIDE);
+        unsafe { ptr.write_volatile(threshold.into()) }
+    }
+
+    /// Set the enable bit for the given interrupt ID
+    fn enable(self, interrupt_id: u16) {
+        let word = interrupt_id as usize / 32;
+        let ptr = unsafe {
+            self.register(Self::ENABLE_OFFSET, Self::ENABLE_STRIDE)
+                .add(word)
+        };
+
+        unsafe { ptr.write_volatile(ptr.read_volatile() | (1 << (interrupt_id % 32))) }
+    }
+
+    fn claim(self) -> *mut u32 {
+        self.register(Self::CLAIM_OFFSET, Self::CONTEXT_STRIDE)
     }
 }
 
@@ -81,27 +133,22 @@ fn set_priority(interrupt_id: u16, priority: u8) {
     assert!(priority <= 7);
     assert!(interrupt_id < MAX_HANDLERS as _);
     unsafe {
-        (BASE_ADDR as *mut u32)
+        (base_addr() as *mut u32)
             .add(interrupt_id.into())
             .write_volatile(priority.into());
     }
 }
 
-/// Set the threshold for context 1
-pub fn set_global_threshold(threshold: u8) {
-    Registers::SupervisorPriority.write(threshold);
-}
-
-/// Set the enable bit for the given interrupt ID on context 1
+/// Enable the given interrupt ID on every hart, whichever claims it first will handle it
 fn enable_device(interrupt_id: u16) {
-    let prev_enable = Registers::SupervisorEnable.read();
-    let new_enable: u32 = prev_enable | (1 << interrupt_id);
-    Registers::SupervisorEnable.write(new_enable);
+    for hart in 0..hart::count() {
+        Context::of(hart).enable(interrupt_id);
+    }
 }
 
-/// Claim the next interrupt for context 1
+/// Claim the next interrupt for the current hart
 pub fn claim() -> Option<u32> {
-    let id = Registers::SupervisorClaim.read();
+    let id = unsafe { Context::current().claim().read_volatile() };
     if id != 0 {
         Some(id)
     } else {
@@ -109,7 +156,7 @@ pub fn claim() -> Option<u32> {
     }
 }
 
-/// Complete an interrupt for context 1. ID should come from `claim()`
+/// Complete an interrupt. ID should come from `claim()`, though not necessarily from the current hart
 pub fn complete(id: u32) {
-    Registers::SupervisorClaim.write(id)
+    unsafe { Context::current().claim().write_volatile(id) }
 }
@@ -83,40 +83,75 @@ supervisor_trap_vector:
 .section .text.vectors
 .global machine_trap_vector
 machine_trap_vector:
-    # Save the registers we will use
-    addi sp, sp, -32
-    sd a0, 0(sp)
-    sd a1, 8(sp)
-    sd a2, 16(sp)
-    sd a3, 24(sp)
-
-    # Load the context from `clint.rs`, matching the layout defined there:
-    #   0: `mtime` pointer
-    #   1: `mtimecmp` pointer
-    #   2: `mtimecmp` interval
+    # Swap in the context of this hart from `clint.rs`, matching the layout defined there:
+    #   0: `mtimecmp` pointer
+    #   1: `msip` pointer
+    #   2: saved a1
+    #   3: saved a2
+    # The stack pointer cannot be used, as it holds a virtual address whenever we trap from a user process.
     csrrw a0, mscratch, a0
+    sd a1, 16(a0)
+    sd a2, 24(a0)
 
-    # Schedule the next interrupt
-    ld a1, 8(a0) # Pointer to mtimecmp
-    ld a3, 0(a1) # Dereference the mtimecmp pointer
-    ld a2, 16(a0) # Load the interval
+    csrr a1, mcause
+    li a2, 1 << 63 | 7
+    beq a1, a2, machine_timer_interrupt
+    li a2, 1 << 63 | 3
+    beq a1, a2, machine_software_interrupt
+    li a2, 9
+    beq a1, a2, supervisor_environment_call
 
-    # Add the new interval to mtimecmp
-    add a3, a3, a2
-    sd a3, 0(a1)
+    # Anything else is a bug. The stack pointer may hold a user virtual address, so switch to the boot stack of this
+    # hart before reporting it, the handler never returns.
+    la sp, _stack_end
+    li a1, {STACK_SIZE}
+    csrr a2, mhartid
+    mul a1, a1, a2
+    sub sp, sp, a1
+    call machine_trap_handler
+machine_trap_halt:
+    wfi
+    j machine_trap_halt
 
-    # Save the context
-    csrrw a0, mscratch, a0
+machine_timer_interrupt:
+    # Forward the interrupt to the kernel as a supervisor timer interrupt
+    li a1, 1 << 5
+    csrs mip, a1
+
+    # Disable the machine timer interrupt until the kernel sets a new timer, as it stays pending until then
+    li a1, 1 << 7
+    csrc mie, a1
+    j machine_trap_return
+
+machine_software_interrupt:
+    # Another hart sent us an IPI, acknowledge it and forward it to the kernel as a supervisor software interrupt
+    ld a1, 8(a0)
+    sw zero, 0(a1)
+    csrsi mip, 1 << 1
+    j machine_trap_return
 
-    # Raise a supervisor software interrupt
-    csrsi sip, 2
+supervisor_environment_call:
+    # The kernel is setting the next timer, with the new value of `mtimecmp` in its a0
+    ld a1, 0(a0)
+    csrr a2, mscratch
+    sd a2, 0(a1)
 
-    # Restore the used registers
-    ld a0, 0(sp)
-    ld a1, 8(sp)
-    ld a2, 16(sp)
-    ld a3, 24(sp)
-    addi sp, sp, 32
+    # Clear the pending supervisor timer interrupt and listen for the next machine timer interrupt
+    li a1, 1 << 5
+    csrc mip, a1
+    li a1, 1 << 7
+    csrs mie, a1
+
+    # Continue after the `ecall` instruction
+    csrr a1, mepc
+    addi a1, a1, 4
+    csrw mepc, a1
+
+machine_trap_return:
+    # Restore the used registers and the context
+    ld a1, 16(a0)
+    ld a2, 24(a0)
+    csrrw a0, mscratch, a0
 
     # Hand control back to the kernel
     mret
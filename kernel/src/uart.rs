@@ -1,10 +1,23 @@
-use crate::spinlock::SpinLock;
+use crate::{devicetree, spinlock::SpinLock};
 use core::fmt::Write;
 
 // Re-export the uart module from the crate shared with userland.
-pub use uart::{NS16550a, BASE_ADDR};
+pub use uart::NS16550a;
 
-pub static UART: SpinLock<uart::NS16550a> = SpinLock::new(NS16550a::DEFAULT);
+/// Where the UART lives on QEMU's virt machine, only used for output until the device tree has been parsed.
+const EARLY_BASE_ADDR: u64 = 0x1000_0000;
+
+pub static UART: SpinLock<uart::NS16550a> =
+    SpinLock::new(NS16550a::with_base_address(EARLY_BASE_ADDR));
+
+/// Switch over to the UART described by the device tree and initialize it.
+pub fn init() {
+    let device = devicetree::expect_device("ns16550a");
+    UART.lock_with(|uart| {
+        *uart = NS16550a::with_base_address(device.region.start);
+        uart.init();
+    });
+}
 
 /// Printing function that uses the UART to print to standard output.
 pub fn print(with_newline: bool, args: ::core::fmt::Arguments) {
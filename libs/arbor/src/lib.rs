@@ -0,0 +1,397 @@
+#![feature(custom_test_frameworks)]
+#![test_runner(test_runner)]
+#![no_std]
+
+//! Resources:
+//! https://devicetree-specification.readthedocs.io/en/stable/flattened-format.html
+//! https://devicetree-specification.readthedocs.io/en/stable/devicetree-basics.html
+//! $ dtc -I dtb -O dts <file>
+
+pub mod node;
+pub mod property;
+
+use node::{Node, Nodes};
+use property::Region;
+
+#[cfg(test)]
+fn test_runner(tests: &[&dyn Fn()]) {
+    tests.iter().for_each(|test| test());
+}
+
+const MAGIC: u32 = 0xd00d_feed;
+
+/// The oldest version of the format whose layout we understand.
+const SUPPORTED_VERSION: u32 = 16;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Error {
+    InvalidMagic(u32),
+    /// The blob is only compatible with versions of the format from the given one onwards.
+    UnsupportedVersion(u32),
+    /// An offset or size points outside of the blob.
+    OutOfBounds,
+}
+
+/// Read a big endian `u32` at the given offset.
+pub(crate) fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// Read a big endian `u64` at the given offset.
+pub(crate) fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    let bytes = bytes.get(offset..offset + 8)?;
+    Some(u64::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// The header at the start of every flattened device tree.
+#[derive(Debug, Clone, Copy)]
+pub struct Header {
+    /// `totalsize`
+    pub total_size: u32,
+    /// `off_dt_struct`
+    pub structure_offset: u32,
+    /// `off_dt_strings`
+    pub strings_offset: u32,
+    /// `off_mem_rsvmap`
+    pub reserved_memory_offset: u32,
+    /// `version`
+    pub version: u32,
+    /// `last_comp_version`
+    pub last_compatible_version: u32,
+    /// `boot_cpuid_phys`
+    pub boot_cpu_id: u32,
+    /// `size_dt_strings`
+    pub strings_size: u32,
+    /// `size_dt_struct`
+    pub structure_size: u32,
+}
+
+impl Header {
+    const SIZE: usize = 10 * 4;
+
+    fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        let field = |index: usize| read_u32(bytes, index * 4).ok_or(Error::OutOfBounds);
+
+        let magic = field(0)?;
+        if magic != MAGIC {
+            return Err(Error::InvalidMagic(magic));
+        }
+
+        let header = Self {
+            total_size: field(1)?,
+            structure_offset: field(2)?,
+            strings_offset: field(3)?,
+            reserved_memory_offset: field(4)?,
+            version: field(5)?,
+            last_compatible_version: field(6)?,
+            boot_cpu_id: field(7)?,
+            strings_size: field(8)?,
+            structure_size: field(9)?,
+        };
+
+        if header.last_compatible_version > SUPPORTED_VERSION {
+            return Err(Error::UnsupportedVersion(header.last_compatible_version));
+        }
+
+        Ok(header)
+    }
+}
+
+/// A parsed flattened device tree, which borrows the original blob.
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceTree<'a> {
+    pub header: Header,
+    bytes: &'a [u8],
+    structure: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl<'a> DeviceTree<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self, Error> {
+        let header = Header::parse(bytes)?;
+        let bytes = bytes
+            .get(..header.total_size as usize)
+            .ok_or(Error::OutOfBounds)?;
+
+        let block = |offset: u32, size: u32| {
+            bytes
+                .get(offset as usize..(offset as usize + size as usize))
+                .ok_or(Error::OutOfBounds)
+        };
+
+        Ok(Self {
+            header,
+            bytes,
+            structure: block(header.structure_offset, header.structure_size)?,
+            strings: block(header.strings_offset, header.strings_size)?,
+        })
+    }
+
+    /// Parse the device tree at the given address, the size is read from its header.
+    ///
+    /// # Safety
+    /// The caller must ensure that the pointer is valid for reads of at least the size of the header,
+    /// and of the whole device tree if the header is valid.
+    pub unsafe fn from_ptr(ptr: *const u8) -> Result<Self, Error> {
+        let header = Header::parse(core::slice::from_raw_parts(ptr, Header::SIZE))?;
+        Self::new(core::slice::from_raw_parts(ptr, header.total_size as _))
+    }
+
+    /// The size of the whole blob in bytes.
+    pub const fn size(&self) -> usize {
+        self.header.total_size as _
+    }
+
+    /// All nodes of the tree in depth-first order, starting with the root node.
+    pub fn nodes(&self) -> Nodes<'a> {
+        Nodes::new(*self)
+    }
+
+    pub fn root(&self) -> Option<Node<'a>> {
+        self.nodes().next()
+    }
+
+    /// All nodes with the given string in their `compatible` property.
+    pub fn find_compatible<'b>(&self, compatible: &'b str) -> impl Iterator<Item = Node<'a>> + 'b
+    where
+        'a: 'b,
+    {
+        self.nodes()
+            .filter(move |node| node.is_compatible(compatible))
+    }
+
+    /// The physical memory of the machine, described by the nodes with a `device_type` of `memory`.
+    pub fn memory(&self) -> impl Iterator<Item = Region> + 'a {
+        self.nodes()
+            .filter(|node| {
+                node.property("device_type")
+                    .and_then(|prop| prop.as_str())
+                    .is_some_and(|device_type| device_type == "memory")
+            })
+            .flat_map(|node| node.reg())
+    }
+
+    /// Regions of physical memory that must not be used, as described by the memory reservation block.
+    /// Note that this does not include the device tree itself.
+    pub fn reserved_memory(&self) -> impl Iterator<Item = Region> + 'a {
+        let bytes = self.bytes;
+        let start = self.header.reserved_memory_offset as usize;
+
+        (0..).map_while(move |index| {
+            let offset = start + (index * 16);
+            let region = Region {
+                start: read_u64(bytes, offset)?,
+                size: read_u64(bytes, offset + 8)?,
+            };
+
+            // The block is terminated by an entry with both fields set to zero
+            (region.start != 0 || region.size != 0).then_some(region)
+        })
+    }
+
+    /// Look up a null-terminated string in the strings block.
+    pub(crate) fn string(&self, offset: usize) -> Option<&'a str> {
+        let bytes = self.strings.get(offset..)?;
+        let len = bytes.iter().position(|&b| b == 0)?;
+        core::str::from_utf8(&bytes[..len]).ok()
+    }
+
+    pub(crate) const fn structure(&self) -> &'a [u8] {
+        self.structure
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a blob with the layout `dtc` produces: the header, the memory reservation block,
+    /// the structure block and then the strings block.
+    struct Blob {
+        bytes: [u8; 512],
+        len: usize,
+    }
+
+    impl Blob {
+        fn u32(&mut self, value: u32) {
+            self.bytes[self.len..self.len + 4].copy_from_slice(&value.to_be_bytes());
+            self.len += 4;
+        }
+
+        fn u64(&mut self, value: u64) {
+            self.bytes[self.len..self.len + 8].copy_from_slice(&value.to_be_bytes());
+            self.len += 8;
+        }
+
+        /// A null-terminated string, padded to the next token.
+        fn str(&mut self, value: &str) {
+            self.bytes[self.len..self.len + value.len()].copy_from_slice(value.as_bytes());
+            self.len = (self.len + value.len() + 1 + 3) & !3;
+        }
+
+        fn property(&mut self, name_offset: u32, value: &[u32]) {
+            self.u32(node::PROPERTY);
+            self.u32(value.len() as u32 * 4);
+            self.u32(name_offset);
+            value.iter().for_each(|&cell| self.u32(cell));
+        }
+
+        fn string_property(&mut self, name_offset: u32, value: &str) {
+            self.u32(node::PROPERTY);
+            self.u32(value.len() as u32 + 1);
+            self.u32(name_offset);
+            self.str(value);
+        }
+    }
+
+    const STRINGS: &str = "#address-cells\0#size-cells\0device_type\0reg\0compatible\0interrupts\0";
+    const ADDRESS_CELLS: u32 = 0;
+    const SIZE_CELLS: u32 = 15;
+    const DEVICE_TYPE: u32 = 27;
+    const REG: u32 = 39;
+    const COMPATIBLE: u32 = 43;
+    const INTERRUPTS: u32 = 54;
+
+    /// The blob of this tree, with `0x81000000..0x81001000` reserved:
+    /// ```dts
+    /// / {
+    ///     #address-cells = <2>;
+    ///     #size-cells = <2>;
+    ///     memory@80000000 {
+    ///         device_type = "memory";
+    ///         reg = <0x0 0x80000000 0x0 0x8000000>;
+    ///     };
+    ///     uart@10000000 {
+    ///         compatible = "ns16550a";
+    ///         reg = <0x0 0x10000000 0x0 0x100>;
+    ///         interrupts = <10>;
+    ///     };
+    /// };
+    /// ```
+    fn blob() -> Blob {
+        let mut blob = Blob {
+            bytes: [0; 512],
+            len: Header::SIZE,
+        };
+
+        let reserved_memory_offset = blob.len;
+        blob.u64(0x8100_0000);
+        blob.u64(0x1000);
+        blob.u64(0);
+        blob.u64(0);
+
+        let structure_offset = blob.len;
+        blob.u32(node::BEGIN_NODE);
+        blob.str("");
+        blob.property(ADDRESS_CELLS, &[2]);
+        blob.property(SIZE_CELLS, &[2]);
+
+        blob.u32(node::BEGIN_NODE);
+        blob.str("memory@80000000");
+        blob.string_property(DEVICE_TYPE, "memory");
+        blob.property(REG, &[0, 0x8000_0000, 0, 0x800_0000]);
+        blob.u32(node::END_NODE);
+
+        blob.u32(node::BEGIN_NODE);
+        blob.str("uart@10000000");
+        blob.string_property(COMPATIBLE, "ns16550a");
+        blob.property(REG, &[0, 0x1000_0000, 0, 0x100]);
+        blob.property(INTERRUPTS, &[10]);
+        blob.u32(node::END_NODE);
+
+        blob.u32(node::END_NODE);
+        blob.u32(node::END);
+        let structure_size = blob.len - structure_offset;
+
+        let strings_offset = blob.len;
+        blob.bytes[blob.len..blob.len + STRINGS.len()].copy_from_slice(STRINGS.as_bytes());
+        blob.len += STRINGS.len();
+        let total_size = blob.len;
+
+        blob.len = 0;
+        for field in [
+            MAGIC,
+            total_size as u32,
+            structure_offset as u32,
+            strings_offset as u32,
+            reserved_memory_offset as u32,
+            17,
+            SUPPORTED_VERSION,
+            0,
+            STRINGS.len() as u32,
+            structure_size as u32,
+        ] {
+            blob.u32(field);
+        }
+
+        blob.len = total_size;
+        blob
+    }
+
+    #[test_case]
+    fn parses_nodes_and_properties() {
+        let blob = blob();
+        let tree = DeviceTree::new(&blob.bytes[..blob.len]).unwrap();
+        assert_eq!(tree.size(), blob.len);
+        assert_eq!(tree.header.version, 17);
+
+        assert!(tree.nodes().map(|node| (node.name, node.depth)).eq([
+            ("", 0),
+            ("memory@80000000", 1),
+            ("uart@10000000", 1)
+        ]));
+
+        let root = tree.root().unwrap();
+        assert_eq!(root.property("#address-cells").unwrap().as_u32(), Some(2));
+        assert!(root.property("reg").is_none());
+
+        let uart = tree.find_compatible("ns16550a").next().unwrap();
+        assert_eq!(uart.name, "uart@10000000");
+        assert!(uart.reg().eq([Region {
+            start: 0x1000_0000,
+            size: 0x100
+        }]));
+        assert!(uart.interrupts().eq([10]));
+        assert!(tree.find_compatible("ns16550").next().is_none());
+
+        assert!(tree.memory().eq([Region {
+            start: 0x8000_0000,
+            size: 0x800_0000
+        }]));
+        assert!(tree.reserved_memory().eq([Region {
+            start: 0x8100_0000,
+            size: 0x1000
+        }]));
+    }
+
+    #[test_case]
+    fn rejects_invalid_magic() {
+        let mut blob = blob();
+        blob.bytes[0] = 0;
+
+        let error = DeviceTree::new(&blob.bytes[..blob.len]).unwrap_err();
+        assert_eq!(error, Error::InvalidMagic(MAGIC & 0x00ff_ffff));
+    }
+
+    #[test_case]
+    fn rejects_incompatible_versions() {
+        let mut blob = blob();
+        blob.bytes[20..24].copy_from_slice(&20u32.to_be_bytes());
+        blob.bytes[24..28].copy_from_slice(&18u32.to_be_bytes());
+
+        let error = DeviceTree::new(&blob.bytes[..blob.len]).unwrap_err();
+        assert_eq!(error, Error::UnsupportedVersion(18));
+    }
+
+    #[test_case]
+    fn rejects_truncated_blobs() {
+        let blob = blob();
+
+        // Within the header, and within the strings block
+        for len in [0, Header::SIZE - 1, blob.len - 1] {
+            let error = DeviceTree::new(&blob.bytes[..len]).unwrap_err();
+            assert_eq!(error, Error::OutOfBounds);
+        }
+    }
+}
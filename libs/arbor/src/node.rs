@@ -0,0 +1,205 @@
+use crate::{
+    property::{Property, Region},
+    read_u32, DeviceTree,
+};
+
+pub(crate) const BEGIN_NODE: u32 = 0x1;
+pub(crate) const END_NODE: u32 = 0x2;
+pub(crate) const PROPERTY: u32 = 0x3;
+const NOP: u32 = 0x4;
+pub(crate) const END: u32 = 0x9;
+
+/// The defaults for `#address-cells` and `#size-cells` when a node does not specify them.
+const DEFAULT_CELLS: (u32, u32) = (2, 1);
+
+/// How deep we keep track of `#address-cells` and `#size-cells`, deeper nodes use the defaults.
+const MAX_DEPTH: usize = 16;
+
+/// Round an offset in the structure block up to the next token.
+const fn align_token(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+enum Token<'a> {
+    BeginNode(&'a str),
+    EndNode,
+    Property(Property<'a>),
+    End,
+}
+
+/// Read the token at the given offset, returning it along with the offset of the token after it.
+fn next_token<'a>(tree: &DeviceTree<'a>, mut offset: usize) -> Option<(Token<'a>, usize)> {
+    let structure = tree.structure();
+
+    loop {
+        let token = read_u32(structure, offset)?;
+        offset += 4;
+
+        match token {
+            BEGIN_NODE => {
+                let bytes = structure.get(offset..)?;
+                let len = bytes.iter().position(|&b| b == 0)?;
+                let name = core::str::from_utf8(&bytes[..len]).ok()?;
+                return Some((Token::BeginNode(name), align_token(offset + len + 1)));
+            }
+
+            PROPERTY => {
+                let len = read_u32(structure, offset)? as usize;
+                let name = tree.string(read_u32(structure, offset + 4)? as _)?;
+                let value = structure.get(offset + 8..offset + 8 + len)?;
+                let property = Property { name, value };
+                return Some((Token::Property(property), align_token(offset + 8 + len)));
+            }
+
+            END_NODE => return Some((Token::EndNode, offset)),
+            END => return Some((Token::End, offset)),
+            NOP => continue,
+            _ => return None,
+        }
+    }
+}
+
+/// Read a number spanning the given amount of cells.
+fn read_cells(bytes: &[u8], cells: u32) -> u64 {
+    (0..cells as usize)
+        .map_while(|cell| read_u32(bytes, cell * 4))
+        .fold(0, |acc, cell| (acc << 32) | cell as u64)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Node<'a> {
+    tree: DeviceTree<'a>,
+    /// The name including the unit address, e.g. `serial@10000000`. Empty for the root node.
+    pub name: &'a str,
+    /// The amount of parents of this node.
+    pub depth: usize,
+    /// The offset of the first token after the name.
+    offset: usize,
+    /// The `#address-cells` and `#size-cells` of our parent, which describe the layout of our `reg` property.
+    parent_cells: (u32, u32),
+}
+
+impl<'a> Node<'a> {
+    pub fn properties(&self) -> Properties<'a> {
+        Properties {
+            tree: self.tree,
+            offset: self.offset,
+        }
+    }
+
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|property| property.name == name)
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.property("compatible")
+            .is_some_and(|prop| prop.strings().any(|c| c == compatible))
+    }
+
+    /// The regions of memory described by the `reg` property.
+    pub fn reg(&self) -> impl Iterator<Item = Region> + 'a {
+        let (address_cells, size_cells) = self.parent_cells;
+        let entry_size = (address_cells + size_cells) as usize * 4;
+        let value = self.property("reg").map_or(&[][..], |prop| prop.value);
+
+        value
+            .chunks_exact(entry_size.max(1))
+            .map(move |entry| Region {
+                start: read_cells(entry, address_cells),
+                size: read_cells(&entry[address_cells as usize * 4..], size_cells),
+            })
+    }
+
+    /// The interrupts described by the `interrupts` property, assuming a single cell for every interrupt.
+    pub fn interrupts(&self) -> impl Iterator<Item = u32> + 'a {
+        self.property("interrupts")
+            .into_iter()
+            .flat_map(|prop| prop.cells())
+    }
+}
+
+/// The properties of a single node.
+pub struct Properties<'a> {
+    tree: DeviceTree<'a>,
+    offset: usize,
+}
+
+impl<'a> Iterator for Properties<'a> {
+    type Item = Property<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match next_token(&self.tree, self.offset)? {
+            (Token::Property(property), next) => {
+                self.offset = next;
+                Some(property)
+            }
+
+            // Properties always come before the children of a node
+            _ => None,
+        }
+    }
+}
+
+/// All nodes of a tree, in depth-first order.
+pub struct Nodes<'a> {
+    tree: DeviceTree<'a>,
+    offset: usize,
+    depth: usize,
+    /// The `#address-cells` and `#size-cells` of every node we are currently nested in.
+    cells: [(u32, u32); MAX_DEPTH],
+}
+
+impl<'a> Nodes<'a> {
+    pub(crate) fn new(tree: DeviceTree<'a>) -> Self {
+        Self {
+            tree,
+            offset: 0,
+            depth: 0,
+            cells: [DEFAULT_CELLS; MAX_DEPTH],
+        }
+    }
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (token, next) = next_token(&self.tree, self.offset)?;
+            self.offset = next;
+
+            match token {
+                Token::BeginNode(name) => {
+                    let parent_cells = self
+                        .depth
+                        .checked_sub(1)
+                        .and_then(|parent| self.cells.get(parent).copied())
+                        .unwrap_or(DEFAULT_CELLS);
+
+                    let node = Node {
+                        tree: self.tree,
+                        name,
+                        depth: self.depth,
+                        offset: next,
+                        parent_cells,
+                    };
+
+                    if let Some(cells) = self.cells.get_mut(self.depth) {
+                        let cells_of = |name| node.property(name).and_then(|prop| prop.as_u32());
+                        *cells = (
+                            cells_of("#address-cells").unwrap_or(DEFAULT_CELLS.0),
+                            cells_of("#size-cells").unwrap_or(DEFAULT_CELLS.1),
+                        );
+                    }
+
+                    self.depth += 1;
+                    return Some(node);
+                }
+
+                Token::EndNode => self.depth = self.depth.saturating_sub(1),
+                Token::Property(_) => continue,
+                Token::End => return None,
+            }
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use crate::{read_u32, read_u64};
+
+/// A single property of a node, the meaning of its value depends on the name.
+#[derive(Debug, Clone, Copy)]
+pub struct Property<'a> {
+    pub name: &'a str,
+    pub value: &'a [u8],
+}
+
+impl<'a> Property<'a> {
+    /// The value as a single `<u32>` cell.
+    pub fn as_u32(&self) -> Option<u32> {
+        (self.value.len() == 4).then(|| read_u32(self.value, 0))?
+    }
+
+    /// The value as a single `<u64>`, spanning two cells.
+    pub fn as_u64(&self) -> Option<u64> {
+        (self.value.len() == 8).then(|| read_u64(self.value, 0))?
+    }
+
+    /// The value as a single string, or the first one if it is a string list.
+    pub fn as_str(&self) -> Option<&'a str> {
+        self.strings().next()
+    }
+
+    /// The value as a list of null-terminated strings.
+    pub fn strings(&self) -> impl Iterator<Item = &'a str> + 'a {
+        self.value
+            .split(|&b| b == 0)
+            .filter(|string| !string.is_empty())
+            .filter_map(|string| core::str::from_utf8(string).ok())
+    }
+
+    /// The value as a list of `<u32>` cells.
+    pub fn cells(&self) -> impl Iterator<Item = u32> + 'a {
+        let value = self.value;
+        (0..value.len() / 4).map_while(move |cell| read_u32(value, cell * 4))
+    }
+}
+
+/// A range of physical addresses, for example from a `reg` property.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub start: u64,
+    pub size: u64,
+}
+
+impl Region {
+    pub const fn end(&self) -> u64 {
+        self.start + self.size
+    }
+
+    pub const fn contains(&self, addr: u64) -> bool {
+        addr >= self.start && addr < self.end()
+    }
+}
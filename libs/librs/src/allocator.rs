@@ -6,11 +6,13 @@ struct Allocator;
 // TODO: handle allocations in userspace
 unsafe impl GlobalAlloc for Allocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        syscall::allocate(layout.size())
+        syscall::allocate(layout.size()).unwrap_or_default()
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
-        syscall::deallocate(ptr);
+        // A failed free means the heap is corrupted, which is only worth the risk of panicking in here in debug builds
+        let result = syscall::deallocate(ptr);
+        debug_assert!(result.is_ok(), "failed to deallocate {ptr:?}: {result:?}");
     }
 }
 
@@ -1,7 +1,9 @@
-use crate::syscall;
+use crate::syscall::{self, Event, Events, Rights, SyscallError};
 use core::{
     mem::size_of,
     ops::{Index, IndexMut},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
 };
 
 // TODO: merge with kernel
@@ -104,11 +106,85 @@ impl From<&str> for MessageData {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+/// The handle to the server of the current process, if it registered as one.
+pub(crate) static OWN_HANDLE: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// A handle to the server of the current process with all rights, if it registered as one.
+pub fn own_handle() -> Option<u64> {
+    let handle = OWN_HANDLE.load(Ordering::Relaxed);
+    (handle != u64::MAX).then_some(handle)
+}
+
+/// A handle to a server with a name, which is only connected to once it is first used.
+#[derive(Debug)]
+pub struct Connection {
+    name: &'static str,
+    handle: AtomicU64,
+}
+
+impl Connection {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            handle: AtomicU64::new(u64::MAX),
+        }
+    }
+
+    /// The handle to the server, or `None` if it did not register yet.
+    pub fn handle(&self) -> Option<u64> {
+        self.connect(syscall::connect)
+    }
+
+    /// The handle to the server, waiting until it registers.
+    pub fn wait(&self) -> u64 {
+        self.connect(syscall::connect_blocking)
+            .expect("interrupt handlers cannot wait for servers")
+    }
+
+    fn connect(&self, connect: fn(&str) -> Result<u64, SyscallError>) -> Option<u64> {
+        let handle = self.handle.load(Ordering::Relaxed);
+        if handle != u64::MAX {
+            return Some(handle);
+        }
+
+        let handle = connect(self.name).ok()?;
+        match self
+            .handle
+            .compare_exchange(u64::MAX, handle, Ordering::Relaxed, Ordering::Relaxed)
+        {
+            Ok(_) => Some(handle),
+            // Another thread connected in the meantime
+            Err(existing) => {
+                let _ = syscall::close_handle(handle);
+                Some(existing)
+            }
+        }
+    }
+}
+
+/// The right to reply to a message that was sent with [`Message::call`], which can only be used once.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ReplyRight(u64);
+
+impl ReplyRight {
+    /// Let the caller continue with the given reply, optionally granting it a handle with at most the given rights.
+    /// Returns `false` if the caller is gone, or the handle could not be granted.
+    pub fn reply(self, identifier: u64, data: MessageData, grant: Option<(u64, Rights)>) -> bool {
+        syscall::reply(self.0, identifier, data, grant).is_ok()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
 pub struct Message {
+    /// The handle of the receiving server for messages that are sent, received messages carry the ID of the sending
+    /// server instead, which is 0 if the sender is not a server.
     pub server_id: u64,
     pub identifier: u64,
     pub data: MessageData,
+    /// A handle granted along with the message and its rights, which are at most the given ones when sending.
+    pub handle: Option<(u64, Rights)>,
+    /// Set for received messages whose sender waits for a reply.
+    pub reply_right: Option<ReplyRight>,
 }
 
 impl Message {
@@ -117,19 +193,44 @@ impl Message {
             server_id,
             identifier,
             data,
+            handle: None,
+            reply_right: None,
         }
     }
 
-    pub fn send(self) {
-        syscall::send_message(self.server_id, self.identifier, self.data);
+    /// Queue the message for the server, waiting for room if its queue is full.
+    pub fn send(self) -> Result<(), SyscallError> {
+        syscall::send_message(
+            self.server_id,
+            self.identifier,
+            self.data,
+            self.handle,
+            true,
+        )
     }
 
+    /// Queue the message for the server, failing with [`SyscallError::Full`] if its queue is full.
+    pub fn try_send(self) -> Result<(), SyscallError> {
+        syscall::send_message(
+            self.server_id,
+            self.identifier,
+            self.data,
+            self.handle,
+            false,
+        )
+    }
+
+    /// Receive a message for the server of the current process, or `None` if there is none or it is no server.
     pub fn receive() -> Option<Message> {
-        syscall::receive_message().map(|(identifier, server_id, data)| Message {
-            server_id,
-            identifier,
-            data,
-        })
+        syscall::receive_message(own_handle()?)
+            .ok()
+            .map(|msg| Message {
+                server_id: msg.sender_sid,
+                identifier: msg.identifier,
+                data: msg.data,
+                handle: msg.handle,
+                reply_right: msg.reply_token.map(ReplyRight),
+            })
     }
 
     pub fn receive_blocking() -> Message {
@@ -137,11 +238,21 @@ impl Message {
         Self::receive().unwrap()
     }
 
-    pub fn send_receive(self) -> Option<Message> {
-        syscall::send_message(self.server_id, self.identifier, self.data);
-        syscall::wait_until_message_received();
-        // TODO: ensure that the message came from the server we contacted
-        Message::receive()
+    /// Wait up to the given duration for a message, or `None` if none arrived in time.
+    pub fn receive_timeout(timeout: Duration) -> Option<Message> {
+        match syscall::wait_events(Events::MESSAGE, Some(timeout)).ok()? {
+            (Event::Message, _) => Self::receive(),
+            _ => None,
+        }
+    }
+
+    /// Send the message and block until the server replies to it, returning the reply.
+    pub fn call(self) -> Result<Message, SyscallError> {
+        let reply = syscall::call(self.server_id, self.identifier, self.data, self.handle)?;
+        Ok(Message {
+            handle: reply.handle,
+            ..Message::new(self.server_id, reply.identifier, reply.data)
+        })
     }
 }
 
@@ -150,14 +261,17 @@ pub struct MessageBuilder {
     server_id: u64,
     identifier: u64,
     data: MessageData,
+    handle: Option<(u64, Rights)>,
 }
 
 impl MessageBuilder {
+    /// Start a message to the server the given handle refers to.
     pub const fn new(server_id: u64) -> MessageBuilder {
         Self {
             server_id,
             identifier: 0,
             data: MessageData::DEFAULT,
+            handle: None,
         }
     }
 
@@ -171,15 +285,29 @@ impl MessageBuilder {
         self
     }
 
+    /// Grant the receiver a copy of the given handle, with at most the given rights.
+    pub const fn with_handle(mut self, handle: u64, rights: Rights) -> MessageBuilder {
+        self.handle = Some((handle, rights));
+        self
+    }
+
     pub const fn build(self) -> Message {
         Message {
-            server_id: self.server_id,
-            identifier: self.identifier,
-            data: self.data,
+            handle: self.handle,
+            ..Message::new(self.server_id, self.identifier, self.data)
         }
     }
 
-    pub fn send(self) {
-        self.build().send();
+    pub fn send(self) -> Result<(), SyscallError> {
+        self.build().send()
+    }
+
+    pub fn try_send(self) -> Result<(), SyscallError> {
+        self.build().try_send()
+    }
+
+    /// Send the built message as the reply to a call, ignoring the server ID.
+    pub fn reply(self, right: ReplyRight) -> bool {
+        right.reply(self.identifier, self.data, self.handle)
     }
 }
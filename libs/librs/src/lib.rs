@@ -7,12 +7,21 @@
 pub mod print;
 pub mod allocator;
 pub mod ipc;
-pub mod mutex;
+pub mod log;
 pub mod path;
+pub mod service;
+pub mod shared;
+pub mod signal;
+pub mod sync;
 pub mod syscall;
 pub mod test;
+pub mod thread;
 
 extern crate alloc;
+// Lets the code that `service` generates refer to this crate by name from within it as well
+extern crate self as librs;
+
+pub use service_macros::service;
 
 use core::arch::asm;
 
@@ -66,5 +75,5 @@ pub extern "C" fn _start() -> ! {
         __zebra_main();
     }
 
-    syscall::exit();
+    syscall::exit(0);
 }
@@ -0,0 +1,17 @@
+//! The service of the log server, which writes to and reads from the UART.
+
+use crate::service::Reply;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+pub const NAME: &str = "log";
+
+#[crate::service(NAME)]
+pub trait Log {
+    /// Write the bytes to the UART.
+    fn write(&mut self, bytes: Vec<u8>);
+
+    /// Read a byte of input, waiting up to the timeout for one to arrive, or forever without one.
+    /// `None` if no input arrived in time.
+    fn read(&mut self, timeout: Option<Duration>, reply: Reply<Option<u8>>);
+}
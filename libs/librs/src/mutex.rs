@@ -1,60 +0,0 @@
-use crate::syscall;
-use core::{
-    cell::UnsafeCell,
-    ops::{Deref, DerefMut},
-    sync::atomic::AtomicBool,
-};
-
-pub struct Mutex<T> {
-    locked: AtomicBool,
-    inner: UnsafeCell<T>,
-}
-
-unsafe impl<T> Send for Mutex<T> {}
-unsafe impl<T> Sync for Mutex<T> {}
-
-impl<T> Mutex<T> {
-    pub const fn new(inner: T) -> Self {
-        Self {
-            locked: AtomicBool::new(false),
-            inner: UnsafeCell::new(inner),
-        }
-    }
-
-    pub fn lock(&self) -> MutexGuard<T> {
-        while self
-            .locked
-            .swap(true, core::sync::atomic::Ordering::Acquire)
-        {
-            syscall::yield_();
-        }
-
-        MutexGuard { lock: self }
-    }
-}
-
-pub struct MutexGuard<'a, T> {
-    lock: &'a Mutex<T>,
-}
-
-impl<'a, T> Drop for MutexGuard<'a, T> {
-    fn drop(&mut self) {
-        self.lock
-            .locked
-            .store(false, core::sync::atomic::Ordering::Release);
-    }
-}
-
-impl<'a, T> Deref for MutexGuard<'a, T> {
-    type Target = T;
-
-    fn deref(&self) -> &Self::Target {
-        unsafe { &*self.lock.inner.get() }
-    }
-}
-
-impl<'a, T> DerefMut for MutexGuard<'a, T> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { &mut *self.lock.inner.get() }
-    }
-}
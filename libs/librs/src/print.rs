@@ -1,21 +1,26 @@
-use crate::ipc::{self, MessageData};
-use core::{
-    fmt::{self, Write},
-    mem::size_of,
-};
+use crate::{log::LogClient, service::INLINE_BYTES};
+use alloc::string::String;
+use core::fmt::{self, Write};
+
+// Output is dropped until the log server is running
+static LOG: LogClient = LogClient::without_waiting();
+
+/// Output up to this size is sent in chunks that fit into messages. Sharing memory for larger output costs
+/// about as many system calls on both sides as sending this many bytes inline.
+const SHARED_MEMORY_THRESHOLD: usize = 8 * INLINE_BYTES;
 
 pub struct StandardOutput;
 
 impl Write for StandardOutput {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        s.as_bytes()
-            .chunks(size_of::<MessageData>())
-            .for_each(|bytes| {
-                ipc::MessageBuilder::new(u64::from_le_bytes(*b"log\0\0\0\0\0"))
-                    .with_identifier(1) // ID_WRITE
-                    .with_data(bytes.into())
-                    .send();
-            });
+        // Output is dropped as well if it cannot be queued, for example in interrupt handlers
+        if s.len() > SHARED_MEMORY_THRESHOLD {
+            let _ = LOG.write(s.as_bytes().to_vec());
+        } else {
+            for chunk in s.as_bytes().chunks(INLINE_BYTES) {
+                let _ = LOG.write(chunk.to_vec());
+            }
+        }
 
         Ok(())
     }
@@ -23,11 +28,14 @@ impl Write for StandardOutput {
 
 impl StandardOutput {
     pub fn print(with_newline: bool, args: ::core::fmt::Arguments) {
+        // Format everything first, so that it takes as few messages as possible
+        let mut output = String::new();
+        output.write_fmt(args).unwrap();
         if with_newline {
-            writeln!(StandardOutput, "{args}").unwrap();
-        } else {
-            write!(StandardOutput, "{args}").unwrap();
+            output.push('\n');
         }
+
+        StandardOutput.write_str(&output).unwrap();
     }
 }
 
@@ -0,0 +1,645 @@
+//! What the clients and servers that [`service`](crate::service!) generates build upon:
+//! how values are packed into messages, and how replies and failures travel back.
+
+use crate::{
+    ipc::{self, MessageData, ReplyRight},
+    shared::{Mapping, SharedMemory},
+    syscall::{self, Rights, SyscallError},
+};
+use alloc::{string::String, vec::Vec};
+use core::{marker::PhantomData, mem::size_of, time::Duration};
+
+/// The identifier of a reply with a value, or with the value of an `Ok` result.
+pub const REPLY_OK: u64 = 0;
+/// The identifier of a reply with the value of an `Err` result.
+pub const REPLY_ERR: u64 = 1;
+/// The identifier of a reply to a request that is unknown to the server, or whose arguments are malformed.
+pub const REPLY_REJECTED: u64 = u64::MAX;
+
+/// Why a request to a service failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The server did not register yet, and the client does not wait for it.
+    NotRunning,
+    /// The request could not be delivered, or the server exited before replying.
+    Ipc(SyscallError),
+    /// The server does not know the request, or could not make sense of its arguments.
+    Rejected,
+    /// The reply could not be made sense of.
+    InvalidReply,
+}
+
+impl From<SyscallError> for Error {
+    fn from(error: SyscallError) -> Self {
+        Self::Ipc(error)
+    }
+}
+
+/// How many words of a message bytes of any length take up, see [`Encoder::push_bytes`].
+pub const BYTES_WORDS: usize = MessageData::LEN;
+
+/// How many bytes fit into a message along with their length, when they are its only value.
+pub const INLINE_BYTES: usize = (BYTES_WORDS - 1) * size_of::<u64>();
+
+/// A value that can be passed in a message, taking up a fixed number of its words.
+pub trait Payload: Sized {
+    /// How many words of the message the value takes up.
+    const WORDS: usize;
+    /// How many handles are granted along with the value.
+    const HANDLES: usize = 0;
+
+    fn encode(&self, encoder: &mut Encoder);
+    fn decode(decoder: &mut Decoder) -> Option<Self>;
+}
+
+/// A value a server replies with, which is any [`Payload`] or a result of them.
+pub trait Response: Sized {
+    const WORDS: usize;
+    const HANDLES: usize;
+
+    fn encode(&self, encoder: &mut Encoder);
+    fn decode(identifier: u64, decoder: &mut Decoder) -> Option<Self>;
+}
+
+/// Whether values of the given size fit into a single message.
+pub const fn fits(words: usize, handles: usize) -> bool {
+    words <= MessageData::LEN && handles <= 1
+}
+
+/// Packs values into a message one after the other.
+#[derive(Debug)]
+pub struct Encoder {
+    identifier: u64,
+    data: MessageData,
+    len: usize,
+    handle: Option<(u64, Rights)>,
+    /// Memory holding a payload too large for the message, which is kept until the message is sent.
+    memory: Option<SharedMemory>,
+}
+
+impl Encoder {
+    pub const fn new(identifier: u64) -> Self {
+        Self {
+            identifier,
+            data: MessageData::DEFAULT,
+            len: 0,
+            handle: None,
+            memory: None,
+        }
+    }
+
+    /// How many words are left in the message.
+    pub const fn remaining(&self) -> usize {
+        MessageData::LEN - self.len
+    }
+
+    pub fn push(&mut self, word: u64) {
+        assert!(self.remaining() > 0, "the values do not fit into a message");
+        self.data[self.len] = word;
+        self.len += 1;
+    }
+
+    /// Leave the given number of words zero, to keep the values after them in place.
+    pub fn skip(&mut self, words: usize) {
+        (0..words).for_each(|_| self.push(0));
+    }
+
+    /// Grant the receiver a copy of the given handle, with at most the given rights.
+    pub fn grant(&mut self, handle: u64, rights: Rights) {
+        assert!(self.handle.is_none(), "only one handle can be granted");
+        self.handle = Some((handle, rights));
+    }
+
+    /// Pack bytes of any length, which take up the rest of the message: their length, followed by the bytes themselves
+    /// if they fit, or granting read-only access to shared memory holding them otherwise.
+    pub fn push_bytes(&mut self, bytes: &[u8]) {
+        self.push(bytes.len() as u64);
+
+        if bytes.len() <= self.remaining() * size_of::<u64>() {
+            for chunk in bytes.chunks(size_of::<u64>()) {
+                let mut word = [0; size_of::<u64>()];
+                word[..chunk.len()].copy_from_slice(chunk);
+                self.push(u64::from_be_bytes(word));
+            }
+            self.skip(self.remaining());
+            return;
+        }
+
+        // Without memory to share them, the receiver finds no handle and rejects the bytes
+        let Ok(memory) = SharedMemory::new(bytes.len()) else {
+            return;
+        };
+        let Ok(mut mapping) = memory.map_mut() else {
+            return;
+        };
+
+        mapping[..bytes.len()].copy_from_slice(bytes);
+        self.grant(memory.handle(), Rights::READ);
+        self.memory = Some(memory);
+    }
+
+    fn message(&self, server_id: u64) -> ipc::Message {
+        ipc::Message {
+            handle: self.handle,
+            ..ipc::Message::new(server_id, self.identifier, self.data)
+        }
+    }
+}
+
+/// Unpacks values from a received message in the order they were packed.
+/// A granted handle that no value took is closed once the decoder is dropped.
+#[derive(Debug)]
+pub struct Decoder {
+    data: MessageData,
+    index: usize,
+    handle: Option<(u64, Rights)>,
+}
+
+impl Decoder {
+    pub fn new(msg: &ipc::Message) -> Self {
+        Self {
+            data: msg.data,
+            index: 0,
+            handle: msg.handle,
+        }
+    }
+
+    /// How many words are left to be unpacked.
+    pub const fn remaining(&self) -> usize {
+        MessageData::LEN - self.index
+    }
+
+    pub fn pop(&mut self) -> Option<u64> {
+        let word = *self.data.data.get(self.index)?;
+        self.index += 1;
+        Some(word)
+    }
+
+    /// Skip over the given number of words.
+    pub fn skip(&mut self, words: usize) -> Option<()> {
+        (0..words).try_for_each(|_| self.pop().map(drop))
+    }
+
+    /// Unpack bytes that were packed with [`Encoder::push_bytes`].
+    pub fn pop_bytes(&mut self) -> Option<Vec<u8>> {
+        let len = self.pop()? as usize;
+
+        // The length comes from the sender, so nothing is allocated before it is known to fit
+        if len <= self.remaining() * size_of::<u64>() {
+            let mut bytes = Vec::with_capacity(len);
+            while let Some(word) = self.pop() {
+                bytes.extend_from_slice(&word.to_be_bytes());
+            }
+            bytes.truncate(len);
+            Some(bytes)
+        } else {
+            let (handle, _) = self.take_handle()?;
+            let memory = SharedMemory::from_handle(handle);
+            Some(memory.map().ok()?.get(..len)?.to_vec())
+        }
+    }
+
+    /// Take the handle that was granted along with the message.
+    pub fn take_handle(&mut self) -> Option<(u64, Rights)> {
+        self.handle.take()
+    }
+}
+
+impl Drop for Decoder {
+    fn drop(&mut self) {
+        if let Some((handle, _)) = self.handle {
+            let _ = syscall::close_handle(handle);
+        }
+    }
+}
+
+/// The right to reply to a call with a value of the given type, which can be kept to reply later.
+#[derive(Debug)]
+pub struct Reply<T> {
+    right: Option<ReplyRight>,
+    value: PhantomData<fn(T)>,
+}
+
+impl<T: Response> Reply<T> {
+    #[doc(hidden)]
+    pub fn new(right: Option<ReplyRight>) -> Self {
+        Self {
+            right,
+            value: PhantomData,
+        }
+    }
+
+    /// Let the caller continue with the given value. Returns `false` if the caller is gone, or did not wait for a reply.
+    pub fn send(self, value: T) -> bool {
+        let Some(right) = self.right else {
+            return false;
+        };
+
+        let mut encoder = Encoder::new(REPLY_OK);
+        value.encode(&mut encoder);
+        right.reply(encoder.identifier, encoder.data, encoder.handle)
+    }
+}
+
+/// Reply to a request that is unknown or malformed, if the sender waits for a reply.
+pub fn reject(right: Option<ReplyRight>) {
+    if let Some(right) = right {
+        right.reply(REPLY_REJECTED, MessageData::DEFAULT, None);
+    }
+}
+
+/// A connection to the server of a service, which the generated clients send their requests over.
+#[derive(Debug)]
+pub struct Client {
+    connection: ipc::Connection,
+    wait: bool,
+}
+
+impl Client {
+    pub const fn new(name: &'static str, wait: bool) -> Self {
+        Self {
+            connection: ipc::Connection::new(name),
+            wait,
+        }
+    }
+
+    /// Block until the server registered.
+    pub fn wait(&self) {
+        self.connection.wait();
+    }
+
+    fn handle(&self) -> Result<u64, Error> {
+        if self.wait {
+            Ok(self.connection.wait())
+        } else {
+            self.connection.handle().ok_or(Error::NotRunning)
+        }
+    }
+
+    /// Queue the request for the server, waiting for room if its queue is full.
+    pub fn send(&self, encoder: Encoder) -> Result<(), Error> {
+        Ok(encoder.message(self.handle()?).send()?)
+    }
+
+    /// Send the request and block until the server replies to it.
+    pub fn call<R: Response>(&self, encoder: Encoder) -> Result<R, Error> {
+        let reply = encoder.message(self.handle()?).call()?;
+        if reply.identifier == REPLY_REJECTED {
+            return Err(Error::Rejected);
+        }
+
+        R::decode(reply.identifier, &mut Decoder::new(&reply)).ok_or(Error::InvalidReply)
+    }
+}
+
+/// Bytes that are passed along with a message in shared memory, as they would not fit into one.
+/// The receiver is granted read-only access to them.
+#[derive(Debug)]
+pub struct Buffer {
+    memory: SharedMemory,
+    len: usize,
+}
+
+impl Buffer {
+    /// Copy the bytes into newly created shared memory, which fails if there is not enough memory.
+    pub fn new(bytes: &[u8]) -> Result<Self, SyscallError> {
+        let memory = SharedMemory::new(bytes.len().max(1))?;
+        memory.map_mut()?[..bytes.len()].copy_from_slice(bytes);
+
+        Ok(Self {
+            memory,
+            len: bytes.len(),
+        })
+    }
+
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Map the memory holding the bytes, which are at its start.
+    pub fn map(&self) -> Option<Mapping> {
+        self.memory
+            .map()
+            .ok()
+            .filter(|mapping| mapping.len() >= self.len)
+    }
+
+    pub fn to_vec(&self) -> Option<Vec<u8>> {
+        Some(self.map()?[..self.len].to_vec())
+    }
+}
+
+impl Payload for Buffer {
+    const WORDS: usize = 1;
+    const HANDLES: usize = 1;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.push(self.len as u64);
+        encoder.grant(self.memory.handle(), Rights::READ);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Option<Self> {
+        let len = decoder.pop()? as usize;
+        let (handle, _) = decoder.take_handle()?;
+
+        Some(Self {
+            memory: SharedMemory::from_handle(handle),
+            len,
+        })
+    }
+}
+
+impl<T: Payload> Response for T {
+    const WORDS: usize = T::WORDS;
+    const HANDLES: usize = T::HANDLES;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        Payload::encode(self, encoder);
+    }
+
+    fn decode(identifier: u64, decoder: &mut Decoder) -> Option<Self> {
+        match identifier {
+            REPLY_OK => Payload::decode(decoder),
+            _ => None,
+        }
+    }
+}
+
+/// Results tell apart their variants by the identifier of the reply, so they take no extra word.
+impl<T: Payload, E: Payload> Response for Result<T, E> {
+    const WORDS: usize = max(T::WORDS, E::WORDS);
+    const HANDLES: usize = max(T::HANDLES, E::HANDLES);
+
+    fn encode(&self, encoder: &mut Encoder) {
+        match self {
+            Ok(value) => value.encode(encoder),
+            Err(error) => {
+                encoder.identifier = REPLY_ERR;
+                error.encode(encoder);
+            }
+        }
+    }
+
+    fn decode(identifier: u64, decoder: &mut Decoder) -> Option<Self> {
+        match identifier {
+            REPLY_OK => Some(Ok(T::decode(decoder)?)),
+            REPLY_ERR => Some(Err(E::decode(decoder)?)),
+            _ => None,
+        }
+    }
+}
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+/// Integers take up a word, signed ones are sign-extended. Values that do not fit into the type are malformed.
+macro_rules! impl_payload_for_integer {
+    ($word:ty => $($ty:ty),*) => {
+        $(
+            impl Payload for $ty {
+                const WORDS: usize = 1;
+
+                fn encode(&self, encoder: &mut Encoder) {
+                    encoder.push(*self as $word as u64);
+                }
+
+                fn decode(decoder: &mut Decoder) -> Option<Self> {
+                    Self::try_from(decoder.pop()? as $word).ok()
+                }
+            }
+        )*
+    };
+}
+
+impl_payload_for_integer!(u64 => u8, u16, u32, u64, usize);
+impl_payload_for_integer!(i64 => i8, i16, i32, i64, isize);
+
+impl Payload for () {
+    const WORDS: usize = 0;
+
+    fn encode(&self, _: &mut Encoder) {}
+
+    fn decode(_: &mut Decoder) -> Option<Self> {
+        Some(())
+    }
+}
+
+impl Payload for bool {
+    const WORDS: usize = 1;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.push(*self as u64);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Option<Self> {
+        match decoder.pop()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+/// Durations are passed in nanoseconds, the longest one is over 500 years.
+impl Payload for Duration {
+    const WORDS: usize = 1;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.push(u64::try_from(self.as_nanos()).unwrap_or(u64::MAX));
+    }
+
+    fn decode(decoder: &mut Decoder) -> Option<Self> {
+        Some(Duration::from_nanos(decoder.pop()?))
+    }
+}
+
+impl Payload for MessageData {
+    const WORDS: usize = MessageData::LEN;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        self.iter().for_each(|&word| encoder.push(word));
+    }
+
+    fn decode(decoder: &mut Decoder) -> Option<Self> {
+        let mut data = MessageData::DEFAULT;
+        for word in data.data.iter_mut() {
+            *word = decoder.pop()?;
+        }
+        Some(data)
+    }
+}
+
+/// Strings take up the rest of the message, see [`Encoder::push_bytes`].
+impl Payload for String {
+    const WORDS: usize = BYTES_WORDS;
+    const HANDLES: usize = 1;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.push_bytes(self.as_bytes());
+    }
+
+    fn decode(decoder: &mut Decoder) -> Option<Self> {
+        String::from_utf8(decoder.pop_bytes()?).ok()
+    }
+}
+
+/// Lists of integers take up the rest of the message, packed tightly by their bytes.
+macro_rules! impl_payload_for_integer_vec {
+    ($($ty:ty),*) => {
+        $(
+            impl Payload for Vec<$ty> {
+                const WORDS: usize = BYTES_WORDS;
+                const HANDLES: usize = 1;
+
+                fn encode(&self, encoder: &mut Encoder) {
+                    let bytes: Vec<u8> = self.iter().flat_map(|value| value.to_be_bytes()).collect();
+                    encoder.push_bytes(&bytes);
+                }
+
+                fn decode(decoder: &mut Decoder) -> Option<Self> {
+                    let bytes = decoder.pop_bytes()?;
+                    let chunks = bytes.chunks_exact(size_of::<$ty>());
+                    if !chunks.remainder().is_empty() {
+                        return None;
+                    }
+
+                    Some(chunks.map(|chunk| <$ty>::from_be_bytes(chunk.try_into().unwrap())).collect())
+                }
+            }
+        )*
+    };
+}
+
+impl_payload_for_integer_vec!(u8, u16, u32, u64, usize);
+
+/// Options take up a word to tell whether there is a value, and as many words as the value otherwise.
+impl<T: Payload> Payload for Option<T> {
+    const WORDS: usize = 1 + T::WORDS;
+    const HANDLES: usize = T::HANDLES;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        match self {
+            Some(value) => {
+                encoder.push(1);
+                value.encode(encoder);
+            }
+            None => {
+                encoder.push(0);
+                encoder.skip(T::WORDS);
+            }
+        }
+    }
+
+    fn decode(decoder: &mut Decoder) -> Option<Self> {
+        match decoder.pop()? {
+            0 => decoder.skip(T::WORDS).map(|_| None),
+            1 => Some(Some(T::decode(decoder)?)),
+            _ => None,
+        }
+    }
+}
+
+impl<T: Payload, const N: usize> Payload for [T; N] {
+    const WORDS: usize = N * T::WORDS;
+    const HANDLES: usize = N * T::HANDLES;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        self.iter().for_each(|value| value.encode(encoder));
+    }
+
+    fn decode(decoder: &mut Decoder) -> Option<Self> {
+        let values: Vec<T> = (0..N).map(|_| T::decode(decoder)).collect::<Option<_>>()?;
+        values.try_into().ok()
+    }
+}
+
+impl<A: Payload, B: Payload> Payload for (A, B) {
+    const WORDS: usize = A::WORDS + B::WORDS;
+    const HANDLES: usize = A::HANDLES + B::HANDLES;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        self.0.encode(encoder);
+        self.1.encode(encoder);
+    }
+
+    fn decode(decoder: &mut Decoder) -> Option<Self> {
+        Some((A::decode(decoder)?, B::decode(decoder)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    fn round_trip<T: Payload>(value: &T) -> Option<T> {
+        let mut encoder = Encoder::new(0);
+        value.encode(&mut encoder);
+        T::decode(&mut Decoder::new(&encoder.message(0)))
+    }
+
+    #[test_case]
+    fn values_survive_being_packed() {
+        assert_eq!(round_trip(&-3i32), Some(-3));
+        assert_eq!(round_trip(&Some(7u8)), Some(Some(7)));
+        assert_eq!(round_trip(&None::<u64>), Some(None));
+        assert_eq!(round_trip(&(true, [1usize, 2, 3])), Some((true, [1, 2, 3])));
+        assert_eq!(
+            round_trip(&Some(Duration::from_millis(5))),
+            Some(Some(Duration::from_millis(5)))
+        );
+        assert_eq!(round_trip(&"hello".to_string()), Some("hello".to_string()));
+    }
+
+    #[test_case]
+    fn large_payloads_are_shared() {
+        let long = "ä".repeat(3000);
+        assert_eq!(round_trip(&long), Some(long));
+
+        let list: Vec<usize> = (0..4).collect();
+        assert_eq!(round_trip(&list), Some(list));
+    }
+
+    #[test_case]
+    fn out_of_range_integers_are_rejected() {
+        let mut encoder = Encoder::new(0);
+        encoder.push(0x100);
+        let msg = encoder.message(0);
+        assert_eq!(<u8 as Payload>::decode(&mut Decoder::new(&msg)), None);
+        assert_eq!(
+            <u16 as Payload>::decode(&mut Decoder::new(&msg)),
+            Some(0x100)
+        );
+    }
+
+    #[test_case]
+    fn oversized_lengths_are_rejected() {
+        let mut encoder = Encoder::new(0);
+        encoder.push(u64::MAX);
+        let msg = encoder.message(0);
+        assert_eq!(Decoder::new(&msg).pop_bytes(), None);
+        assert_eq!(<String as Payload>::decode(&mut Decoder::new(&msg)), None);
+    }
+
+    #[test_case]
+    fn results_are_told_apart_by_identifier() {
+        let mut encoder = Encoder::new(0);
+        Response::encode(&Err::<u64, u8>(4), &mut encoder);
+        assert_eq!(encoder.identifier, REPLY_ERR);
+
+        let msg = encoder.message(0);
+        assert_eq!(
+            <Result<u64, u8> as Response>::decode(REPLY_ERR, &mut Decoder::new(&msg)),
+            Some(Err(4))
+        );
+    }
+}
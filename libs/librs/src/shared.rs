@@ -0,0 +1,92 @@
+//! Memory that can be shared with other processes by granting its handle along with a message.
+
+use crate::syscall::{self, SyscallError};
+use core::{
+    ops::{Deref, DerefMut},
+    slice,
+};
+
+/// A handle to shared memory. The memory is freed once every handle to it is closed and every mapping of it is gone.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SharedMemory {
+    handle: u64,
+}
+
+impl SharedMemory {
+    /// Create zero-filled memory of at least the given size, which may be read, written and granted.
+    pub fn new(size: usize) -> Result<Self, SyscallError> {
+        syscall::create_memory(size).map(|handle| Self { handle })
+    }
+
+    /// Take ownership of a handle that was granted along with a message.
+    pub fn from_handle(handle: u64) -> Self {
+        Self { handle }
+    }
+
+    pub fn handle(&self) -> u64 {
+        self.handle
+    }
+
+    /// Map the memory read-only, fails with [`SyscallError::InvalidHandle`] if the handle may not read it.
+    pub fn map(&self) -> Result<Mapping, SyscallError> {
+        let (start, size) = syscall::map_memory(self.handle, false)?;
+        Ok(Mapping {
+            start,
+            size,
+            writable: false,
+        })
+    }
+
+    /// Map the memory readable and writable, fails with [`SyscallError::InvalidHandle`] if the handle may not write to it.
+    pub fn map_mut(&self) -> Result<Mapping, SyscallError> {
+        let (start, size) = syscall::map_memory(self.handle, true)?;
+        Ok(Mapping {
+            start,
+            size,
+            writable: true,
+        })
+    }
+
+    /// Take back every handle and mapping that was derived from this handle by granting it.
+    pub fn revoke(&self) -> Result<(), SyscallError> {
+        syscall::revoke(self.handle)
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        // The handle stays valid until it is dropped
+        let _ = syscall::close_handle(self.handle);
+    }
+}
+
+/// Shared memory that is mapped into the current process, it is unmapped once dropped.
+/// The mapping is gone as well once it is revoked, accessing it afterwards faults.
+#[derive(Debug)]
+pub struct Mapping {
+    start: *mut u8,
+    size: usize,
+    writable: bool,
+}
+
+impl Deref for Mapping {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.start, self.size) }
+    }
+}
+
+impl DerefMut for Mapping {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        assert!(self.writable, "the memory was mapped read-only");
+        unsafe { slice::from_raw_parts_mut(self.start, self.size) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // Fails if the mapping was revoked, in which case it is gone already
+        let _ = syscall::unmap_memory(self.start);
+    }
+}
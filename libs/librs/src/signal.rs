@@ -0,0 +1,61 @@
+//! Handling of signals, the asynchronous notifications processes send each other with [`kill`].
+
+use crate::syscall::{self, MaskHow};
+use alloc::vec;
+use core::mem::ManuallyDrop;
+
+pub use syscall::{kill, Signal, SignalSet};
+
+const STACK_SIZE: usize = 16 * 1024;
+
+/// What happens when the current process receives a signal.
+#[derive(Debug, Clone, Copy)]
+pub enum Handler {
+    /// Terminate the process, or ignore the signal for [`Signal::Child`].
+    Default,
+    Ignore,
+    /// Call the given function on one of the threads of the process, which continues afterwards.
+    Function(fn(Signal)),
+}
+
+/// Set the handler of the given signal, returns `false` if it cannot be changed, which is the case for [`Signal::Kill`].
+pub fn set_handler(signal: Signal, handler: Handler) -> bool {
+    let entry: extern "C" fn(u64, u64) -> ! = signal_entry;
+    let previous = match handler {
+        Handler::Default => syscall::signal_action(signal, 0, 0),
+        Handler::Ignore => syscall::signal_action(signal, 1, 0),
+        Handler::Function(function) => {
+            syscall::signal_action(signal, entry as usize, function as usize as _)
+        }
+    };
+
+    previous.is_ok()
+}
+
+/// Keep the given signals pending until they are unblocked, returning the signals that were blocked before.
+pub fn block(signals: SignalSet) -> SignalSet {
+    syscall::signal_mask(MaskHow::Block, signals)
+}
+
+/// Deliver the given signals again, including the ones that arrived while they were blocked.
+pub fn unblock(signals: SignalSet) -> SignalSet {
+    syscall::signal_mask(MaskHow::Unblock, signals)
+}
+
+/// Run signal handlers of the calling thread on a dedicated stack, so that they also work when it ran out of stack.
+pub fn use_alternate_stack() {
+    let mut stack = ManuallyDrop::new(vec![0u8; STACK_SIZE]);
+    let top = (stack.as_mut_ptr() as usize + STACK_SIZE) & !0xf;
+
+    // The stack is used for the rest of the life of the process
+    unsafe { syscall::signal_stack(top as _) };
+}
+
+extern "C" fn signal_entry(signal: u64, function: u64) -> ! {
+    let function: fn(Signal) = unsafe { core::mem::transmute(function as usize) };
+    if let Ok(signal) = Signal::try_from(signal) {
+        function(signal);
+    }
+
+    syscall::signal_return();
+}
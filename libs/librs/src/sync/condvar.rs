@@ -0,0 +1,56 @@
+use super::MutexGuard;
+use crate::syscall::{self, SyscallError};
+use core::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+/// Lets threads wait for a condition protected by a [`super::Mutex`] to change.
+pub struct Condvar {
+    /// Changed on every notification, so that waiters notice notifications sent after they released the mutex.
+    sequence: AtomicU32,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            sequence: AtomicU32::new(0),
+        }
+    }
+
+    /// Release the mutex and block until notified, then lock it again. Wake ups may be spurious.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        self.wait_timeout(guard, None).0
+    }
+
+    /// Like [`Condvar::wait`], but give up once the timeout expires, in which case `true` is returned as well.
+    pub fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Option<Duration>,
+    ) -> (MutexGuard<'a, T>, bool) {
+        let sequence = self.sequence.load(Ordering::Relaxed);
+        let mutex = guard.lock;
+        drop(guard);
+
+        let timed_out =
+            syscall::futex_wait(&self.sequence, sequence, timeout) == Err(SyscallError::TimedOut);
+        (mutex.lock(), timed_out)
+    }
+
+    pub fn notify_one(&self) {
+        self.sequence.fetch_add(1, Ordering::Relaxed);
+        syscall::futex_wake(&self.sequence, 1);
+    }
+
+    pub fn notify_all(&self) {
+        self.sequence.fetch_add(1, Ordering::Relaxed);
+        syscall::futex_wake(&self.sequence, u32::MAX);
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,13 @@
+//! Blocking synchronization primitives, which put waiting threads to sleep on a futex instead of spinning.
+
+mod condvar;
+mod mutex;
+mod once;
+mod rwlock;
+mod semaphore;
+
+pub use condvar::Condvar;
+pub use mutex::{Mutex, MutexGuard};
+pub use once::Once;
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::Semaphore;
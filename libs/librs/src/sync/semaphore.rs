@@ -0,0 +1,43 @@
+use crate::syscall;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// A counting semaphore, threads block in [`Semaphore::acquire`] until a permit is available.
+pub struct Semaphore {
+    permits: AtomicU32,
+}
+
+impl Semaphore {
+    pub const fn new(permits: u32) -> Self {
+        Self {
+            permits: AtomicU32::new(permits),
+        }
+    }
+
+    pub fn acquire(&self) {
+        while !self.try_acquire() {
+            let _ = syscall::futex_wait(&self.permits, 0, None);
+        }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        let mut permits = self.permits.load(Ordering::Relaxed);
+        while permits > 0 {
+            match self.permits.compare_exchange_weak(
+                permits,
+                permits - 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(current) => permits = current,
+            }
+        }
+
+        false
+    }
+
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::Release);
+        syscall::futex_wake(&self.permits, 1);
+    }
+}
@@ -1,12 +1,28 @@
 use crate::ipc::MessageData;
 use alloc::vec::Vec;
-use core::{arch::asm, ops::RangeInclusive, time::Duration};
+use core::{
+    arch::asm,
+    ops::RangeInclusive,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
 use syscall::SystemCall;
 
-/// Exit the current process.
-pub fn exit() -> ! {
+pub use syscall::{
+    Event, Events, ExitStatus, KillReason, MaskHow, Priority, ProcessInfo, Rights, RunState,
+    Signal, SignalSet, SyscallError, TraceKind, TraceRecord,
+};
+
+/// The result of a system call from the error it returned in `T3`, which is 0 if it succeeded.
+fn check(error: u64) -> Result<(), SyscallError> {
+    SyscallError::from_raw(error).map_or(Ok(()), Err)
+}
+
+/// Exit the current process with the given code, which is reported to the parent.
+pub fn exit(code: i32) -> ! {
     unsafe {
         asm!("ecall",
+            in("a0") code as i64,
             in("a7") SystemCall::Exit as usize,
             options(noreturn, nomem, nostack)
         );
@@ -18,6 +34,7 @@ pub fn yield_() {
     unsafe {
         asm!("ecall",
             in("a7") SystemCall::Yield as usize,
+            lateout("t3") _,
             options(nomem, nostack)
         );
     }
@@ -28,52 +45,144 @@ pub fn wait_until_message_received() {
     unsafe {
         asm!("ecall",
             in("a7") SystemCall::SleepUntilMessageReceived as usize,
+            lateout("t3") _,
             options(nomem, nostack)
         );
     }
 }
 
+/// Block until one of the given events happens, or until the timeout passed.
+/// For interrupts, the id of the interrupt is returned along with the event. An interrupt that arrived while the
+/// process was not waiting for interrupts ends the next wait for them right away.
+/// Fails if nothing could end the wait, when called from an interrupt handler, or when interrupted by a signal.
+pub fn wait_events(
+    events: Events,
+    timeout: Option<Duration>,
+) -> Result<(Event, u64), SyscallError> {
+    let event: u64;
+    let value: u64;
+    let error: u64;
+    let timeout = timeout.map_or(u64::MAX, |timeout| {
+        u64::try_from(timeout.as_nanos()).unwrap_or(u64::MAX - 1)
+    });
+
+    unsafe {
+        asm!("ecall",
+            inlateout("a0") events.raw() => event,
+            inlateout("a1") timeout => value,
+            in("a7") SystemCall::WaitEvents as usize,
+            lateout("t3") error,
+            options(nomem, nostack)
+        );
+    }
+
+    check(error)?;
+    let event = Event::new_with_raw_value(event).expect("the kernel only returns known events");
+    Ok((event, value))
+}
+
 /// Allocate a block of memory of the given size.
-pub fn allocate(size: usize) -> *mut u8 {
+pub fn allocate(size: usize) -> Result<*mut u8, SyscallError> {
     let result: *mut u8;
+    let error: u64;
 
     unsafe {
         asm!("ecall",
             in("a0") size,
             lateout("a0") result,
             in("a7") SystemCall::Allocate as usize,
+            lateout("t3") error,
             options(nomem, nostack)
         );
     }
 
-    result
+    check(error).map(|()| result)
 }
 
-/// Deallocate a block of memory, may panic if the pointer is invalid.
+/// Deallocate a block of memory, fails if it was not allocated with [`allocate`].
 ///
 /// # Safety
-/// The callee must ensure that the pointer is valid.
-pub unsafe fn deallocate(ptr: *mut u8) {
+/// The callee must ensure that the memory is not used anymore.
+pub unsafe fn deallocate(ptr: *mut u8) -> Result<(), SyscallError> {
+    let error: u64;
+
     unsafe {
         asm!("ecall",
             in("a0") ptr,
             in("a7") SystemCall::Deallocate as usize,
+            lateout("t3") error,
             options(nomem, nostack)
         );
     }
+
+    check(error)
+}
+
+/// Spawn a new process from an ELF file as a child of the current process, returning its PID.
+/// The process is named after the program in the ELF. Use [`wait`] to block until it exits.
+/// Fails with [`SyscallError::InvalidArgument`] if the file is not a valid ELF.
+pub fn spawn(elf: &[u8]) -> Result<u64, SyscallError> {
+    spawn_inner(elf, "")
 }
 
-/// Spawn a new process from an ELF file. If `blocking` is `true`, the current process will block until the new process exits.
-pub fn spawn(elf: &[u8], blocking: bool) {
+/// Spawn a new process like [`spawn`], but with the given name.
+pub fn spawn_named(elf: &[u8], name: &str) -> Result<u64, SyscallError> {
+    spawn_inner(elf, name)
+}
+
+fn spawn_inner(elf: &[u8], name: &str) -> Result<u64, SyscallError> {
+    let pid: u64;
+    let error: u64;
+
     unsafe {
         asm!("ecall",
             in("a0") elf.as_ptr(),
             in("a1") elf.len(),
-            in("a2") blocking as u64,
+            in("a2") name.as_ptr(),
+            in("a3") name.len(),
+            lateout("a0") pid,
             in("a7") SystemCall::Spawn as usize,
+            lateout("t3") error,
+            options(nostack)
+        );
+    }
+
+    check(error).map(|()| pid)
+}
+
+/// Block until the child process with the given PID exits, returning how it ended.
+/// Fails with [`SyscallError::NotFound`] if there is no such child, or if its status was already collected.
+pub fn wait(pid: u64) -> Result<ExitStatus, SyscallError> {
+    wait_inner(pid).map(|(_, status)| status)
+}
+
+/// Block until any child process exits, returning its PID and how it ended.
+/// Fails with [`SyscallError::NotFound`] if there are no children.
+pub fn wait_any() -> Result<(u64, ExitStatus), SyscallError> {
+    wait_inner(u64::MAX)
+}
+
+fn wait_inner(pid: u64) -> Result<(u64, ExitStatus), SyscallError> {
+    let child: u64;
+    let kind: u64;
+    let value: u64;
+    let error: u64;
+
+    unsafe {
+        asm!("ecall",
+            in("a0") pid,
+            lateout("a0") child,
+            lateout("a1") kind,
+            lateout("a2") value,
+            in("a7") SystemCall::Wait as usize,
+            lateout("t3") error,
             options(nomem, nostack)
         );
     }
+
+    check(error)?;
+    let status = ExitStatus::from_raw(kind, value).expect("the kernel only returns known statuses");
+    Ok((child, status))
 }
 
 /// The duration since the system was booted.
@@ -86,6 +195,7 @@ pub fn duration_since_boot() -> Duration {
             lateout("a0") secs,
             lateout("a1") subsec_nanos,
             in("a7") SystemCall::DurationSinceBootup as usize,
+            lateout("t3") _,
             options(nomem, nostack)
         );
     }
@@ -103,31 +213,48 @@ pub fn sleep(duration: Duration) {
             in("a0") secs,
             in("a1") subsec_nanos,
             in("a7") SystemCall::Sleep as usize,
+            lateout("t3") _,
             options(nomem, nostack)
         );
     }
 }
 
 /// Identity map the given range of physical memory into the processes address space.
-pub fn identity_map(range: RangeInclusive<u64>) {
+/// Fails with [`SyscallError::InvalidArgument`] if the start or the end of the range is not page aligned.
+pub fn identity_map(range: RangeInclusive<u64>) -> Result<(), SyscallError> {
     let start = *range.start();
     let end = *range.end();
+    let error: u64;
 
     unsafe {
         asm!("ecall",
             in("a0") start,
             in("a1") end,
             in("a7") SystemCall::IdentityMap as usize,
+            lateout("t3") error,
             options(nomem, nostack)
         );
     }
+
+    check(error)
 }
 
-/// Send a message to the server with the given ID.
-pub fn send_message(server_id: u64, identifier: u64, data: MessageData) {
+/// Queue a message for the server the given handle refers to, which requires the [`Rights::SEND`] right.
+/// Optionally a copy of another handle is granted to the receiver, with at most the given rights.
+/// If the queue of the server is full, this either blocks until there is room or fails with [`SyscallError::Full`].
+pub fn send_message(
+    handle: u64,
+    identifier: u64,
+    data: MessageData,
+    grant: Option<(u64, Rights)>,
+    block: bool,
+) -> Result<(), SyscallError> {
+    let (grant_handle, grant_rights) = grant.map_or((u64::MAX, 0), |(h, r)| (h, r.raw()));
+    let error: u64;
+
     unsafe {
         asm!("ecall",
-            in("a0") server_id,
+            in("a0") handle,
             in("a1") identifier,
             in("a2") data[0],
             in("a3") data[1],
@@ -135,57 +262,238 @@ pub fn send_message(server_id: u64, identifier: u64, data: MessageData) {
             in("a5") data[3],
             in("a6") data[4],
             in("a7") SystemCall::SendMessage as usize,
+            in("t0") grant_handle,
+            in("t1") grant_rights,
+            in("t2") block as u64,
+            lateout("t3") error,
             options(nomem, nostack)
         );
     }
+
+    check(error)
+}
+
+/// A message as it was received by [`receive_message`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReceivedMessage {
+    pub identifier: u64,
+    /// The ID of the server that sent the message, or 0 if the sender is not a server.
+    pub sender_sid: u64,
+    pub data: MessageData,
+    /// The token to [`reply`] with, if the message was sent with [`call`].
+    pub reply_token: Option<u64>,
+    /// The handle that was granted along with the message, and its rights.
+    pub handle: Option<(u64, Rights)>,
 }
 
-/// Receive a message from a client, returning the identifier and data.
-pub fn receive_message() -> Option<(u64, u64, MessageData)> {
+/// Receive a message from a client of the server the given handle refers to, which requires the [`Rights::RECEIVE`]
+/// right that only the handle from [`register_server`] has. Fails with [`SyscallError::WouldBlock`] if there is none.
+pub fn receive_message(handle: u64) -> Result<ReceivedMessage, SyscallError> {
     let identifier: u64;
     let sender_sid: u64;
     let mut data = [0; 5];
+    let reply_token: u64;
+    let granted: u64;
+    let rights: u64;
+    let error: u64;
 
     unsafe {
         asm!("ecall",
-            lateout("a0") identifier,
+            inlateout("a0") handle => identifier,
             lateout("a1") sender_sid,
             lateout("a2") data[0],
             lateout("a3") data[1],
             lateout("a4") data[2],
             lateout("a5") data[3],
             lateout("a6") data[4],
-            in("a7") SystemCall::ReceiveMessage as usize,
+            inlateout("a7") SystemCall::ReceiveMessage as usize => reply_token,
+            lateout("t0") granted,
+            lateout("t1") rights,
+            lateout("t3") error,
             options(nomem, nostack)
         );
     }
 
-    if identifier == u64::MAX {
-        None
-    } else {
-        Some((identifier, sender_sid, data.into()))
+    check(error)?;
+    Ok(ReceivedMessage {
+        identifier,
+        sender_sid,
+        data: data.into(),
+        reply_token: (reply_token != 0).then_some(reply_token),
+        handle: (granted != u64::MAX).then(|| (granted, Rights::from_raw(rights))),
+    })
+}
+
+/// Send a message to the server the given handle refers to, and block until it replies to exactly this message.
+/// This waits for room if the queue of the server is full. The caller does not need to be a server.
+pub fn call(
+    handle: u64,
+    identifier: u64,
+    data: MessageData,
+    grant: Option<(u64, Rights)>,
+) -> Result<ReceivedMessage, SyscallError> {
+    let (grant_handle, grant_rights) = grant.map_or((u64::MAX, 0), |(h, r)| (h, r.raw()));
+    let reply_identifier: u64;
+    let sender_sid: u64;
+    let mut reply = [0; 5];
+    let reply_handle: u64;
+    let reply_rights: u64;
+    let error: u64;
+
+    unsafe {
+        asm!("ecall",
+            inlateout("a0") handle => reply_identifier,
+            inlateout("a1") identifier => sender_sid,
+            inlateout("a2") data[0] => reply[0],
+            inlateout("a3") data[1] => reply[1],
+            inlateout("a4") data[2] => reply[2],
+            inlateout("a5") data[3] => reply[3],
+            inlateout("a6") data[4] => reply[4],
+            in("a7") SystemCall::Call as usize,
+            inlateout("t0") grant_handle => reply_handle,
+            inlateout("t1") grant_rights => reply_rights,
+            lateout("t3") error,
+            options(nomem, nostack)
+        );
+    }
+
+    check(error)?;
+    Ok(ReceivedMessage {
+        identifier: reply_identifier,
+        sender_sid,
+        data: reply.into(),
+        reply_token: None,
+        handle: (reply_handle != u64::MAX).then(|| (reply_handle, Rights::from_raw(reply_rights))),
+    })
+}
+
+/// Reply to a message received with the given token, which can only be done once.
+pub fn reply(
+    token: u64,
+    identifier: u64,
+    data: MessageData,
+    grant: Option<(u64, Rights)>,
+) -> Result<(), SyscallError> {
+    let (grant_handle, grant_rights) = grant.map_or((u64::MAX, 0), |(h, r)| (h, r.raw()));
+    let error: u64;
+
+    unsafe {
+        asm!("ecall",
+            in("a0") token,
+            in("a1") identifier,
+            in("a2") data[0],
+            in("a3") data[1],
+            in("a4") data[2],
+            in("a5") data[3],
+            in("a6") data[4],
+            in("a7") SystemCall::Reply as usize,
+            in("t0") grant_handle,
+            in("t1") grant_rights,
+            lateout("t3") error,
+            options(nomem, nostack)
+        );
     }
+
+    check(error)
+}
+
+/// Register the current process as a server, optionally with a unique name like `fs.ustar` that anyone can
+/// [`connect`] to. Returns a handle to the new server with all rights, which is also available from
+/// [`crate::ipc::own_handle`]. Fails with [`SyscallError::AlreadyExists`] if the name is taken,
+/// or if the current process already is a server.
+pub fn register_server(name: Option<&str>) -> Result<u64, SyscallError> {
+    register_server_with_queue_limit(name, None)
 }
 
-/// Register the current process as a server, optionally with a public name. Returns the server ID.
-pub fn register_server(public_name: Option<u64>) -> Option<u64> {
-    let public_name = public_name.unwrap_or(0);
-    let server_id: u64;
+/// Like [`register_server`], but with a limit on how many messages can be queued for the server.
+/// Without a limit, the default one of the kernel is used.
+pub fn register_server_with_queue_limit(
+    name: Option<&str>,
+    limit: Option<usize>,
+) -> Result<u64, SyscallError> {
+    let name = name.unwrap_or_default();
+    let handle: u64;
+    let error: u64;
 
     unsafe {
         asm!("ecall",
-            in("a0") public_name,
-            lateout("a0") server_id,
+            in("a0") name.as_ptr(),
+            in("a1") name.len(),
+            in("a2") limit.unwrap_or(0),
+            lateout("a0") handle,
             in("a7") SystemCall::RegisterServer as usize,
+            lateout("t3") error,
+            options(nostack)
+        );
+    }
+
+    check(error)?;
+    crate::ipc::OWN_HANDLE.store(handle, Ordering::Relaxed);
+    Ok(handle)
+}
+
+fn connect_inner(name: &str, wait: bool) -> Result<u64, SyscallError> {
+    let handle: u64;
+    let error: u64;
+
+    unsafe {
+        asm!("ecall",
+            in("a0") name.as_ptr(),
+            in("a1") name.len(),
+            in("a2") wait as u64,
+            lateout("a0") handle,
+            in("a7") SystemCall::Connect as usize,
+            lateout("t3") error,
+            options(nostack)
+        );
+    }
+
+    check(error).map(|()| handle)
+}
+
+/// Get a handle to the server with the given name, which allows sending to it and granting it.
+/// Fails with [`SyscallError::NotFound`] if no server registered with the name yet.
+pub fn connect(name: &str) -> Result<u64, SyscallError> {
+    connect_inner(name, false)
+}
+
+/// Like [`connect`], but blocks until a server registers with the given name.
+/// Only fails when called from an interrupt handler, which may not block, or with an empty name.
+pub fn connect_blocking(name: &str) -> Result<u64, SyscallError> {
+    connect_inner(name, true)
+}
+
+/// Close the given handle, fails with [`SyscallError::InvalidHandle`] if it does not exist.
+pub fn close_handle(handle: u64) -> Result<(), SyscallError> {
+    let error: u64;
+
+    unsafe {
+        asm!("ecall",
+            in("a0") handle,
+            in("a7") SystemCall::CloseHandle as usize,
+            lateout("t3") error,
             options(nomem, nostack)
         );
     }
 
-    if server_id == u64::MAX {
-        None
-    } else {
-        Some(server_id)
+    check(error)
+}
+
+/// Take away every handle that was granted from the given one, including those granted from them in turn.
+/// The handle itself stays valid. Fails with [`SyscallError::InvalidHandle`] if it does not exist.
+pub fn revoke(handle: u64) -> Result<(), SyscallError> {
+    let error: u64;
+
+    unsafe {
+        asm!("ecall",
+            in("a0") handle,
+            in("a7") SystemCall::Revoke as usize,
+            lateout("t3") error,
+            options(nomem, nostack)
+        );
     }
+
+    check(error)
 }
 
 /// Register a function as the handler for a given interrupt, must call `complete_interrupt` when done.
@@ -196,6 +504,7 @@ pub fn register_interrupt_handler(interrupt: u64, handler: extern "C" fn()) {
             in("a0") interrupt,
             in("a1") handler as usize,
             in("a7") SystemCall::RegisterInterruptHandler as usize,
+            lateout("t3") _,
             options(nomem, nostack)
         );
     }
@@ -211,24 +520,424 @@ pub fn complete_interrupt() -> ! {
     }
 }
 
-/// Transfer the given range of memory to the given server. The memory will be unmapped from the current process.
-pub fn transfer_memory(to_sid: u64, buffer: Vec<u8>) {
-    let aligned_size = super::align_page_up(buffer.len());
-    assert!(aligned_size >= buffer.capacity());
+/// Create zero-filled shared memory of at least the given size, returning a handle to it that may read, write and
+/// grant it. Fails if the size is 0 or there is not enough memory.
+pub fn create_memory(size: usize) -> Result<u64, SyscallError> {
+    let handle: u64;
+    let error: u64;
+
+    unsafe {
+        asm!("ecall",
+            inlateout("a0") size => handle,
+            in("a7") SystemCall::CreateMemory as usize,
+            lateout("t3") error,
+            options(nomem, nostack)
+        );
+    }
+
+    check(error).map(|()| handle)
+}
+
+/// Map the shared memory the given handle refers to, which needs the `WRITE` right to be mapped writable.
+/// Returns the start and the size of the mapping, or fails with [`SyscallError::InvalidHandle`] if the handle
+/// does not have the rights.
+pub fn map_memory(handle: u64, writable: bool) -> Result<(*mut u8, usize), SyscallError> {
+    let rights = if writable {
+        Rights::READ.union(Rights::WRITE)
+    } else {
+        Rights::READ
+    };
+    let start: u64;
+    let size: usize;
+    let error: u64;
+
+    unsafe {
+        asm!("ecall",
+            inlateout("a0") handle => start,
+            inlateout("a1") rights.raw() => size,
+            in("a7") SystemCall::MapMemory as usize,
+            lateout("t3") error,
+            options(nomem, nostack)
+        );
+    }
+
+    check(error).map(|()| (start as _, size))
+}
+
+/// Unmap the shared memory that was mapped at the given address,
+/// fails with [`SyscallError::InvalidAddress`] if there is none.
+pub fn unmap_memory(start: *mut u8) -> Result<(), SyscallError> {
+    let error: u64;
+
+    unsafe {
+        asm!("ecall",
+            in("a0") start,
+            in("a7") SystemCall::UnmapMemory as usize,
+            lateout("t3") error,
+            options(nostack)
+        );
+    }
+
+    check(error)
+}
+
+/// A memory mapped device, as described by the device tree.
+#[derive(Debug, Clone, Copy)]
+pub struct Device {
+    pub address: u64,
+    pub size: u64,
+    pub interrupt: Option<u64>,
+}
+
+impl Device {
+    /// Identity map the registers of the device into the address space of the current process.
+    pub fn identity_map(&self) -> Result<(), SyscallError> {
+        let last_page = (self.address + self.size - 1) & !(super::PAGE_SIZE as u64 - 1);
+        identity_map(self.address..=last_page)
+    }
+}
+
+/// Find the device at the given index among all devices compatible with the given string.
+/// Fails with [`SyscallError::NotFound`] if there are not that many.
+pub fn find_device(compatible: &str, index: usize) -> Result<Device, SyscallError> {
+    let address: u64;
+    let size: u64;
+    let interrupt: u64;
+    let error: u64;
+
+    unsafe {
+        asm!("ecall",
+            in("a0") compatible.as_ptr(),
+            in("a1") compatible.len(),
+            in("a2") index,
+            lateout("a0") address,
+            lateout("a1") size,
+            lateout("a2") interrupt,
+            in("a7") SystemCall::FindDevice as usize,
+            lateout("t3") error,
+            options(nostack)
+        );
+    }
+
+    check(error)?;
+    Ok(Device {
+        address,
+        size,
+        interrupt: (interrupt != u64::MAX).then_some(interrupt),
+    })
+}
+
+/// All devices compatible with the given string.
+pub fn find_devices(compatible: &str) -> impl Iterator<Item = Device> + '_ {
+    (0..).map_while(move |index| find_device(compatible, index).ok())
+}
+
+/// The priority of the given process, or of the current one if `pid` is `None`.
+pub fn priority(pid: Option<u64>) -> Result<Priority, SyscallError> {
+    priority_inner(pid, u64::MAX)
+}
 
-    // The process this is mapped into is responsible for deallocation.
-    let slice = Vec::leak(buffer);
+/// Change the priority of the given process, or of the current one if `pid` is `None`, returning its previous priority.
+/// Fails with [`SyscallError::NotFound`] if the process does not exist, or with [`SyscallError::PermissionDenied`].
+/// Processes may only lower their own priority unless they are `init`, except for device drivers which may request the real-time class.
+pub fn set_priority(pid: Option<u64>, priority: Priority) -> Result<Priority, SyscallError> {
+    priority_inner(pid, priority.level() as _)
+}
 
-    let start = slice.as_ptr();
-    let end = unsafe { start.add(slice.len()) };
+fn priority_inner(pid: Option<u64>, level: u64) -> Result<Priority, SyscallError> {
+    let result: u64;
+    let error: u64;
 
     unsafe {
         asm!("ecall",
-            in("a0") to_sid,
-            in("a1") start,
-            in("a2") end,
-            in("a7") SystemCall::TransferMemory as usize,
+            in("a0") pid.unwrap_or(u64::MAX),
+            in("a1") level,
+            lateout("a0") result,
+            in("a7") SystemCall::Priority as usize,
+            lateout("t3") error,
             options(nomem, nostack)
         );
     }
+
+    check(error)?;
+    Ok(u8::try_from(result)
+        .ok()
+        .and_then(Priority::new)
+        .expect("the kernel only returns valid priorities"))
+}
+
+/// Start a new thread of the current process at `entry`, running on the given stack with `arg` as its argument.
+/// Returns the ID of the new thread, or fails with [`SyscallError::LimitReached`] if the process has too many threads.
+///
+/// # Safety
+/// The stack must be valid for as long as the thread runs, and `entry` must never return but call `thread_exit` instead.
+pub unsafe fn thread_create(
+    entry: extern "C" fn(u64) -> !,
+    stack: *mut u8,
+    arg: u64,
+) -> Result<u64, SyscallError> {
+    let tid: u64;
+    let error: u64;
+
+    unsafe {
+        asm!("ecall",
+            in("a0") entry as usize,
+            in("a1") stack,
+            in("a2") arg,
+            lateout("a0") tid,
+            in("a7") SystemCall::ThreadCreate as usize,
+            lateout("t3") error,
+            options(nostack)
+        );
+    }
+
+    check(error).map(|()| tid)
+}
+
+/// Exit the current thread, the process exits along with its last thread.
+pub fn thread_exit() -> ! {
+    unsafe {
+        asm!("ecall",
+            in("a7") SystemCall::ThreadExit as usize,
+            options(noreturn, nomem, nostack)
+        );
+    }
+}
+
+/// Block until the given thread of the current process exits, or return right away if it already did.
+/// Fails with [`SyscallError::NotFound`] if there is no such thread, or if it was joined or detached already.
+pub fn thread_join(tid: u64) -> Result<(), SyscallError> {
+    let error: u64;
+
+    unsafe {
+        asm!("ecall",
+            in("a0") tid,
+            in("a7") SystemCall::ThreadJoin as usize,
+            lateout("t3") error,
+            options(nomem, nostack)
+        );
+    }
+
+    check(error)
+}
+
+/// Let the given thread of the current process exit without being joined, which it can no longer be afterwards.
+/// Fails with [`SyscallError::NotFound`] if there is no such thread, or if it was joined or detached already.
+pub fn thread_detach(tid: u64) -> Result<(), SyscallError> {
+    let error: u64;
+
+    unsafe {
+        asm!("ecall",
+            in("a0") tid,
+            in("a7") SystemCall::ThreadDetach as usize,
+            lateout("t3") error,
+            options(nomem, nostack)
+        );
+    }
+
+    check(error)
+}
+
+/// Block the current thread while `futex` holds `expected`, until another thread wakes it or the timeout expires.
+/// Fails with [`SyscallError::TimedOut`] if the timeout expired, or with [`SyscallError::Interrupted`] if a signal
+/// handler ran. Wake ups may be spurious, so the caller has to check its condition again either way.
+pub fn futex_wait(
+    futex: &AtomicU32,
+    expected: u32,
+    timeout: Option<Duration>,
+) -> Result<(), SyscallError> {
+    let timeout = timeout.map_or(u64::MAX, |timeout| {
+        u64::try_from(timeout.as_nanos()).unwrap_or(u64::MAX - 1)
+    });
+    let error: u64;
+
+    unsafe {
+        asm!("ecall",
+            in("a0") futex.as_ptr(),
+            in("a1") expected,
+            in("a2") timeout,
+            in("a7") SystemCall::FutexWait as usize,
+            lateout("t3") error,
+            options(nostack)
+        );
+    }
+
+    check(error)
+}
+
+/// Wake up to `count` threads waiting on `futex`, returning how many were woken.
+pub fn futex_wake(futex: &AtomicU32, count: u32) -> u32 {
+    let woken: u64;
+
+    unsafe {
+        asm!("ecall",
+            in("a0") futex.as_ptr(),
+            in("a1") count as u64,
+            lateout("a0") woken,
+            in("a7") SystemCall::FutexWake as usize,
+            lateout("t3") _,
+            options(nostack)
+        );
+    }
+
+    woken as _
+}
+
+/// Send a signal to the process with the given PID, fails with [`SyscallError::NotFound`] if there is no such process.
+pub fn kill(pid: u64, signal: Signal) -> Result<(), SyscallError> {
+    let error: u64;
+
+    unsafe {
+        asm!("ecall",
+            in("a0") pid,
+            in("a1") signal as u64,
+            in("a7") SystemCall::Kill as usize,
+            lateout("t3") error,
+            options(nomem, nostack)
+        );
+    }
+
+    check(error)
+}
+
+/// Set what happens when the current process receives the given signal. An `entry` of 0 restores the default action,
+/// and 1 ignores the signal. Otherwise the handler at `entry` is called with the signal and `arg`, and has to call
+/// [`signal_return`] once it is done. Returns the previous entry, or fails with [`SyscallError::InvalidArgument`]
+/// if the action of the signal cannot be changed.
+pub fn signal_action(signal: Signal, entry: usize, arg: u64) -> Result<usize, SyscallError> {
+    let previous: u64;
+    let error: u64;
+
+    unsafe {
+        asm!("ecall",
+            in("a0") signal as u64,
+            in("a1") entry,
+            in("a2") arg,
+            lateout("a0") previous,
+            in("a7") SystemCall::SignalAction as usize,
+            lateout("t3") error,
+            options(nomem, nostack)
+        );
+    }
+
+    check(error).map(|()| previous as _)
+}
+
+/// Change which signals of the current process stay pending instead of being delivered, returning the previous mask.
+pub fn signal_mask(how: MaskHow, signals: SignalSet) -> SignalSet {
+    let previous: u64;
+
+    unsafe {
+        asm!("ecall",
+            in("a0") how as u64,
+            in("a1") signals.raw(),
+            lateout("a0") previous,
+            in("a7") SystemCall::SignalMask as usize,
+            // Only fails for invalid values of `how`
+            lateout("t3") _,
+            options(nomem, nostack)
+        );
+    }
+
+    SignalSet::from_raw(previous)
+}
+
+/// Run signal handlers of the calling thread on the stack ending at `top` instead of the stack it was interrupted on,
+/// or stop doing so if it is null. Every thread needs its own stack for this, new threads start without one.
+///
+/// # Safety
+/// The stack must stay valid for as long as it is used.
+pub unsafe fn signal_stack(top: *mut u8) {
+    unsafe {
+        asm!("ecall",
+            in("a0") top,
+            in("a7") SystemCall::SignalStack as usize,
+            lateout("t3") _,
+            options(nostack)
+        );
+    }
+}
+
+/// Continue where the current thread was interrupted by a signal, must only be called at the end of a signal handler.
+pub fn signal_return() -> ! {
+    unsafe {
+        asm!("ecall",
+            in("a7") SystemCall::SignalReturn as usize,
+            options(noreturn, nomem, nostack)
+        );
+    }
+}
+
+/// A snapshot of every process, ordered by PID.
+pub fn list_processes() -> Vec<ProcessInfo> {
+    let mut infos = Vec::new();
+
+    // Processes may be spawned in between, so retry until all of them fit
+    loop {
+        let total: u64;
+
+        unsafe {
+            asm!("ecall",
+                in("a0") infos.as_mut_ptr(),
+                in("a1") infos.capacity(),
+                lateout("a0") total,
+                in("a7") SystemCall::ListProcesses as usize,
+                // Only fails for buffers that are not mapped
+                lateout("t3") _,
+                options(nostack)
+            );
+        }
+
+        let total = total as usize;
+        if total <= infos.capacity() {
+            unsafe { infos.set_len(total) };
+            return infos;
+        }
+
+        infos.reserve(total);
+    }
+}
+
+/// Start or stop recording the messages sent to and from the server behind the handle.
+/// Returns the ID of the server in the records. Fails with [`SyscallError::InvalidHandle`] if the handle does not
+/// refer to a server, or with [`SyscallError::PermissionDenied`] if we are neither `init` nor started by it.
+pub fn trace_server(handle: u64, traced: bool) -> Result<u64, SyscallError> {
+    let result: u64;
+    let error: u64;
+
+    unsafe {
+        asm!("ecall",
+            in("a0") handle,
+            in("a1") traced as u64,
+            lateout("a0") result,
+            in("a7") SystemCall::TraceServer as usize,
+            lateout("t3") error,
+            options(nomem, nostack)
+        );
+    }
+
+    check(error).map(|()| result)
+}
+
+/// Take up to `count` of the oldest records of traced messages,
+/// fails with [`SyscallError::PermissionDenied`] if we may not trace.
+pub fn read_trace(count: usize) -> Result<Vec<TraceRecord>, SyscallError> {
+    let mut records = Vec::with_capacity(count);
+    let read: u64;
+    let error: u64;
+
+    unsafe {
+        asm!("ecall",
+            in("a0") records.as_mut_ptr(),
+            in("a1") records.capacity(),
+            lateout("a0") read,
+            in("a7") SystemCall::ReadTrace as usize,
+            lateout("t3") error,
+            options(nostack)
+        );
+    }
+
+    check(error)?;
+    unsafe { records.set_len(read as _) };
+    Ok(records)
 }
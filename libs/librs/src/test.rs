@@ -26,11 +26,11 @@ pub fn test_runner(tests: &[&dyn Testable]) {
     for test in tests {
         test.run()
     }
-    syscall::exit();
+    syscall::exit(0);
 }
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     println!("{info}");
-    syscall::exit();
+    syscall::exit(101);
 }
@@ -0,0 +1,68 @@
+//! Threads that share the address space of the current process.
+
+use crate::syscall;
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::mem::{self, ManuallyDrop};
+
+const STACK_SIZE: usize = 64 * 1024;
+
+type Main = Box<dyn FnOnce() + Send>;
+
+/// A handle to a spawned thread. Dropping it detaches the thread, which leaks its stack.
+pub struct JoinHandle {
+    tid: u64,
+    stack: ManuallyDrop<Vec<u8>>,
+}
+
+impl JoinHandle {
+    /// The ID of the thread.
+    pub fn id(&self) -> u64 {
+        self.tid
+    }
+
+    /// Wait for the thread to exit.
+    pub fn join(mut self) {
+        // Cannot fail, as only this handle joins the thread
+        let _ = syscall::thread_join(self.tid);
+
+        // The thread no longer runs on its stack, so it can be freed
+        unsafe { ManuallyDrop::drop(&mut self.stack) };
+        mem::forget(self);
+    }
+}
+
+impl Drop for JoinHandle {
+    fn drop(&mut self) {
+        // Cannot fail either, as the thread was not joined
+        let _ = syscall::thread_detach(self.tid);
+    }
+}
+
+/// Run the given closure on a new thread.
+pub fn spawn<F>(f: F) -> JoinHandle
+where
+    F: FnOnce() + Send + 'static,
+{
+    let mut stack = ManuallyDrop::new(vec![0u8; STACK_SIZE]);
+    let stack_top = (stack.as_mut_ptr() as usize + STACK_SIZE) & !0xf;
+
+    // The closure is a fat pointer, so box it again to pass it as a single argument
+    let main: *mut Main = Box::into_raw(Box::new(Box::new(f)));
+
+    let tid = unsafe { syscall::thread_create(thread_start, stack_top as _, main as _) };
+    let Ok(tid) = tid else {
+        unsafe {
+            drop(Box::from_raw(main));
+            ManuallyDrop::drop(&mut stack);
+        }
+        panic!("failed to spawn thread");
+    };
+
+    JoinHandle { tid, stack }
+}
+
+extern "C" fn thread_start(main: u64) -> ! {
+    let main = unsafe { Box::from_raw(main as *mut Main) };
+    main();
+    syscall::thread_exit();
+}
@@ -0,0 +1,525 @@
+//! Turns a trait that describes an IPC service into the pieces both sides of it need.
+//!
+//! Every method of the trait is a request, numbered in the order they are declared:
+//! - Methods without a return type are sent, without waiting for the server to handle them.
+//! - Methods with a return type are calls, the server replies with the returned value.
+//! - Methods whose last argument is a `Reply<T>` are calls as well, the server replies with it whenever it likes.
+//!
+//! The server implements the trait and hands received messages to the generated `dispatch` method,
+//! or has `serve` do that forever. Clients use the generated `<Trait>Client`, which connects to the server by name.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, spanned::Spanned, Attribute, Error, Expr, FnArg,
+    GenericArgument, Ident, ItemTrait, Pat, PathArguments, ReturnType, TraitItem, Type,
+};
+
+/// How the server answers a request.
+enum Kind {
+    Send,
+    Call(Type),
+    DeferredCall(Type),
+}
+
+struct Request {
+    name: Ident,
+    id: u64,
+    docs: Vec<Attribute>,
+    args: Vec<(Ident, Type)>,
+    kind: Kind,
+}
+
+/// Generate a client and request dispatch for the service described by the trait,
+/// whose server registers under the name given as argument.
+///
+/// ```ignore
+/// #[librs::service(NAME)]
+/// pub trait FileSystem {
+///     fn file_index(&mut self, path: String) -> Result<FileIndex, Error>;
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn service(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let name = parse_macro_input!(attr as Expr);
+    let mut service = parse_macro_input!(item as ItemTrait);
+
+    match expand(&name, &mut service) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+fn expand(name: &Expr, service: &mut ItemTrait) -> Result<TokenStream2, Error> {
+    let requests = service
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            TraitItem::Method(method) => Some(method),
+            _ => None,
+        })
+        .enumerate()
+        .map(|(index, method)| parse_request(index as u64 + 1, method))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Granted handles that no argument takes are closed once the decoder is dropped
+    let decoder = if requests.iter().any(|request| !request.args.is_empty()) {
+        quote!(let mut decoder = ::librs::service::Decoder::new(&msg);)
+    } else {
+        quote!(let _decoder = ::librs::service::Decoder::new(&msg);)
+    };
+    let arms = requests.iter().map(dispatch_arm);
+
+    service.items.push(parse_quote! {
+        /// Handle a request that was received for this service, replying to it if the sender waits for a reply.
+        /// Requests that are unknown or malformed are rejected.
+        fn dispatch(&mut self, mut msg: ::librs::ipc::Message)
+        where
+            Self: Sized,
+        {
+            let right = msg.reply_right.take();
+            #decoder
+
+            match msg.identifier {
+                #(#arms)*
+                _ => ::librs::service::reject(right),
+            }
+        }
+    });
+
+    service.items.push(parse_quote! {
+        /// Receive and handle requests forever.
+        fn serve(&mut self) -> !
+        where
+            Self: Sized,
+        {
+            loop {
+                self.dispatch(::librs::ipc::Message::receive_blocking());
+            }
+        }
+    });
+
+    let vis = &service.vis;
+    let client = format_ident!("{}Client", service.ident);
+    let client_doc = format!(
+        "A client of the [`{}`] service, which connects to its server once it is first used.",
+        service.ident
+    );
+    let methods = requests.iter().map(client_method);
+    let names = requests.iter().map(|request| request.name.to_string());
+    let assertions = requests.iter().map(size_assertion);
+
+    Ok(quote! {
+        #service
+
+        #[doc = #client_doc]
+        #[derive(Debug)]
+        #vis struct #client(::librs::service::Client);
+
+        impl #client {
+            /// The names of the requests, the one with identifier `n` at index `n - 1`.
+            pub const REQUESTS: &'static [&'static str] = &[#(#names),*];
+
+            /// A client whose requests wait for the server to register.
+            pub const fn new() -> Self {
+                Self(::librs::service::Client::new(#name, true))
+            }
+
+            /// A client whose requests fail with [`Error::NotRunning`](::librs::service::Error::NotRunning)
+            /// until the server registered.
+            pub const fn without_waiting() -> Self {
+                Self(::librs::service::Client::new(#name, false))
+            }
+
+            /// Block until the server registered.
+            pub fn wait(&self) {
+                self.0.wait();
+            }
+
+            #(#methods)*
+        }
+
+        #(#assertions)*
+    })
+}
+
+fn parse_request(id: u64, method: &syn::TraitItemMethod) -> Result<Request, Error> {
+    let signature = &method.sig;
+    let mut inputs = signature.inputs.iter();
+
+    if !matches!(inputs.next(), Some(FnArg::Receiver(_))) {
+        return Err(Error::new(
+            signature.span(),
+            "requests must take `self` by reference",
+        ));
+    }
+
+    let mut args = inputs
+        .map(|input| match input {
+            FnArg::Typed(arg) => match arg.pat.as_ref() {
+                Pat::Ident(pat) => Ok((pat.ident.clone(), arg.ty.as_ref().clone())),
+                pat => Err(Error::new(
+                    pat.span(),
+                    "arguments must be plain identifiers",
+                )),
+            },
+            FnArg::Receiver(receiver) => Err(Error::new(receiver.span(), "unexpected receiver")),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let deferred = args.last().and_then(|(_, ty)| reply_type(ty));
+    let kind = match (&signature.output, deferred) {
+        (ReturnType::Default, None) => Kind::Send,
+        (ReturnType::Type(_, ty), None) => Kind::Call(ty.as_ref().clone()),
+        (ReturnType::Default, Some(ty)) => {
+            args.pop();
+            Kind::DeferredCall(ty)
+        }
+        (ReturnType::Type(..), Some(_)) => {
+            return Err(Error::new(
+                signature.output.span(),
+                "requests that reply later cannot return a value",
+            ))
+        }
+    };
+
+    Ok(Request {
+        name: signature.ident.clone(),
+        id,
+        docs: method
+            .attrs
+            .iter()
+            .filter(|attr| attr.path.is_ident("doc"))
+            .cloned()
+            .collect(),
+        args,
+        kind,
+    })
+}
+
+/// The type of the value replied with, if the given type is a `Reply<T>`.
+fn reply_type(ty: &Type) -> Option<Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Reply" {
+        return None;
+    }
+
+    match &segment.arguments {
+        PathArguments::AngleBracketed(generics) => match generics.args.first()? {
+            GenericArgument::Type(ty) if generics.args.len() == 1 => Some(ty.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn dispatch_arm(request: &Request) -> TokenStream2 {
+    let Request { name, id, args, .. } = request;
+    let names: Vec<_> = args.iter().map(|(name, _)| name).collect();
+
+    let decode = (!args.is_empty()).then(|| {
+        let decoded = names
+            .iter()
+            .map(|_| quote!(::librs::service::Payload::decode(&mut decoder)));
+
+        quote! {
+            let (#(Some(#names),)*) = (#(#decoded,)*) else {
+                ::librs::service::reject(right);
+                return;
+            };
+        }
+    });
+
+    let handle = match request.kind {
+        Kind::Send => quote! {
+            self.#name(#(#names),*);
+            ::librs::service::Reply::new(right).send(());
+        },
+        Kind::Call(_) => quote! {
+            let value = self.#name(#(#names),*);
+            ::librs::service::Reply::new(right).send(value);
+        },
+        Kind::DeferredCall(_) => quote! {
+            self.#name(#(#names,)* ::librs::service::Reply::new(right));
+        },
+    };
+
+    quote! {
+        #id => {
+            #decode
+            #handle
+        }
+    }
+}
+
+fn client_method(request: &Request) -> TokenStream2 {
+    let Request {
+        name,
+        id,
+        docs,
+        args,
+        ..
+    } = request;
+    let names = args.iter().map(|(name, _)| name);
+    let encoder = if args.is_empty() {
+        quote!(let encoder = ::librs::service::Encoder::new(#id);)
+    } else {
+        quote!(let mut encoder = ::librs::service::Encoder::new(#id);)
+    };
+    let params = args.iter().map(|(name, ty)| quote!(#name: #ty));
+
+    let (output, finish) = match &request.kind {
+        Kind::Send => (quote!(()), quote!(self.0.send(encoder))),
+        Kind::Call(ty) | Kind::DeferredCall(ty) => (quote!(#ty), quote!(self.0.call(encoder))),
+    };
+
+    quote! {
+        #(#docs)*
+        pub fn #name(&self, #(#params),*) -> Result<#output, ::librs::service::Error> {
+            #encoder
+            #(::librs::service::Payload::encode(&#names, &mut encoder);)*
+            #finish
+        }
+    }
+}
+
+/// Fail to compile if the arguments or the reply of a request do not fit into a message.
+fn size_assertion(request: &Request) -> TokenStream2 {
+    let name = &request.name;
+    let types: Vec<_> = request.args.iter().map(|(_, ty)| ty).collect();
+    let args_message = format!("the arguments of `{name}` do not fit into a message");
+
+    let reply = match &request.kind {
+        Kind::Send => None,
+        Kind::Call(ty) | Kind::DeferredCall(ty) => {
+            let reply_message = format!("the reply to `{name}` does not fit into a message");
+            Some(quote! {
+                const _: () = assert!(
+                    ::librs::service::fits(
+                        <#ty as ::librs::service::Response>::WORDS,
+                        <#ty as ::librs::service::Response>::HANDLES,
+                    ),
+                    #reply_message
+                );
+            })
+        }
+    };
+
+    quote! {
+        const _: () = assert!(
+            ::librs::service::fits(
+                0 #(+ <#types as ::librs::service::Payload>::WORDS)*,
+                0 #(+ <#types as ::librs::service::Payload>::HANDLES)*,
+            ),
+            #args_message
+        );
+        #reply
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quote::ToTokens;
+    use syn::{Expr, ImplItem, Item, Lit, Stmt};
+
+    fn expand_service(mut service: ItemTrait) -> Result<syn::File, Error> {
+        let tokens = expand(&parse_quote!(NAME), &mut service)?;
+        Ok(syn::parse2(tokens).expect("the expansion is valid Rust"))
+    }
+
+    fn client_items(file: &syn::File) -> &[ImplItem] {
+        file.items
+            .iter()
+            .find_map(|item| match item {
+                Item::Impl(client) => Some(&client.items[..]),
+                _ => None,
+            })
+            .expect("a client is generated")
+    }
+
+    fn client_method<'a>(file: &'a syn::File, name: &str) -> &'a syn::ImplItemMethod {
+        client_items(file)
+            .iter()
+            .find_map(|item| match item {
+                ImplItem::Method(method) if method.sig.ident == name => Some(method),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    /// The arms of the `match` on the request identifier in the generated `dispatch`.
+    fn dispatch_arms(file: &syn::File) -> Vec<syn::Arm> {
+        let Some(Item::Trait(service)) = file.items.first() else {
+            panic!("the trait comes first");
+        };
+
+        let dispatch = service
+            .items
+            .iter()
+            .find_map(|item| match item {
+                TraitItem::Method(method) if method.sig.ident == "dispatch" => {
+                    method.default.as_ref()
+                }
+                _ => None,
+            })
+            .unwrap();
+
+        dispatch
+            .stmts
+            .iter()
+            .find_map(|stmt| match stmt {
+                Stmt::Expr(Expr::Match(matched)) => Some(matched.arms.clone()),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    fn error_message(service: ItemTrait) -> String {
+        match expand_service(service) {
+            Ok(_) => panic!("the service is invalid"),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn numbers_requests_in_declaration_order() {
+        let file = expand_service(parse_quote! {
+            pub trait Log {
+                fn write(&mut self, text: String);
+                fn read(&mut self) -> Option<char>;
+                fn flush(&mut self, reply: Reply<()>);
+            }
+        })
+        .unwrap();
+
+        let requests = client_items(&file)
+            .iter()
+            .find_map(|item| match item {
+                ImplItem::Const(requests) if requests.ident == "REQUESTS" => Some(&requests.expr),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(
+            requests.to_token_stream().to_string(),
+            quote!(&["write", "read", "flush"]).to_string()
+        );
+
+        let arms = dispatch_arms(&file);
+        let ids: Vec<_> = arms
+            .iter()
+            .filter_map(|arm| match &arm.pat {
+                Pat::Lit(lit) => match lit.expr.as_ref() {
+                    Expr::Lit(syn::ExprLit {
+                        lit: Lit::Int(id), ..
+                    }) => Some(id.base10_parse::<u64>().unwrap()),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ids, [1, 2, 3]);
+
+        // Unknown requests are rejected
+        assert!(matches!(arms.last().unwrap().pat, Pat::Wild(_)));
+        assert!(arms
+            .last()
+            .unwrap()
+            .body
+            .to_token_stream()
+            .to_string()
+            .contains("reject"));
+    }
+
+    #[test]
+    fn rejects_arguments_that_do_not_decode() {
+        let file = expand_service(parse_quote! {
+            pub trait Files {
+                fn open(&mut self, path: String, flags: u64) -> u64;
+                fn sync(&mut self);
+            }
+        })
+        .unwrap();
+
+        let arms = dispatch_arms(&file);
+        let open = arms[0].body.to_token_stream().to_string();
+        assert!(open.contains(&quote!(let (Some(path), Some(flags),) =).to_string()));
+        assert!(
+            open.contains(&quote!(else { ::librs::service::reject(right); return; }).to_string())
+        );
+
+        // Requests without arguments have nothing to decode
+        let sync = arms[1].body.to_token_stream().to_string();
+        assert!(!sync.contains("reject"));
+    }
+
+    #[test]
+    fn clients_return_replies_wrapped_in_a_result() {
+        let file = expand_service(parse_quote! {
+            pub trait FileSystem {
+                fn file_index(&mut self, path: String) -> Result<FileIndex, Error>;
+                fn flush(&mut self);
+                fn watch(&mut self, path: String, reply: Reply<bool>);
+            }
+        })
+        .unwrap();
+
+        let output = |name| {
+            client_method(&file, name)
+                .sig
+                .output
+                .to_token_stream()
+                .to_string()
+        };
+        assert_eq!(
+            output("file_index"),
+            quote!(-> Result<Result<FileIndex, Error>, ::librs::service::Error>).to_string()
+        );
+        assert_eq!(
+            output("flush"),
+            quote!(-> Result<(), ::librs::service::Error>).to_string()
+        );
+        assert_eq!(
+            output("watch"),
+            quote!(-> Result<bool, ::librs::service::Error>).to_string()
+        );
+
+        // The client does not pass the reply itself
+        assert_eq!(client_method(&file, "watch").sig.inputs.len(), 2);
+    }
+
+    #[test]
+    fn rejects_invalid_requests() {
+        assert_eq!(
+            error_message(parse_quote! {
+                trait Service {
+                    fn request(value: u64);
+                }
+            }),
+            "requests must take `self` by reference"
+        );
+
+        assert_eq!(
+            error_message(parse_quote! {
+                trait Service {
+                    fn request(&self, (a, b): (u64, u64));
+                }
+            }),
+            "arguments must be plain identifiers"
+        );
+
+        assert_eq!(
+            error_message(parse_quote! {
+                trait Service {
+                    fn request(&self, reply: Reply<u64>) -> u64;
+                }
+            }),
+            "requests that reply later cannot return a value"
+        );
+    }
+}
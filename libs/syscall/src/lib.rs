@@ -3,6 +3,7 @@
 #![no_std]
 
 use bitbybit::bitenum;
+use core::{fmt, time::Duration};
 
 #[cfg(test)]
 fn test_runner(tests: &[&dyn Fn()]) {
@@ -27,7 +28,32 @@ pub enum SystemCall {
     RegisterInterruptHandler = 11,
     CompleteInterrupt = 12,
     Yield = 13,
-    TransferMemory = 14,
+    FindDevice = 15,
+    Priority = 16,
+    ThreadCreate = 17,
+    ThreadExit = 18,
+    ThreadJoin = 19,
+    FutexWait = 20,
+    FutexWake = 21,
+    Wait = 22,
+    Kill = 23,
+    SignalAction = 24,
+    SignalMask = 25,
+    SignalStack = 26,
+    SignalReturn = 27,
+    ListProcesses = 28,
+    Call = 29,
+    Reply = 30,
+    Connect = 31,
+    CloseHandle = 32,
+    Revoke = 33,
+    CreateMemory = 34,
+    MapMemory = 35,
+    UnmapMemory = 36,
+    WaitEvents = 37,
+    TraceServer = 38,
+    ReadTrace = 39,
+    ThreadDetach = 40,
 
     // TODO: Remove these
     Spawn = 7,
@@ -43,3 +69,490 @@ impl TryFrom<u64> for SystemCall {
         Self::new_with_raw_value(value).map_err(SystemCallError::Invalid)
     }
 }
+
+/// The scheduling priority of a process, processes with a higher level are always scheduled first.
+/// Levels from [`Priority::REAL_TIME`] upwards form the real-time class, which is meant for device drivers.
+/// Unlike normal processes, real-time processes never age into a higher level, but they can starve everything below them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Priority(u8);
+
+impl Priority {
+    pub const LOWEST: Self = Self(0);
+    pub const DEFAULT: Self = Self(4);
+    pub const REAL_TIME: Self = Self(8);
+    pub const HIGHEST: Self = Self(11);
+
+    /// The amount of distinct levels.
+    pub const LEVELS: usize = Self::HIGHEST.0 as usize + 1;
+
+    pub const fn new(level: u8) -> Option<Self> {
+        if level <= Self::HIGHEST.0 {
+            Some(Self(level))
+        } else {
+            None
+        }
+    }
+
+    pub const fn level(&self) -> u8 {
+        self.0
+    }
+
+    pub const fn is_real_time(&self) -> bool {
+        self.0 >= Self::REAL_TIME.0
+    }
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// How a process ended, as reported to its parent by the `Wait` system call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// The process exited on its own with the given code.
+    Exited(i32),
+    /// The kernel killed the process.
+    Killed(KillReason),
+}
+
+/// Why the kernel killed a process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KillReason {
+    /// The process raised an exception it could not recover from, with the given `scause` code.
+    Exception(u64),
+    /// The process made a system call that does not exist, or passed invalid arguments to one.
+    InvalidSystemCall,
+    /// The kernel ran out of memory while handling a request of the process.
+    OutOfMemory,
+    /// The process received a signal whose action is to terminate it.
+    Signal(Signal),
+}
+
+impl ExitStatus {
+    pub const SUCCESS: Self = Self::Exited(0);
+
+    /// Encode the status as a kind and a value, so that it can be passed in two registers.
+    pub const fn into_raw(self) -> (u64, u64) {
+        match self {
+            Self::Exited(code) => (0, code as u64),
+            Self::Killed(KillReason::Exception(cause)) => (1, cause),
+            Self::Killed(KillReason::InvalidSystemCall) => (2, 0),
+            Self::Killed(KillReason::OutOfMemory) => (3, 0),
+            Self::Killed(KillReason::Signal(signal)) => (4, signal as u64),
+        }
+    }
+
+    pub fn from_raw(kind: u64, value: u64) -> Option<Self> {
+        match kind {
+            0 => Some(Self::Exited(value as i32)),
+            1 => Some(Self::Killed(KillReason::Exception(value))),
+            2 => Some(Self::Killed(KillReason::InvalidSystemCall)),
+            3 => Some(Self::Killed(KillReason::OutOfMemory)),
+            4 => Signal::try_from(value)
+                .ok()
+                .map(|signal| Self::Killed(KillReason::Signal(signal))),
+            _ => None,
+        }
+    }
+
+    pub const fn success(&self) -> bool {
+        matches!(self, Self::Exited(0))
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exited(code) => write!(f, "exited with code {code}"),
+            Self::Killed(KillReason::Exception(cause)) => {
+                write!(f, "killed by an unhandled exception (scause {cause:#x})")
+            }
+            Self::Killed(KillReason::InvalidSystemCall) => {
+                write!(f, "killed for an invalid system call")
+            }
+            Self::Killed(KillReason::OutOfMemory) => write!(f, "killed as it ran out of memory"),
+            Self::Killed(KillReason::Signal(signal)) => write!(f, "killed by signal {signal:?}"),
+        }
+    }
+}
+
+/// An asynchronous notification sent to a process. The numbers match the ones of the respective POSIX signals.
+#[derive(Debug, PartialEq, Eq)]
+#[bitenum(u64)]
+pub enum Signal {
+    /// Sent by the shell when Control-C is pressed.
+    Interrupt = 2,
+    /// Always terminates the process, it can neither be handled nor masked.
+    Kill = 9,
+    User1 = 10,
+    User2 = 12,
+    /// Asks the process to terminate.
+    Terminate = 15,
+    /// Sent to the parent when a child process exits.
+    Child = 17,
+}
+
+/// What a signal does when the process did not install a handler for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultAction {
+    Terminate,
+    Ignore,
+}
+
+impl Signal {
+    pub const fn default_action(&self) -> DefaultAction {
+        match self {
+            Self::Child => DefaultAction::Ignore,
+            _ => DefaultAction::Terminate,
+        }
+    }
+
+    /// Whether the process may install a handler for this signal, or mask it.
+    pub const fn can_be_handled(&self) -> bool {
+        !matches!(self, Self::Kill)
+    }
+}
+
+impl TryFrom<u64> for Signal {
+    type Error = u64;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        Self::new_with_raw_value(value)
+    }
+}
+
+/// A set of signals, where bit `n` stands for the signal with number `n`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SignalSet(u64);
+
+impl SignalSet {
+    pub const EMPTY: Self = Self(0);
+
+    pub const fn from_raw(raw: u64) -> Self {
+        Self(raw)
+    }
+
+    pub const fn raw(&self) -> u64 {
+        self.0
+    }
+
+    pub const fn with(self, signal: Signal) -> Self {
+        Self(self.0 | (1 << signal as u64))
+    }
+
+    pub const fn contains(&self, signal: Signal) -> bool {
+        self.0 & (1 << signal as u64) != 0
+    }
+
+    pub fn insert(&mut self, signal: Signal) {
+        *self = self.with(signal);
+    }
+
+    pub fn remove(&mut self, signal: Signal) {
+        self.0 &= !(1 << signal as u64);
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// The signals in this set, ordered by their number.
+    pub fn iter(self) -> impl Iterator<Item = Signal> {
+        (0..u64::BITS as u64)
+            .filter(move |bit| self.0 & (1 << bit) != 0)
+            .filter_map(|bit| Signal::try_from(bit).ok())
+    }
+}
+
+/// How the `SignalMask` system call changes the mask of a process.
+#[derive(Debug, PartialEq, Eq)]
+#[bitenum(u64)]
+pub enum MaskHow {
+    Block = 0,
+    Unblock = 1,
+    Set = 2,
+}
+
+/// Why a system call failed, as returned in `T3` by every system call. `T3` is 0 if the call succeeded.
+#[derive(Debug, PartialEq, Eq)]
+#[bitenum(u64, exhaustive: false)]
+pub enum SyscallError {
+    /// The handle does not exist, or lacks the rights the operation needs.
+    InvalidHandle = 1,
+    /// The queue of the server is full, and the sender did not want to block.
+    Full = 2,
+    /// The operation would have to block, but the caller did not want to or is an interrupt handler, which may not.
+    WouldBlock = 3,
+    /// The handle to grant does not exist, or lacks the right to be granted.
+    InvalidGrant = 4,
+    /// The other side exited, either the server before replying or the caller before the reply arrived.
+    Gone = 5,
+    /// The server did not receive a call with the reply token, or already replied to it.
+    InvalidToken = 6,
+    /// An argument is out of range, misaligned or malformed, like a name that is not UTF-8 or a file that is not an ELF.
+    InvalidArgument = 7,
+    /// A pointer argument refers to memory that is not mapped, or not mapped the way the operation needs.
+    InvalidAddress = 8,
+    /// The process, thread, child, server or device does not exist.
+    NotFound = 9,
+    /// Only certain processes may do this, like `init` or device drivers.
+    PermissionDenied = 10,
+    /// There is not enough memory left.
+    OutOfMemory = 11,
+    /// The name is already taken, or the process already registered a server.
+    AlreadyExists = 12,
+    /// A limit was reached, like the number of threads of a process.
+    LimitReached = 13,
+    /// The timeout expired before the wait ended.
+    TimedOut = 14,
+    /// A signal handler ran before the wait ended.
+    Interrupted = 15,
+}
+
+impl SyscallError {
+    /// The error returned in `T3`, values that are no error are `None`.
+    pub fn from_raw(raw: u64) -> Option<Self> {
+        Self::new_with_raw_value(raw).ok()
+    }
+}
+
+impl fmt::Display for SyscallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            Self::InvalidHandle => "invalid handle",
+            Self::Full => "queue is full",
+            Self::WouldBlock => "operation would block",
+            Self::InvalidGrant => "invalid handle to grant",
+            Self::Gone => "the other side exited",
+            Self::InvalidToken => "invalid reply token",
+            Self::InvalidArgument => "invalid argument",
+            Self::InvalidAddress => "invalid address",
+            Self::NotFound => "not found",
+            Self::PermissionDenied => "permission denied",
+            Self::OutOfMemory => "out of memory",
+            Self::AlreadyExists => "already exists",
+            Self::LimitReached => "limit reached",
+            Self::TimedOut => "timed out",
+            Self::Interrupted => "interrupted by a signal",
+        };
+        f.write_str(description)
+    }
+}
+
+/// What the holder of a capability handle may do with the server endpoint or the shared memory it refers to.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Rights(u64);
+
+impl Rights {
+    pub const NONE: Self = Self(0);
+    /// Send messages and calls to the endpoint.
+    pub const SEND: Self = Self(1 << 0);
+    /// Receive the messages sent to the endpoint, only the server itself holds this right.
+    pub const RECEIVE: Self = Self(1 << 1);
+    /// Pass the handle on to other processes along with a message.
+    pub const GRANT: Self = Self(1 << 2);
+    /// Map the shared memory readable.
+    pub const READ: Self = Self(1 << 3);
+    /// Map the shared memory writable.
+    pub const WRITE: Self = Self(1 << 4);
+    pub const ALL: Self = Self::SEND
+        .union(Self::RECEIVE)
+        .union(Self::GRANT)
+        .union(Self::READ)
+        .union(Self::WRITE);
+
+    pub const fn from_raw(raw: u64) -> Self {
+        Self(raw & Self::ALL.0)
+    }
+
+    pub const fn raw(&self) -> u64 {
+        self.0
+    }
+
+    /// Whether all of the given rights are part of these.
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    pub const fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+/// The sources of events a thread can wait for at once with the `WaitEvents` system call, besides a timeout.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Events(u64);
+
+impl Events {
+    pub const NONE: Self = Self(0);
+    /// A message was queued for the server of the process.
+    pub const MESSAGE: Self = Self(1 << 0);
+    /// An interrupt the process registered a handler for was raised.
+    pub const INTERRUPT: Self = Self(1 << 1);
+    pub const ALL: Self = Self::MESSAGE.union(Self::INTERRUPT);
+
+    pub const fn from_raw(raw: u64) -> Self {
+        Self(raw & Self::ALL.0)
+    }
+
+    pub const fn raw(&self) -> u64 {
+        self.0
+    }
+
+    /// Whether all of the given events are part of these.
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// Which event ended a wait, as returned by the `WaitEvents` system call.
+#[derive(Debug, PartialEq, Eq)]
+#[bitenum(u64, exhaustive: false)]
+pub enum Event {
+    Message = 0,
+    Interrupt = 1,
+    Timeout = 2,
+}
+
+/// What a process is doing, as reported by the `ListProcesses` system call.
+/// A process with several threads reports the state of its most active thread, with `Running` being the most active.
+#[derive(Debug, PartialEq, Eq)]
+#[bitenum(u64)]
+pub enum RunState {
+    Running = 0,
+    Ready = 1,
+    Sleeping = 2,
+    /// Waiting for a message, a child, another thread or a futex.
+    Blocked = 3,
+}
+
+/// A snapshot of a process, as written to userspace by the `ListProcesses` system call.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ProcessInfo {
+    pub pid: u64,
+    /// The PID of the parent, or `u64::MAX` if the process has none.
+    pub parent: u64,
+    /// The raw value of the [`RunState`] of the process.
+    pub state: u64,
+    pub priority: u64,
+    pub threads: u64,
+    /// The CPU time all threads of the process used so far, in nanoseconds.
+    pub cpu_time: u64,
+    /// How often a thread of the process was switched to.
+    pub switches: u64,
+    name_len: u64,
+    name: [u8; ProcessInfo::NAME_LEN],
+}
+
+impl ProcessInfo {
+    /// The maximum length of a name in bytes, longer names are truncated.
+    pub const NAME_LEN: usize = 32;
+
+    /// A process without a parent, threads, or used CPU time so far.
+    pub fn new(pid: u64, name: &str) -> Self {
+        let mut info = Self {
+            pid,
+            parent: u64::MAX,
+            ..Default::default()
+        };
+        info.set_name(name);
+        info
+    }
+
+    pub fn name(&self) -> &str {
+        let name = &self.name[..(self.name_len as usize).min(Self::NAME_LEN)];
+        core::str::from_utf8(name).unwrap_or("?")
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        let mut len = name.len().min(Self::NAME_LEN);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        self.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+        self.name_len = len as _;
+    }
+
+    pub fn parent(&self) -> Option<u64> {
+        (self.parent != u64::MAX).then_some(self.parent)
+    }
+
+    pub fn state(&self) -> Option<RunState> {
+        RunState::new_with_raw_value(self.state).ok()
+    }
+
+    pub fn priority(&self) -> Option<Priority> {
+        u8::try_from(self.priority).ok().and_then(Priority::new)
+    }
+
+    pub const fn cpu_time(&self) -> Duration {
+        Duration::from_nanos(self.cpu_time)
+    }
+}
+
+/// What happened to a traced message.
+#[derive(Debug, PartialEq, Eq)]
+#[bitenum(u64, exhaustive: false)]
+pub enum TraceKind {
+    /// Queued for the receiver, without waiting for a reply.
+    Send = 0,
+    /// Queued for the receiver, whose reply the sender waits for.
+    Call = 1,
+    /// Taken from the queue by the receiver.
+    Receive = 2,
+    /// Delivered to the caller as the reply to a call.
+    Reply = 3,
+}
+
+/// A message to or from a traced server, as written to userspace by the `ReadTrace` system call.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TraceRecord {
+    /// When it happened, in nanoseconds since bootup.
+    pub time: u64,
+    /// The raw value of the [`TraceKind`].
+    pub kind: u64,
+    pub sender_pid: u64,
+    /// The server the sender registered, or 0 if it is not one.
+    pub sender_sid: u64,
+    /// The server the message was sent to, or 0 for replies to senders that are not servers.
+    pub receiver_sid: u64,
+    /// The token that ties a call to its reply, or 0 for messages that were only sent.
+    pub token: u64,
+    pub identifier: u64,
+    pub data: [u64; 5],
+}
+
+impl TraceRecord {
+    pub const fn time(&self) -> Duration {
+        Duration::from_nanos(self.time)
+    }
+
+    pub fn kind(&self) -> Option<TraceKind> {
+        TraceKind::new_with_raw_value(self.kind).ok()
+    }
+}
@@ -10,10 +10,6 @@ fn test_runner(tests: &[&dyn Fn()]) {
     tests.iter().for_each(|test| test());
 }
 
-// See docs/device-trees/qemu-virt.dts
-pub const BASE_ADDR: u64 = 0x1000_0000;
-pub const INTERRUPT_ID: u64 = 10;
-
 pub trait UartRegister {
     fn ptr_offset() -> usize;
 }
@@ -88,14 +84,17 @@ pub struct NS16550a {
 impl_uart_register!(NS16550a, 0);
 
 impl NS16550a {
-    pub const DEFAULT: NS16550a = NS16550a {
-        base_ptr: BASE_ADDR,
-        interrupt: Interrupt::new().with_enabled(true),
-        fifo: Fifo::new().with_enabled(true),
-        line_control: LineControl::new()
-            .with_parity_enable(true)
-            .with_word_length(WordLength::Eight),
-    };
+    /// The default configuration, with the registers at the given address.
+    pub const fn with_base_address(base_ptr: u64) -> Self {
+        Self::new(
+            base_ptr,
+            Interrupt::new().with_enabled(true),
+            Fifo::new().with_enabled(true),
+            LineControl::new()
+                .with_parity_enable(true)
+                .with_word_length(WordLength::Eight),
+        )
+    }
 
     pub const fn new(
         base_ptr: u64,